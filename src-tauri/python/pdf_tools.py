@@ -178,7 +178,7 @@ def add_pdf(path: str):
     conn = get_db()
     for idx, ch in enumerate(chunks):
         chunk_id = f"{doc_id}_{idx}"
-        emb = hash_embed(ch["text"]).tobytes()
+        emb = hash_embed(ch["text"]).astype("<f4").tobytes()
         ps, pe = ch["page_range"]
         conn.execute(
             "INSERT OR REPLACE INTO embeddings VALUES (?,?,?,?,?,?)",
@@ -452,7 +452,7 @@ def search(query: str, k: int = 3):
     qvec = hash_embed(query)
 
     def _score(blob: bytes) -> float:
-        emb = np.frombuffer(blob, dtype=np.float32)
+        emb = np.frombuffer(blob, dtype="<f4")
         return float(np.dot(qvec, emb))
 
     conn.create_function("cosine_sim", 1, _score)
@@ -0,0 +1,689 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use sqlx::{Executor, Row, SqliteConnection};
+
+use crate::commands::EMBED_DIM;
+use crate::embeddings::{self, decode_vector};
+
+/// Indexes with fewer chunks than this are searched exactly.
+pub const EXACT_SEARCH_LIMIT: usize = 5_000;
+
+/// The graph's file, beside `index.sqlite`.
+pub const GRAPH_FILE: &str = "vectors.hnsw";
+
+/// Links per node on the upper layers; the bottom layer allows twice as many.
+const M: usize = 16;
+const EF_CONSTRUCTION: usize = 100;
+const MIN_EF_SEARCH: usize = 64;
+
+const MAGIC: &[u8; 8] = b"BLSMHNSW";
+const FORMAT_VERSION: u32 = 1;
+
+/// Every write to a vector table is logged so a persisted graph can catch up
+/// with chunks added or removed by either indexer since it was saved.
+const LOG_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS vector_log (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    chunk_id TEXT NOT NULL
+);
+CREATE TRIGGER IF NOT EXISTS embeddings_logged_insert AFTER INSERT ON embeddings
+BEGIN INSERT INTO vector_log (chunk_id) VALUES (new.chunk_id); END;
+CREATE TRIGGER IF NOT EXISTS embeddings_logged_update AFTER UPDATE ON embeddings
+BEGIN INSERT INTO vector_log (chunk_id) VALUES (new.chunk_id); END;
+CREATE TRIGGER IF NOT EXISTS embeddings_logged_delete AFTER DELETE ON embeddings
+BEGIN INSERT INTO vector_log (chunk_id) VALUES (old.chunk_id); END;
+CREATE TRIGGER IF NOT EXISTS chunk_vectors_logged_insert AFTER INSERT ON chunk_vectors
+BEGIN INSERT INTO vector_log (chunk_id) VALUES (new.chunk_id); END;
+CREATE TRIGGER IF NOT EXISTS chunk_vectors_logged_update AFTER UPDATE ON chunk_vectors
+BEGIN INSERT INTO vector_log (chunk_id) VALUES (new.chunk_id); END;
+CREATE TRIGGER IF NOT EXISTS chunk_vectors_logged_delete AFTER DELETE ON chunk_vectors
+BEGIN INSERT INTO vector_log (chunk_id) VALUES (old.chunk_id); END;
+";
+
+#[derive(Clone, Copy, PartialEq)]
+struct Scored {
+    sim: f32,
+    node: u32,
+}
+
+impl Eq for Scored {}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sim
+            .total_cmp(&other.sim)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Hierarchical navigable small world graph over unit vectors, scored by
+/// dot product. Removed chunks stay in the graph as tombstones so it stays
+/// connected; [`HnswIndex::compacted`] rebuilds it without them.
+pub struct HnswIndex {
+    /// Embedding model the vectors came from.
+    pub model: String,
+    pub dim: usize,
+    /// Last `vector_log` entry reflected in the graph.
+    pub log_seq: i64,
+    ids: Vec<String>,
+    vectors: Vec<f32>,
+    /// Neighbours of each node, per layer from the bottom up.
+    links: Vec<Vec<Vec<u32>>>,
+    deleted: Vec<bool>,
+    by_id: HashMap<String, u32>,
+    entry: Option<u32>,
+    rng: u64,
+}
+
+impl HnswIndex {
+    pub fn new(model: &str, dim: usize) -> Self {
+        HnswIndex {
+            model: model.to_string(),
+            dim,
+            log_seq: 0,
+            ids: Vec::new(),
+            vectors: Vec::new(),
+            links: Vec::new(),
+            deleted: Vec::new(),
+            by_id: HashMap::new(),
+            entry: None,
+            rng: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    /// Chunks in the index, not counting removed ones.
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    fn vector(&self, node: u32) -> &[f32] {
+        let start = node as usize * self.dim;
+        &self.vectors[start..start + self.dim]
+    }
+
+    fn random_level(&mut self) -> usize {
+        // xorshift64*, seeded per index so builds are reproducible.
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let bits = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11;
+        let uniform = (bits as f64 + 1.0) / (1u64 << 53) as f64;
+        (-uniform.ln() / (M as f64).ln()) as usize
+    }
+
+    fn max_links(level: usize) -> usize {
+        if level == 0 {
+            2 * M
+        } else {
+            M
+        }
+    }
+
+    fn top_level(&self) -> usize {
+        self.entry
+            .map(|e| self.links[e as usize].len() - 1)
+            .unwrap_or(0)
+    }
+
+    /// Best `ef` nodes on `level` reachable from `entry_points`, best first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[u32],
+        ef: usize,
+        level: usize,
+    ) -> Vec<Scored> {
+        let mut visited: HashSet<u32> = entry_points.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for &node in entry_points {
+            let s = Scored {
+                sim: dot(query, self.vector(node)),
+                node,
+            };
+            candidates.push(s);
+            found.push(Reverse(s));
+        }
+        while let Some(current) = candidates.pop() {
+            let worst = found
+                .peek()
+                .map(|r: &Reverse<Scored>| r.0.sim)
+                .unwrap_or(f32::MIN);
+            if current.sim < worst && found.len() >= ef {
+                break;
+            }
+            let Some(neighbours) = self.links[current.node as usize].get(level) else {
+                continue;
+            };
+            for &n in neighbours {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let s = Scored {
+                    sim: dot(query, self.vector(n)),
+                    node: n,
+                };
+                let worst = found.peek().map(|r| r.0.sim).unwrap_or(f32::MIN);
+                if found.len() < ef || s.sim > worst {
+                    candidates.push(s);
+                    found.push(Reverse(s));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        let mut out: Vec<Scored> = found.into_iter().map(|r| r.0).collect();
+        out.sort_by(|a, b| b.cmp(a));
+        out
+    }
+
+    /// Pick up to `m` neighbours from `candidates` (best first), preferring
+    /// ones that are not already covered by a closer pick, so links spread
+    /// across clusters instead of all pointing into one.
+    fn select_neighbours(&self, candidates: &[Scored], m: usize) -> Vec<u32> {
+        let mut picked: Vec<u32> = Vec::with_capacity(m);
+        let mut skipped = Vec::new();
+        for c in candidates {
+            if picked.len() >= m {
+                break;
+            }
+            let covered = picked
+                .iter()
+                .any(|&p| dot(self.vector(c.node), self.vector(p)) > c.sim);
+            if covered {
+                skipped.push(c.node);
+            } else {
+                picked.push(c.node);
+            }
+        }
+        for node in skipped {
+            if picked.len() >= m {
+                break;
+            }
+            picked.push(node);
+        }
+        picked
+    }
+
+    /// Add or replace the vector for `id`.
+    pub fn insert(&mut self, id: &str, vector: &[f32]) -> Result<(), String> {
+        if vector.len() != self.dim {
+            return Err(format!(
+                "vector for {id} has {} dimensions, index has {}",
+                vector.len(),
+                self.dim
+            ));
+        }
+        self.remove(id);
+        let node = self.ids.len() as u32;
+        let level = self.random_level();
+        self.ids.push(id.to_string());
+        self.vectors.extend_from_slice(vector);
+        self.links.push(vec![Vec::new(); level + 1]);
+        self.deleted.push(false);
+        self.by_id.insert(id.to_string(), node);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return Ok(());
+        };
+        let top = self.top_level();
+        let mut entry_points = vec![entry];
+        for l in (level + 1..=top).rev() {
+            entry_points = vec![self.search_layer(vector, &entry_points, 1, l)[0].node];
+        }
+        for l in (0..=level.min(top)).rev() {
+            let candidates = self.search_layer(vector, &entry_points, EF_CONSTRUCTION, l);
+            let neighbours = self.select_neighbours(&candidates, M);
+            for &n in &neighbours {
+                let links = &mut self.links[n as usize][l];
+                links.push(node);
+                if links.len() > Self::max_links(l) {
+                    let base = self.vector(n).to_vec();
+                    let mut scored: Vec<Scored> = self.links[n as usize][l]
+                        .iter()
+                        .map(|&o| Scored {
+                            sim: dot(&base, self.vector(o)),
+                            node: o,
+                        })
+                        .collect();
+                    scored.sort_by(|a, b| b.cmp(a));
+                    self.links[n as usize][l] = self.select_neighbours(&scored, Self::max_links(l));
+                }
+            }
+            self.links[node as usize][l] = neighbours;
+            entry_points = candidates.iter().map(|c| c.node).collect();
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+        Ok(())
+    }
+
+    /// Remove `id`; returns whether it was present.
+    pub fn remove(&mut self, id: &str) -> bool {
+        match self.by_id.remove(id) {
+            Some(node) => {
+                self.deleted[node as usize] = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Up to `k` ids most similar to `query`, best first. `ef` trades speed
+    /// for recall.
+    pub fn search(&self, query: &[f32], k: usize, ef: usize) -> Vec<(String, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        if self.is_empty() || query.len() != self.dim {
+            return Vec::new();
+        }
+        let mut entry_points = vec![entry];
+        for l in (1..=self.top_level()).rev() {
+            entry_points = vec![self.search_layer(query, &entry_points, 1, l)[0].node];
+        }
+        // Tombstones take up room in the candidate list; widen it to match.
+        let ef = ef.max(k) + (self.ids.len() - self.len()).min(ef);
+        self.search_layer(query, &entry_points, ef, 0)
+            .into_iter()
+            .filter(|s| !self.deleted[s.node as usize])
+            .take(k)
+            .map(|s| (self.ids[s.node as usize].clone(), s.sim))
+            .collect()
+    }
+
+    /// Whether removed chunks outnumber live ones.
+    pub fn needs_compaction(&self) -> bool {
+        self.ids.len() - self.len() > self.len()
+    }
+
+    /// The index rebuilt from its live chunks only.
+    pub fn compacted(&self) -> Self {
+        let mut fresh = HnswIndex::new(&self.model, self.dim);
+        fresh.log_seq = self.log_seq;
+        for (node, id) in self.ids.iter().enumerate() {
+            if !self.deleted[node] {
+                // Dimensions match, so this can't fail.
+                let _ = fresh.insert(id, self.vector(node as u32));
+            }
+        }
+        fresh
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut out = Vec::with_capacity(self.vectors.len() * 4 + self.ids.len() * 160);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.dim as u32).to_le_bytes());
+        out.extend_from_slice(&self.log_seq.to_le_bytes());
+        out.extend_from_slice(&self.rng.to_le_bytes());
+        out.extend_from_slice(&self.entry.unwrap_or(u32::MAX).to_le_bytes());
+        write_str(&mut out, &self.model);
+        out.extend_from_slice(&(self.ids.len() as u32).to_le_bytes());
+        for (node, id) in self.ids.iter().enumerate() {
+            write_str(&mut out, id);
+            out.push(self.deleted[node] as u8);
+            for v in self.vector(node as u32) {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            out.extend_from_slice(&(self.links[node].len() as u32).to_le_bytes());
+            for layer in &self.links[node] {
+                out.extend_from_slice(&(layer.len() as u32).to_le_bytes());
+                for n in layer {
+                    out.extend_from_slice(&n.to_le_bytes());
+                }
+            }
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, out).map_err(|e| e.to_string())?;
+        fs::rename(&tmp, path).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        let mut r = Reader {
+            bytes: &bytes,
+            pos: 0,
+        };
+        if r.take(8)? != MAGIC {
+            return Err(format!("{} is not a vector index", path.display()));
+        }
+        let version = r.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(format!("unsupported vector index version {version}"));
+        }
+        let dim = r.u32()? as usize;
+        let log_seq = r.u64()? as i64;
+        let rng = r.u64()?;
+        let entry = Some(r.u32()?).filter(|e| *e != u32::MAX);
+        let model = r.string()?;
+        let count = r.u32()? as usize;
+        let mut index = HnswIndex::new(&model, dim);
+        index.log_seq = log_seq;
+        index.rng = rng;
+        index.entry = entry;
+        for node in 0..count {
+            let id = r.string()?;
+            let deleted = r.take(1)?[0] != 0;
+            for _ in 0..dim {
+                index.vectors.push(f32::from_le_bytes(r.array()?));
+            }
+            let layers = r.u32()? as usize;
+            let mut links = Vec::with_capacity(layers);
+            for _ in 0..layers {
+                let n = r.u32()? as usize;
+                let layer = (0..n).map(|_| r.u32()).collect::<Result<Vec<_>, _>>()?;
+                if layer.iter().any(|&l| l as usize >= count) {
+                    return Err("vector index links out of range".into());
+                }
+                links.push(layer);
+            }
+            if !deleted {
+                index.by_id.insert(id.clone(), node as u32);
+            }
+            index.ids.push(id);
+            index.deleted.push(deleted);
+            index.links.push(links);
+        }
+        if entry.is_some_and(|e| e as usize >= count) {
+            return Err("vector index entry point out of range".into());
+        }
+        Ok(index)
+    }
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + n;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or("vector index is truncated")?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())
+    }
+}
+
+/// Which vectors a search runs over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorSource<'a> {
+    /// Hashed vectors in `embeddings`.
+    Hashed,
+    /// Model vectors in `chunk_vectors`.
+    Model(&'a str),
+}
+
+/// The vectors searches use: `model`'s when the index holds them, the
+/// hashed ones otherwise.
+pub async fn current_source<'a>(
+    conn: &mut SqliteConnection,
+    model: &'a str,
+) -> Result<VectorSource<'a>, String> {
+    Ok(match embeddings::index_model(conn).await? {
+        Some(m) if m.model == model && model != embeddings::HASH_MODEL => {
+            VectorSource::Model(model)
+        }
+        _ => VectorSource::Hashed,
+    })
+}
+
+impl VectorSource<'_> {
+    fn model(&self) -> &str {
+        match self {
+            VectorSource::Hashed => embeddings::HASH_MODEL,
+            VectorSource::Model(m) => m,
+        }
+    }
+
+    fn decode(&self, blob: &[u8]) -> Vec<f32> {
+        decode_vector(blob)
+    }
+
+    /// Table and filter selecting this source's vectors; `?1` is the model.
+    fn table(&self) -> (&'static str, &'static str) {
+        match self {
+            VectorSource::Hashed => ("embeddings", "?1 IS NOT NULL"),
+            VectorSource::Model(_) => ("chunk_vectors", "model = ?1"),
+        }
+    }
+
+    async fn dimension(&self, conn: &mut SqliteConnection) -> Result<usize, String> {
+        match self {
+            VectorSource::Hashed => Ok(EMBED_DIM),
+            VectorSource::Model(m) => embeddings::index_model(conn)
+                .await?
+                .filter(|i| i.model == *m)
+                .map(|i| i.dimension)
+                .ok_or_else(|| format!("the index has no {m} vectors")),
+        }
+    }
+
+    async fn count(&self, conn: &mut SqliteConnection) -> Result<usize, String> {
+        let (table, filter) = self.table();
+        let n: i64 = sqlx::query(&format!("SELECT COUNT(*) AS n FROM {table} WHERE {filter}"))
+            .bind(self.model())
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?
+            .get("n");
+        Ok(n as usize)
+    }
+
+    /// Vectors of every chunk, or of `chunk_id` only.
+    async fn rows(
+        &self,
+        conn: &mut SqliteConnection,
+        chunk_id: Option<&str>,
+    ) -> Result<Vec<(String, Vec<f32>)>, String> {
+        let (table, filter) = self.table();
+        let sql = format!(
+            "SELECT chunk_id, embedding FROM {table} WHERE {filter} AND (?2 IS NULL OR chunk_id = ?2)"
+        );
+        Ok(sqlx::query(&sql)
+            .bind(self.model())
+            .bind(chunk_id)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|r| {
+                (
+                    r.get("chunk_id"),
+                    self.decode(&r.get::<Vec<u8>, _>("embedding")),
+                )
+            })
+            .collect())
+    }
+}
+
+/// Graphs kept in memory between searches, by file. The lock is only held
+/// to look a graph up or swap it; searches run on a shared copy.
+fn loaded() -> &'static Mutex<HashMap<PathBuf, Arc<HnswIndex>>> {
+    static LOADED: OnceLock<Mutex<HashMap<PathBuf, Arc<HnswIndex>>>> = OnceLock::new();
+    LOADED.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn cache() -> std::sync::MutexGuard<'static, HashMap<PathBuf, Arc<HnswIndex>>> {
+    loaded().lock().unwrap_or_else(|e| e.into_inner())
+}
+
+async fn last_seq(conn: &mut SqliteConnection) -> Result<i64, String> {
+    Ok(
+        sqlx::query("SELECT COALESCE(MAX(seq), 0) AS seq FROM vector_log")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?
+            .get("seq"),
+    )
+}
+
+/// Bring the graph at `path` up to date with `source`'s vectors. Run by
+/// indexing, never by searches.
+///
+/// An index with fewer than `min_chunks` chunks keeps no graph and is
+/// searched exactly. Otherwise the graph is built on first use, then caught
+/// up from `vector_log` and saved; it is rebuilt when the source's model or
+/// dimension no longer matches.
+pub async fn update_graph(
+    conn: &mut SqliteConnection,
+    path: &Path,
+    source: VectorSource<'_>,
+    min_chunks: usize,
+) -> Result<(), String> {
+    embeddings::ensure_schema(conn).await?;
+    conn.execute(LOG_SCHEMA).await.map_err(|e| e.to_string())?;
+    if source.count(conn).await? < min_chunks {
+        // Drop any old graph so it isn't trusted again after the log has
+        // been cleared.
+        cache().remove(path);
+        if path.exists() {
+            fs::remove_file(path).map_err(|e| e.to_string())?;
+        }
+        conn.execute("DELETE FROM vector_log")
+            .await
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+    let dim = source.dimension(conn).await?;
+
+    // Searches meanwhile fall back to the saved file.
+    let cached = cache().remove(path).and_then(|i| Arc::try_unwrap(i).ok());
+    let cached = match cached {
+        Some(index) => Some(index),
+        None if path.exists() => HnswIndex::load(path)
+            .map_err(|e| log::warn!("rebuilding vector index: {e}"))
+            .ok(),
+        None => None,
+    }
+    .filter(|i| i.model == source.model() && i.dim == dim);
+
+    let mut changed = false;
+    let index = match cached {
+        Some(mut index) => {
+            let touched: Vec<(i64, String)> =
+                sqlx::query("SELECT seq, chunk_id FROM vector_log WHERE seq > ? ORDER BY seq")
+                    .bind(index.log_seq)
+                    .fetch_all(&mut *conn)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .into_iter()
+                    .map(|r| (r.get("seq"), r.get("chunk_id")))
+                    .collect();
+            let mut ids: Vec<&str> = touched.iter().map(|(_, id)| id.as_str()).collect();
+            ids.sort_unstable();
+            ids.dedup();
+            for id in ids {
+                index.remove(id);
+                for (id, vector) in source.rows(conn, Some(id)).await? {
+                    index.insert(&id, &vector)?;
+                }
+            }
+            if let Some((seq, _)) = touched.last() {
+                index.log_seq = *seq;
+                changed = true;
+            }
+            if index.needs_compaction() {
+                index = index.compacted();
+            }
+            index
+        }
+        None => {
+            let seq = last_seq(conn).await?;
+            let mut index = HnswIndex::new(source.model(), dim);
+            for (id, vector) in source.rows(conn, None).await? {
+                index.insert(&id, &vector)?;
+            }
+            index.log_seq = seq;
+            changed = true;
+            index
+        }
+    };
+    if changed {
+        index.save(path)?;
+        sqlx::query("DELETE FROM vector_log WHERE seq <= ?")
+            .bind(index.log_seq)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    cache().insert(path.to_path_buf(), Arc::new(index));
+    Ok(())
+}
+
+/// Nearest chunks to `query` from the graph at `path`, or `None` when there
+/// is no graph of `source`'s vectors and the index should be searched
+/// exactly.
+///
+/// The graph is only as fresh as the last [`update_graph`]: chunks written
+/// since are missing and removed ones may still be returned.
+pub fn nearest(
+    path: &Path,
+    source: VectorSource<'_>,
+    query: &[f32],
+    k: usize,
+) -> Result<Option<Vec<(String, f32)>>, String> {
+    let cached = cache().get(path).cloned();
+    let index = match cached {
+        Some(index) => index,
+        None if path.exists() => {
+            let index = Arc::new(HnswIndex::load(path)?);
+            cache().insert(path.to_path_buf(), index.clone());
+            index
+        }
+        None => return Ok(None),
+    };
+    if index.model != source.model() || index.dim != query.len() {
+        return Ok(None);
+    }
+    Ok(Some(index.search(query, k, MIN_EF_SEARCH.max(4 * k))))
+}
@@ -0,0 +1,364 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{net::TcpStream, sync::mpsc::UnboundedSender, time::Instant};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+type ComfySocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How long a queued workflow may take before it is given up on.
+pub const WORKFLOW_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Base URL of the ComfyUI server started by `comfy_start`.
+///
+/// Can be overridden with `BLOSSOM_COMFY_URL`.
+pub fn comfy_url() -> String {
+    if let Ok(url) = env::var("BLOSSOM_COMFY_URL") {
+        if !url.trim().is_empty() {
+            return url.trim_end_matches('/').to_string();
+        }
+    }
+    "http://127.0.0.1:8188".to_string()
+}
+
+/// Execution progress of a submitted workflow, as reported by ComfyUI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowProgress {
+    pub prompt_id: String,
+    pub node: Option<String>,
+    pub value: u64,
+    pub max: u64,
+}
+
+fn ws_url(base: &str, client_id: &str) -> Option<String> {
+    let mut url = url::Url::parse(base).ok()?;
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    url.set_scheme(scheme).ok()?;
+    url.set_path("/ws");
+    url.set_query(Some(&format!("clientId={client_id}")));
+    Some(url.to_string())
+}
+
+async fn submit(
+    client: &reqwest::Client,
+    base: &str,
+    workflow: &Value,
+    client_id: &str,
+) -> Result<String, String> {
+    let resp = client
+        .post(format!("{base}/prompt"))
+        .json(&json!({ "prompt": workflow, "client_id": client_id }))
+        .send()
+        .await
+        .map_err(|e| format!("failed to submit workflow: {e}"))?;
+    let status = resp.status();
+    let body: Value = resp.json().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("ComfyUI rejected workflow ({status}): {body}"));
+    }
+    body["prompt_id"]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| format!("no prompt_id in response: {body}"))
+}
+
+/// Follow websocket events until `prompt_id` finishes.
+///
+/// Returns `Ok(())` when execution finished or the socket went away; the
+/// caller reads the final state from `/history` either way.
+async fn follow_ws(
+    mut ws: ComfySocket,
+    prompt_id: &str,
+    progress: Option<&UnboundedSender<WorkflowProgress>>,
+) -> Result<(), String> {
+    let mut node: Option<String> = None;
+    while let Some(msg) = ws.next().await {
+        let text = match msg {
+            Ok(Message::Text(t)) => t,
+            Ok(Message::Close(_)) | Err(_) => break,
+            // Binary frames are live previews.
+            Ok(_) => continue,
+        };
+        let event: Value = match serde_json::from_str(&text) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let data = &event["data"];
+        if let Some(id) = data["prompt_id"].as_str() {
+            if id != prompt_id {
+                continue;
+            }
+        }
+        match event["type"].as_str().unwrap_or_default() {
+            "executing" => {
+                if data["node"].is_null() {
+                    break;
+                }
+                node = data["node"].as_str().map(|s| s.to_string());
+            }
+            "progress" => {
+                if let Some(tx) = progress {
+                    let _ = tx.send(WorkflowProgress {
+                        prompt_id: prompt_id.to_string(),
+                        node: node.clone(),
+                        value: data["value"].as_u64().unwrap_or(0),
+                        max: data["max"].as_u64().unwrap_or(0),
+                    });
+                }
+            }
+            "execution_success" => break,
+            "execution_error" => {
+                let msg = data["exception_message"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .trim();
+                return Err(format!("ComfyUI execution failed: {msg}"));
+            }
+            _ => {}
+        }
+    }
+    let _ = ws.close(None).await;
+    Ok(())
+}
+
+/// Where `prompt_id` is in ComfyUI's queue: `Some(true)` while it runs,
+/// `Some(false)` while it waits and `None` once it has left the queue.
+async fn queue_position(
+    client: &reqwest::Client,
+    base: &str,
+    prompt_id: &str,
+) -> Result<Option<bool>, String> {
+    let resp = client
+        .get(format!("{base}/queue"))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        // Can't tell; assume it is still waiting.
+        return Ok(Some(false));
+    }
+    let body: Value = resp.json().await.map_err(|e| e.to_string())?;
+    let listed = |key: &str| {
+        body[key]
+            .as_array()
+            .is_some_and(|items| items.iter().any(|item| item[1].as_str() == Some(prompt_id)))
+    };
+    Ok(if listed("queue_running") {
+        Some(true)
+    } else if listed("queue_pending") {
+        Some(false)
+    } else {
+        None
+    })
+}
+
+/// Poll `/history` until the prompt shows up and return its entry.
+///
+/// Fails once `deadline` passes or when the prompt is neither finished nor
+/// queued any more, e.g. because ComfyUI was restarted.
+async fn wait_history(
+    client: &reqwest::Client,
+    base: &str,
+    prompt_id: &str,
+    deadline: Instant,
+) -> Result<Value, String> {
+    let mut missing = false;
+    loop {
+        let resp = client
+            .get(format!("{base}/history/{prompt_id}"))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if resp.status().is_success() {
+            let body: Value = resp.json().await.map_err(|e| e.to_string())?;
+            let entry = &body[prompt_id];
+            if !entry.is_null() {
+                if entry["status"]["status_str"].as_str() == Some("error") {
+                    return Err(format!(
+                        "ComfyUI execution failed: {}",
+                        entry["status"]["messages"]
+                    ));
+                }
+                return Ok(entry.clone());
+            }
+        }
+        if queue_position(client, base, prompt_id).await?.is_none() {
+            // It may have finished between the two requests; look once more.
+            if missing {
+                return Err(format!("ComfyUI dropped prompt {prompt_id}"));
+            }
+            missing = true;
+            continue;
+        }
+        missing = false;
+        if Instant::now() >= deadline {
+            return Err(format!("timed out waiting for ComfyUI prompt {prompt_id}"));
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// Remove `prompt_id` from ComfyUI's queue, interrupting it if it is running.
+async fn cancel_prompt(client: &reqwest::Client, base: &str, prompt_id: &str) {
+    let res = match queue_position(client, base, prompt_id).await {
+        Ok(Some(true)) => client
+            .post(format!("{base}/interrupt"))
+            .json(&json!({ "prompt_id": prompt_id }))
+            .send()
+            .await
+            .map(drop),
+        Ok(Some(false)) => client
+            .post(format!("{base}/queue"))
+            .json(&json!({ "delete": [prompt_id] }))
+            .send()
+            .await
+            .map(drop),
+        Ok(None) => Ok(()),
+        Err(e) => {
+            log::warn!("could not cancel ComfyUI prompt {prompt_id}: {e}");
+            Ok(())
+        }
+    };
+    if let Err(e) = res {
+        log::warn!("could not cancel ComfyUI prompt {prompt_id}: {e}");
+    }
+}
+
+/// Cancels the prompt in ComfyUI unless disarmed, so a workflow that fails,
+/// times out or whose task is cancelled doesn't keep the GPU busy.
+struct PromptGuard {
+    client: reqwest::Client,
+    base: String,
+    prompt_id: String,
+    armed: bool,
+}
+
+impl Drop for PromptGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        if let Ok(rt) = tokio::runtime::Handle::try_current() {
+            let client = self.client.clone();
+            let base = self.base.clone();
+            let prompt_id = self.prompt_id.clone();
+            rt.spawn(async move { cancel_prompt(&client, &base, &prompt_id).await });
+        }
+    }
+}
+
+async fn download_outputs(
+    client: &reqwest::Client,
+    base: &str,
+    prompt_id: &str,
+    entry: &Value,
+    out_dir: &Path,
+) -> Result<Vec<PathBuf>, String> {
+    fs::create_dir_all(out_dir).map_err(|e| e.to_string())?;
+    let mut paths = Vec::new();
+    let outputs = match entry["outputs"].as_object() {
+        Some(o) => o,
+        None => return Ok(paths),
+    };
+    for output in outputs.values() {
+        let images = match output["images"].as_array() {
+            Some(i) => i,
+            None => continue,
+        };
+        for image in images {
+            let kind = image["type"].as_str().unwrap_or("output");
+            // Preview nodes write to ComfyUI's temp folder; only keep saved images.
+            if kind != "output" {
+                continue;
+            }
+            let filename = match image["filename"].as_str() {
+                Some(f) => f,
+                None => continue,
+            };
+            let subfolder = image["subfolder"].as_str().unwrap_or_default();
+            let resp = client
+                .get(format!("{base}/view"))
+                .query(&[
+                    ("filename", filename),
+                    ("subfolder", subfolder),
+                    ("type", kind),
+                ])
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if !resp.status().is_success() {
+                return Err(format!("failed to download {filename}: {}", resp.status()));
+            }
+            let bytes = resp.bytes().await.map_err(|e| e.to_string())?;
+            let name = Path::new(filename)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "output.png".into());
+            let mut dest = out_dir.join(&name);
+            if dest.exists() {
+                dest = out_dir.join(format!("{prompt_id}_{name}"));
+            }
+            fs::write(&dest, &bytes).map_err(|e| e.to_string())?;
+            paths.push(dest);
+        }
+    }
+    Ok(paths)
+}
+
+/// Submit `workflow` (API format) to ComfyUI at `base`, wait for it to finish
+/// and download its output images into `out_dir`.
+///
+/// Progress is streamed over ComfyUI's websocket when it is reachable;
+/// otherwise the history endpoint is polled until the prompt completes. The
+/// prompt is cancelled in ComfyUI if it doesn't finish within `timeout` or
+/// the returned future is dropped first.
+pub async fn run_workflow(
+    base: &str,
+    workflow: &Value,
+    out_dir: &Path,
+    progress: Option<UnboundedSender<WorkflowProgress>>,
+    timeout: Duration,
+) -> Result<Vec<PathBuf>, String> {
+    let deadline = Instant::now() + timeout;
+    let client = reqwest::Client::new();
+    let client_id: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+
+    // Connect before submitting so no events are missed.
+    let ws = match ws_url(base, &client_id) {
+        Some(url) => match connect_async(url).await {
+            Ok((ws, _)) => Some(ws),
+            Err(e) => {
+                log::warn!("ComfyUI websocket unavailable, polling instead: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let prompt_id = submit(&client, base, workflow, &client_id).await?;
+    let mut guard = PromptGuard {
+        client: client.clone(),
+        base: base.to_string(),
+        prompt_id: prompt_id.clone(),
+        armed: true,
+    };
+    if let Some(ws) = ws {
+        tokio::time::timeout_at(deadline, follow_ws(ws, &prompt_id, progress.as_ref()))
+            .await
+            .map_err(|_| format!("timed out waiting for ComfyUI prompt {prompt_id}"))??;
+    }
+    let entry = wait_history(&client, base, &prompt_id, deadline).await?;
+    guard.armed = false;
+    download_outputs(&client, base, &prompt_id, &entry, out_dir).await
+}
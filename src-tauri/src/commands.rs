@@ -1,5 +1,6 @@
 // src-tauri/src/commands.rs
 use std::{
+    collections::{BTreeMap, HashMap},
     env,
     fs::{self, OpenOptions},
     io::{BufRead, BufReader, Write},
@@ -11,15 +12,28 @@ use std::{
 
 use dirs;
 
+use crate::context;
+use crate::conversations;
+use crate::intent;
+use crate::llm;
+use crate::ollama::{self, models_dir, ModelFeature};
+use crate::pdf_library::{self, DuplicatePolicy};
+use crate::prompts;
 use crate::python_helpers::conda_python;
+use crate::records::{self, ParsedBatch, RecordError, RecordKind, Rule, Spell};
+use crate::retrieval;
+use crate::search::{
+    self, Diversity, Highlight, PageRequest, Scope, SearchFacets, SearchFilters, SearchMode,
+    SNIPPET_CHARS,
+};
+use crate::structured;
 use crate::task_queue::{Task, TaskCommand, TaskQueue};
 use chrono::{Local, Utc};
-use rand::{thread_rng, Rng};
 use reqwest;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use sha2::{Digest, Sha256};
-use sqlx::{Connection, Row, SqliteConnection};
 use std::process::Command as StdCommand;
 use sysinfo::System;
 use tauri::async_runtime::Mutex as AsyncMutex;
@@ -310,6 +324,23 @@ pub async fn comfy_stop() -> Result<(), String> {
     Ok(())
 }
 
+/// Queue a ComfyUI workflow; the task result is the list of saved images.
+#[tauri::command]
+pub async fn comfy_submit_workflow(
+    queue: State<'_, TaskQueue>,
+    workflow: Value,
+    output_dir: Option<String>,
+) -> Result<u64, String> {
+    if !workflow.is_object() {
+        return Err("workflow must be a JSON object".into());
+    }
+    let cmd = TaskCommand::ComfyWorkflow {
+        workflow,
+        output_dir,
+    };
+    Ok(queue.enqueue("comfy_workflow".into(), cmd).await)
+}
+
 fn default_comfy_path<R: Runtime>(app: &AppHandle<R>) -> PathBuf {
     if let Ok(cwd) = std::env::current_dir() {
         let dev = cwd.join("ComfyUI");
@@ -403,6 +434,10 @@ pub struct PdfSearchHit {
     pub page_range: [u32; 2],
     pub text: String,
     pub score: f32,
+    /// Part of `text` around the best match.
+    pub snippet: String,
+    /// Where the query's terms are in `snippet`.
+    pub highlights: Vec<Highlight>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -411,59 +446,127 @@ pub struct VaultSearchHit {
     pub page_range: [u32; 2],
     pub text: String,
     pub score: f32,
+    /// Part of `text` around the best match.
+    pub snippet: String,
+    /// Where the query's terms are in `snippet`.
+    pub highlights: Vec<Highlight>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct SpellRecord {
-    pub name: String,
-    pub description: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RuleRecord {
-    pub name: String,
-    pub description: String,
-}
-
+pub struct VaultSearchPage {
+    pub hits: Vec<VaultSearchHit>,
+    pub facets: SearchFacets,
+    /// Hits that can be paged through, at most 200
+    /// ([`search::MAX_CANDIDATES`]); not an exact count of the matches.
+    pub total: usize,
+    pub offset: usize,
+    pub next_cursor: Option<String>,
+}
+
+/// Queue an incremental `vault_index` run, which embeds chunks added since
+/// the last one with the configured model and drops vectors of removed ones.
+async fn refresh_index(queue: &TaskQueue) {
+    let cmd = TaskCommand::IndexVault { force: false };
+    queue.enqueue("vault_index".into(), cmd).await;
+}
+
+/// Import a PDF into the document library. Returns its `doc_id` and page
+/// count, with the `action` taken and the `existing` documents it matched.
+/// A file whose text is already imported is skipped unless `on_duplicate`
+/// says to replace the old copy or keep both. Pages without a text layer
+/// are listed in `pages_without_text`. The new chunks get model vectors from
+/// a queued index refresh.
 #[tauri::command]
-pub async fn pdf_add<R: Runtime>(app: AppHandle<R>, path: String) -> Result<Value, String> {
-    let out = run_pdf_tool(&app, &["add", &path])?;
-    serde_json::from_str(&out).map_err(|e| e.to_string())
+pub async fn pdf_add<R: Runtime>(
+    app: AppHandle<R>,
+    queue: State<'_, TaskQueue>,
+    path: String,
+    on_duplicate: Option<DuplicatePolicy>,
+) -> Result<Value, String> {
+    let report = pdf_library::add_pdf(
+        &vault_index_path(&app),
+        Path::new(&path),
+        on_duplicate.unwrap_or_default(),
+    )
+    .await?;
+    refresh_index(&queue).await;
+    let doc = report.doc;
+    Ok(json!({
+        "doc_id": doc.doc_id,
+        "pages": doc.pages,
+        "title": doc.title,
+        "created": doc.created,
+        "action": report.action,
+        "existing": report.existing,
+        "edition_of": doc.edition_of,
+        "pages_without_text": report.pages_without_text,
+    }))
 }
 
 #[tauri::command]
-pub async fn pdf_remove<R: Runtime>(app: AppHandle<R>, doc_id: String) -> Result<(), String> {
-    let _ = run_pdf_tool(&app, &["remove", &doc_id])?;
+pub async fn pdf_remove<R: Runtime>(
+    app: AppHandle<R>,
+    queue: State<'_, TaskQueue>,
+    doc_id: String,
+) -> Result<(), String> {
+    pdf_library::remove_pdf(&vault_index_path(&app), &doc_id).await?;
+    refresh_index(&queue).await;
     Ok(())
 }
 
 #[tauri::command]
 pub async fn pdf_list<R: Runtime>(app: AppHandle<R>) -> Result<Vec<PdfDoc>, String> {
-    let out = run_pdf_tool(&app, &["list"])?;
-    let v: Value = serde_json::from_str(&out).map_err(|e| e.to_string())?;
-    serde_json::from_value(v["documents"].clone()).map_err(|e| e.to_string())
+    Ok(pdf_library::list_pdfs(&vault_index_path(&app))
+        .await?
+        .into_iter()
+        .map(|d| PdfDoc {
+            doc_id: d.doc_id,
+            title: Some(d.title),
+            pages: Some(d.pages),
+            created: d.created,
+        })
+        .collect())
 }
 
+/// Search the chunks of indexed PDFs. `mode` defaults to hybrid keyword and
+/// vector search; `diversity` re-ranks the hits so they cover more pages.
 #[tauri::command]
 pub async fn pdf_search<R: Runtime>(
     app: AppHandle<R>,
     query: String,
     k: Option<u32>,
+    mode: Option<SearchMode>,
+    diversity: Option<Diversity>,
 ) -> Result<Vec<PdfSearchHit>, String> {
-    let mut args = vec!["search".to_string(), query];
-    if let Some(k) = k {
-        args.push("-k".into());
-        args.push(k.to_string());
-    }
-    let arg_refs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-    let out = run_pdf_tool(&app, &arg_refs)?;
-    let v: Value = serde_json::from_str(&out).map_err(|e| e.to_string())?;
-    serde_json::from_value(v["results"].clone()).map_err(|e| e.to_string())
+    let k = k.unwrap_or(3) as usize;
+    let index_dir = vault_index_path(&app);
+    let mode = mode.unwrap_or_default();
+    let filters = SearchFilters::default();
+    let hits = match &diversity {
+        Some(d) => {
+            search::search_diverse(&index_dir, &query, k, mode, Scope::Pdfs, &filters, d).await?
+        }
+        None => search::search_index(&index_dir, &query, k, mode, Scope::Pdfs, &filters).await?,
+    };
+    Ok(hits
+        .into_iter()
+        .map(|h| {
+            let snippet = search::snippet(&h.text, &query, SNIPPET_CHARS);
+            PdfSearchHit {
+                doc_id: h.doc_id,
+                page_range: h.page_range,
+                text: h.text,
+                score: h.score,
+                snippet: snippet.text,
+                highlights: snippet.highlights,
+            }
+        })
+        .collect())
 }
 
-const EMBED_DIM: usize = 512;
+pub const EMBED_DIM: usize = 512;
 
-fn hash_embed(text: &str) -> Vec<f32> {
+pub fn hash_embed(text: &str) -> Vec<f32> {
     let mut vec = vec![0f32; EMBED_DIM];
     for token in text.split_whitespace() {
         let digest = Sha256::digest(token.to_lowercase().as_bytes());
@@ -480,9 +583,11 @@ fn hash_embed(text: &str) -> Vec<f32> {
     vec
 }
 
-fn vault_index_path<R: Runtime>(app: &AppHandle<R>) -> PathBuf {
+/// Root of the user's output folder (`BLOSSOM_OUTPUT_DIR`, then the
+/// `output_folder` setting, then `Knowledge`).
+pub fn output_folder() -> PathBuf {
     if let Ok(dir) = env::var("BLOSSOM_OUTPUT_DIR") {
-        return PathBuf::from(dir).join("Index");
+        return PathBuf::from(dir);
     }
     if let Some(home) = dirs::home_dir() {
         let settings = home.join(".blossom_settings.json");
@@ -490,60 +595,87 @@ fn vault_index_path<R: Runtime>(app: &AppHandle<R>) -> PathBuf {
             if let Ok(text) = fs::read_to_string(&settings) {
                 if let Ok(v) = serde_json::from_str::<Value>(&text) {
                     if let Some(out) = v["output_folder"].as_str() {
-                        return PathBuf::from(out).join("Index");
+                        return PathBuf::from(out);
                     }
                 }
             }
         }
     }
-    PathBuf::from("Knowledge").join("Index")
+    PathBuf::from("Knowledge")
+}
+
+/// Folder holding the vault's `index.sqlite`, inside the output folder.
+pub fn vault_index_dir() -> PathBuf {
+    output_folder().join("Index")
+}
+
+fn vault_index_path<R: Runtime>(_app: &AppHandle<R>) -> PathBuf {
+    vault_index_dir()
 }
 
+/// Queue indexing of the notes in the output folder; the task result is an
+/// `IndexReport`.
 #[tauri::command]
+pub async fn vault_index(queue: State<'_, TaskQueue>, force: Option<bool>) -> Result<u64, String> {
+    let cmd = TaskCommand::IndexVault {
+        force: force.unwrap_or(false),
+    };
+    Ok(queue.enqueue("vault_index".into(), cmd).await)
+}
+
+/// Search the vault index. `mode` defaults to hybrid keyword and vector search.
+///
+/// Results come a page at a time: `limit` hits (default 10) starting at
+/// `offset`, or where the `cursor` from the previous page left off, with
+/// facet counts over every ranked match. `diversity` re-ranks the matches
+/// with maximal marginal relevance and caps the hits per document.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn vault_search<R: Runtime>(
     app: AppHandle<R>,
     query: String,
-    k: Option<u32>,
-) -> Result<Vec<VaultSearchHit>, String> {
-    let k = k.unwrap_or(3) as usize;
-    let db_path = vault_index_path(&app).join("index.sqlite");
-    if !db_path.exists() {
-        return Ok(Vec::new());
-    }
-    let mut conn = SqliteConnection::connect(&format!("sqlite:{}", db_path.to_string_lossy()))
-        .await
-        .map_err(|e| e.to_string())?;
-    let rows = sqlx::query("SELECT doc_id, page_start, page_end, text, embedding FROM embeddings")
-        .fetch_all(&mut conn)
-        .await
-        .map_err(|e| e.to_string())?;
-    let qvec = hash_embed(&query);
-    let mut hits = Vec::new();
-    for row in rows {
-        let doc_id: String = row.get("doc_id");
-        let ps: i64 = row.get("page_start");
-        let pe: i64 = row.get("page_end");
-        let text: String = row.get("text");
-        let blob: Vec<u8> = row.get("embedding");
-        let mut emb = vec![0f32; blob.len() / 4];
-        for (i, chunk) in blob.chunks_exact(4).enumerate() {
-            emb[i] = f32::from_ne_bytes(chunk.try_into().unwrap());
-        }
-        let score: f32 = qvec.iter().zip(emb.iter()).map(|(a, b)| a * b).sum();
-        hits.push(VaultSearchHit {
-            doc_id,
-            page_range: [ps as u32, pe as u32],
-            text,
-            score,
-        });
-    }
-    hits.sort_by(|a, b| {
-        b.score
-            .partial_cmp(&a.score)
-            .unwrap_or(std::cmp::Ordering::Equal)
-    });
-    hits.truncate(k);
-    Ok(hits)
+    mode: Option<SearchMode>,
+    filters: Option<SearchFilters>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    cursor: Option<String>,
+    diversity: Option<Diversity>,
+) -> Result<VaultSearchPage, String> {
+    let page = search::search_page(
+        &vault_index_path(&app),
+        &query,
+        mode.unwrap_or_default(),
+        Scope::All,
+        &filters.unwrap_or_default(),
+        &PageRequest {
+            limit: limit.map(|l| l as usize),
+            offset: offset.map(|o| o as usize),
+            cursor,
+        },
+        diversity.as_ref(),
+    )
+    .await?;
+    Ok(VaultSearchPage {
+        hits: page
+            .hits
+            .into_iter()
+            .map(|h| {
+                let snippet = search::snippet(&h.text, &query, SNIPPET_CHARS);
+                VaultSearchHit {
+                    doc_id: h.doc_id,
+                    page_range: h.page_range,
+                    text: h.text,
+                    score: h.score,
+                    snippet: snippet.text,
+                    highlights: snippet.highlights,
+                }
+            })
+            .collect(),
+        facets: page.facets,
+        total: page.total,
+        offset: page.offset,
+        next_cursor: page.next_cursor,
+    })
 }
 
 #[tauri::command]
@@ -552,6 +684,14 @@ pub async fn pdf_ingest<R: Runtime>(
     queue: State<'_, TaskQueue>,
     doc_id: String,
 ) -> Result<u64, String> {
+    if let Some(original) = pdf_library::get_pdf(&vault_index_path(&app), &doc_id)
+        .await?
+        .and_then(|d| d.duplicate_of)
+    {
+        return Err(format!(
+            "{doc_id} has the same text as {original}; ingest that document instead"
+        ));
+    }
     let py = conda_python();
     if !py.exists() {
         return Err(format!("Python not found at {}", py.display()));
@@ -572,20 +712,24 @@ pub async fn pdf_ingest<R: Runtime>(
 pub async fn parse_spell_pdf<R: Runtime>(
     app: AppHandle<R>,
     path: String,
-) -> Result<Vec<SpellRecord>, String> {
+) -> Result<ParsedBatch<Spell>, String> {
     let out = run_pdf_tool(&app, &["spells", &path])?;
     let v: Value = serde_json::from_str(&out).map_err(|e| e.to_string())?;
-    serde_json::from_value(v["spells"].clone()).map_err(|e| e.to_string())
+    let spells: Vec<Value> =
+        serde_json::from_value(v["spells"].clone()).map_err(|e| e.to_string())?;
+    Ok(records::parse_batch(RecordKind::Spell, spells))
 }
 
 #[tauri::command]
 pub async fn parse_rule_pdf<R: Runtime>(
     app: AppHandle<R>,
     path: String,
-) -> Result<Vec<RuleRecord>, String> {
+) -> Result<ParsedBatch<Rule>, String> {
     let out = run_pdf_tool(&app, &["rules", &path])?;
     let v: Value = serde_json::from_str(&out).map_err(|e| e.to_string())?;
-    serde_json::from_value(v["rules"].clone()).map_err(|e| e.to_string())
+    let rules: Vec<Value> =
+        serde_json::from_value(v["rules"].clone()).map_err(|e| e.to_string())?;
+    Ok(records::parse_batch(RecordKind::Rule, rules))
 }
 
 #[tauri::command]
@@ -602,6 +746,10 @@ pub async fn parse_lore_pdf<R: Runtime>(
 mod tests {
     use super::*;
 
+    fn extract_intent(content: &str) -> String {
+        intent::extract_intent(content, &intent::IntentSettings::default()).intent
+    }
+
     #[test]
     fn parse_json_npc() {
         let content = "{\"intent\":\"npc\",\"confidence\":0.9}";
@@ -733,7 +881,7 @@ fn blender_path() -> PathBuf {
 Rule storage
 ============================== */
 
-fn rule_storage_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+pub fn rule_storage_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
     let dir = app
         .path()
         .app_data_dir()
@@ -748,45 +896,29 @@ pub async fn save_rule<R: Runtime>(
     app: AppHandle<R>,
     rule: Value,
     overwrite: Option<bool>,
-) -> Result<(), String> {
-    let id = rule["id"]
-        .as_str()
-        .ok_or_else(|| "missing id".to_string())?;
+) -> Result<(), RecordError> {
+    let rule = records::parse_record(RecordKind::Rule, rule)?;
+    let id = records::record_id(&rule)?;
     let dir = rule_storage_dir(&app)?;
     let path = dir.join(format!("{id}.json"));
     if path.exists() && !overwrite.unwrap_or(false) {
-        return Err("exists".into());
+        return Err("exists".to_string().into());
     }
     let json = serde_json::to_string_pretty(&rule).map_err(|e| e.to_string())?;
-    fs::write(&path, json).map_err(|e| e.to_string())
+    fs::write(&path, json).map_err(|e| e.to_string())?;
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn list_rules<R: Runtime>(app: AppHandle<R>) -> Result<Vec<Value>, String> {
-    let dir = rule_storage_dir(&app)?;
-    let mut rules = Vec::new();
-    if dir.exists() {
-        let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
-        for entry in entries {
-            let entry = entry.map_err(|e| e.to_string())?;
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("json")
-                && path.file_name().and_then(|s| s.to_str()) != Some("index.json")
-            {
-                let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
-                let data: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
-                rules.push(data);
-            }
-        }
-    }
-    Ok(rules)
+    records::load_dir(RecordKind::Rule, &rule_storage_dir(&app)?)
 }
 
 /* ==============================
 Spell storage
 ============================== */
 
-fn spell_storage_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+pub fn spell_storage_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
     let dir = app
         .path()
         .app_data_dir()
@@ -801,45 +933,29 @@ pub async fn save_spell<R: Runtime>(
     app: AppHandle<R>,
     spell: Value,
     overwrite: Option<bool>,
-) -> Result<(), String> {
-    let id = spell["id"]
-        .as_str()
-        .ok_or_else(|| "missing id".to_string())?;
+) -> Result<(), RecordError> {
+    let spell = records::parse_record(RecordKind::Spell, spell)?;
+    let id = records::record_id(&spell)?;
     let dir = spell_storage_dir(&app)?;
     let path = dir.join(format!("{id}.json"));
     if path.exists() && !overwrite.unwrap_or(false) {
-        return Err("exists".into());
+        return Err("exists".to_string().into());
     }
     let json = serde_json::to_string_pretty(&spell).map_err(|e| e.to_string())?;
-    fs::write(&path, json).map_err(|e| e.to_string())
+    fs::write(&path, json).map_err(|e| e.to_string())?;
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn list_spells<R: Runtime>(app: AppHandle<R>) -> Result<Vec<Value>, String> {
-    let dir = spell_storage_dir(&app)?;
-    let mut spells = Vec::new();
-    if dir.exists() {
-        let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
-        for entry in entries {
-            let entry = entry.map_err(|e| e.to_string())?;
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("json")
-                && path.file_name().and_then(|s| s.to_str()) != Some("index.json")
-            {
-                let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
-                let data: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
-                spells.push(data);
-            }
-        }
-    }
-    Ok(spells)
+    records::load_dir(RecordKind::Spell, &spell_storage_dir(&app)?)
 }
 
 /* ==============================
 Lore storage
 ============================== */
 
-fn lore_storage_dir<R: Runtime>(app: &AppHandle<R>, world: &str) -> Result<PathBuf, String> {
+pub fn lore_storage_dir<R: Runtime>(app: &AppHandle<R>, world: &str) -> Result<PathBuf, String> {
     let dir = app
         .path()
         .app_data_dir()
@@ -886,14 +1002,13 @@ pub async fn save_lore<R: Runtime>(
     world: String,
     lore: Value,
     overwrite: Option<bool>,
-) -> Result<(), String> {
-    let id = lore["id"]
-        .as_str()
-        .ok_or_else(|| "missing id".to_string())?;
+) -> Result<(), RecordError> {
+    let lore = records::parse_record(RecordKind::Lore, lore)?;
+    let id = records::record_id(&lore)?;
     let dir = lore_storage_dir(&app, &world)?;
     let path = dir.join(format!("{}.json", &id));
     if path.exists() && !overwrite.unwrap_or(false) {
-        return Err("exists".into());
+        return Err("exists".to_string().into());
     }
     let json = serde_json::to_string_pretty(&lore).map_err(|e| e.to_string())?;
     fs::write(&path, json).map_err(|e| e.to_string())?;
@@ -903,28 +1018,14 @@ pub async fn save_lore<R: Runtime>(
 
 #[tauri::command]
 pub async fn list_lore<R: Runtime>(app: AppHandle<R>, world: String) -> Result<Vec<Value>, String> {
-    let dir = lore_storage_dir(&app, &world)?;
-    let mut lore = Vec::new();
-    if dir.exists() {
-        let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
-        for entry in entries {
-            let entry = entry.map_err(|e| e.to_string())?;
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
-                let data: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
-                lore.push(data);
-            }
-        }
-    }
-    Ok(lore)
+    records::load_dir(RecordKind::Lore, &lore_storage_dir(&app, &world)?)
 }
 
 /* ==============================
 NPC storage
 ============================== */
 
-fn npc_storage_dir<R: Runtime>(app: &AppHandle<R>, world: &str) -> Result<PathBuf, String> {
+pub fn npc_storage_dir<R: Runtime>(app: &AppHandle<R>, world: &str) -> Result<PathBuf, String> {
     let dir = app
         .path()
         .app_data_dir()
@@ -958,17 +1059,15 @@ fn create_placeholder_image(path: &Path, width: u32, height: u32) -> Result<(),
 pub async fn save_npc<R: Runtime>(
     app: AppHandle<R>,
     world: String,
-    mut npc: Value,
+    npc: Value,
     overwrite: Option<bool>,
-) -> Result<Value, String> {
-    let id = npc["id"]
-        .as_str()
-        .ok_or_else(|| "missing id".to_string())?
-        .to_string();
+) -> Result<Value, RecordError> {
+    let mut npc = records::parse_record(RecordKind::Npc, npc)?;
+    let id = records::record_id(&npc)?;
     let dir = npc_storage_dir(&app, &world)?;
     let path = dir.join(format!("{}.json", id));
     if path.exists() && !overwrite.unwrap_or(false) {
-        return Err("exists".into());
+        return Err("exists".to_string().into());
     }
     let portraits = dir.join("portraits");
     fs::create_dir_all(&portraits).map_err(|e| e.to_string())?;
@@ -1011,21 +1110,7 @@ pub async fn save_npc<R: Runtime>(
 
 #[tauri::command]
 pub async fn list_npcs<R: Runtime>(app: AppHandle<R>, world: String) -> Result<Vec<Value>, String> {
-    let dir = npc_storage_dir(&app, &world)?;
-    let mut npcs = Vec::new();
-    if dir.exists() {
-        let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
-        for entry in entries {
-            let entry = entry.map_err(|e| e.to_string())?;
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
-                let data: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
-                npcs.push(data);
-            }
-        }
-    }
-    Ok(npcs)
+    records::load_dir(RecordKind::Npc, &npc_storage_dir(&app, &world)?)
 }
 
 #[tauri::command]
@@ -1121,7 +1206,7 @@ pub struct ChatMessage {
     pub content: String,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, JsonSchema)]
 pub struct NpcEvent {
     pub who: String,
     pub action: String,
@@ -1131,18 +1216,28 @@ pub struct NpcEvent {
 }
 
 pub fn parse_npc_event(s: &str) -> Result<NpcEvent, String> {
-    serde_json::from_str::<NpcEvent>(s)
+    structured::parse_reply::<NpcEvent>(s)
         .map_err(|e| format!("invalid npc event: {e}; input: {s}"))
 }
 
-fn models_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
-    let dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|_| "app data dir".to_string())?
-        .join("ollama-models");
-    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
-    Ok(dir)
+/// NPC event from `backend` for `messages`, fitted to `model`'s context.
+pub async fn npc_event_reply(
+    backend: &dyn llm::LlmBackend,
+    model: &str,
+    messages: Vec<ChatMessage>,
+) -> Result<NpcEvent, String> {
+    let req = context::fit_request(backend, model, messages).await?;
+    structured::chat_structured::<NpcEvent>(backend, req, structured::MAX_REPAIR_ATTEMPTS)
+        .await
+        .map_err(|e| format!("invalid npc event: {e}"))
+}
+
+fn npc_event_prompt<R: Runtime>(app: &AppHandle<R>, world: Option<&str>) -> Result<String, String> {
+    let vars = BTreeMap::from([(
+        "fields",
+        "who, action, targets, effects, narration".to_string(),
+    )]);
+    prompts::render_prompt(app, "npc_event", "npc_event_chat", world, &vars)
 }
 
 #[tauri::command]
@@ -1151,6 +1246,8 @@ pub async fn start_ollama<R: Runtime>(app: AppHandle<R>) -> Result<(), String> {
     kill_port(11434);
     let client = reqwest::Client::new();
     let dir = models_dir(&app)?;
+    let base = ollama::ollama_url();
+    let model = ollama::default_model();
 
     // spawn serve
     let mut cmd = PCommand::new("ollama");
@@ -1175,7 +1272,7 @@ pub async fn start_ollama<R: Runtime>(app: AppHandle<R>) -> Result<(), String> {
     // wait for server
     for _ in 0..20 {
         if client
-            .get("http://127.0.0.1:11434/")
+            .get(format!("{base}/"))
             .timeout(std::time::Duration::from_millis(500))
             .send()
             .await
@@ -1187,7 +1284,7 @@ pub async fn start_ollama<R: Runtime>(app: AppHandle<R>) -> Result<(), String> {
     }
 
     if client
-        .get("http://127.0.0.1:11434/")
+        .get(format!("{base}/"))
         .timeout(std::time::Duration::from_millis(500))
         .send()
         .await
@@ -1197,25 +1294,14 @@ pub async fn start_ollama<R: Runtime>(app: AppHandle<R>) -> Result<(), String> {
     }
 
     // check model
-    let resp = client
-        .get("http://127.0.0.1:11434/api/tags")
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-    let json: Value = resp.json().await.map_err(|e| e.to_string())?;
-    let has = json["models"]
-        .as_array()
-        .map(|arr| {
-            arr.iter()
-                .any(|m| m["name"].as_str() == Some("gpt-oss:20b"))
-        })
-        .unwrap_or(false);
+    let has = ollama::installed_models()
+        .await?
+        .iter()
+        .any(|m| m.name == model);
 
     if !has {
         let mut pull = PCommand::new("ollama");
-        pull.arg("pull")
-            .arg("gpt-oss:20b")
-            .env("OLLAMA_MODELS", &dir);
+        pull.arg("pull").arg(&model).env("OLLAMA_MODELS", &dir);
         // Use AppHandle as emitter for logs when pulling the model
         let child = spawn_with_logging(pull, app.clone(), "ollama_log")
             .map_err(|e| format!("ollama pull failed: {e}"))?;
@@ -1243,205 +1329,215 @@ pub async fn stop_ollama() -> Result<(), String> {
     Ok(())
 }
 
+/// Retrieved passages for `query` as prompt text with `[S1]`-style markers.
+///
+/// See [`retrieval::retrieve_passages`] for the structured form.
 #[tauri::command]
 pub async fn retrieve_context<R: Runtime>(
     app: AppHandle<R>,
     query: String,
     intent: String,
+    world: Option<String>,
 ) -> Result<String, String> {
-    if intent != "npc" && intent != "lore" {
-        return Ok(String::new());
-    }
-    let mut ctx = String::new();
-    if let Ok(results) = pdf_search(app.clone(), query.clone(), None).await {
-        if !results.is_empty() {
-            ctx.push_str("Relevant documents:\n");
-            for r in &results {
-                ctx.push_str(&format!(
-                    "- {} p.{}-{}: {}\n",
-                    r.doc_id, r.page_range[0], r.page_range[1], r.text
-                ));
-            }
-        }
-    }
-    if let Ok(notes) = vault_search(app.clone(), query, None).await {
-        if !notes.is_empty() {
-            ctx.push_str("Relevant notes:\n");
-            for n in &notes {
-                ctx.push_str(&format!(
-                    "- {} p.{}-{}: {}\n",
-                    n.doc_id, n.page_range[0], n.page_range[1], n.text
-                ));
-            }
-        }
-    }
-    Ok(ctx)
-}
-
+    let passages = retrieval::retrieve(
+        &app,
+        &query,
+        &intent,
+        world.as_deref(),
+        retrieval::default_budget(),
+    )
+    .await;
+    Ok(retrieval::format_context(&passages))
+}
+
+/// Reply of the configured chat model to `messages`, trimmed to fit its context.
 #[cfg(not(test))]
-#[tauri::command]
-pub async fn general_chat<R: Runtime>(
-    _app: AppHandle<R>,
-    messages: Vec<ChatMessage>,
-) -> Result<String, String> {
-    let client = reqwest::Client::new();
-    let resp = client
-        .post("http://127.0.0.1:11434/api/chat")
-        .json(&serde_json::json!({
-          "model": "gpt-oss:20b",
-          "stream": false,
-          "messages": messages,
-        }))
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let json: Value = resp.json().await.map_err(|e| e.to_string())?;
-    let content = json["message"]["content"]
-        .as_str()
-        .or_else(|| json["content"].as_str())
-        .ok_or("no content")?;
-    Ok(content.to_string())
+async fn chat_reply(model: &str, messages: Vec<ChatMessage>) -> Result<String, String> {
+    let backend = llm::backend();
+    let req = context::fit_request(backend.as_ref(), model, messages).await?;
+    backend.chat(&req).await
 }
 
 #[cfg(test)]
+async fn chat_reply(_model: &str, _messages: Vec<ChatMessage>) -> Result<String, String> {
+    Ok("{\"shortTerm\":\"up\",\"longTerm\":\"down\"}".into())
+}
+
+/// Chat with the configured model.
+///
+/// Without `conversation_id` the call is stateless and `messages` is the full
+/// history. With one, `messages` holds only the new turn: the stored history
+/// is prepended, and the new turn plus the reply are saved to the conversation.
 #[tauri::command]
 pub async fn general_chat<R: Runtime>(
-    _app: AppHandle<R>,
-    _messages: Vec<ChatMessage>,
+    app: AppHandle<R>,
+    messages: Vec<ChatMessage>,
+    conversation_id: Option<String>,
 ) -> Result<String, String> {
-    Ok("{\"shortTerm\":\"up\",\"longTerm\":\"down\"}".into())
+    let model = ollama::model_for(ModelFeature::Chat);
+    let Some(id) = conversation_id else {
+        return chat_reply(&model, messages).await;
+    };
+    let mut conn = conversations::open(&app).await?;
+    let mut history = conversations::history(&mut conn, &id).await?;
+    history.extend(messages.iter().cloned());
+    let reply = chat_reply(&model, history).await?;
+    let mut turn = messages;
+    turn.push(ChatMessage {
+        role: "assistant".into(),
+        content: reply.clone(),
+    });
+    conversations::append(&mut conn, &id, &turn).await?;
+    conversations::set_model(&mut conn, &id, &model).await?;
+    Ok(reply)
 }
 
-#[cfg(not(test))]
 #[tauri::command]
 pub async fn npc_event_chat<R: Runtime>(
-    _app: AppHandle<R>,
+    app: AppHandle<R>,
     messages: Vec<ChatMessage>,
+    world: Option<String>,
 ) -> Result<NpcEvent, String> {
     let mut msgs = messages.clone();
     msgs.push(ChatMessage {
         role: "system".into(),
-        content: "Reply ONLY in JSON with fields who, action, targets, effects, narration.".into(),
+        content: npc_event_prompt(&app, world.as_deref())?,
     });
-    let client = reqwest::Client::new();
-    let resp = client
-        .post("http://127.0.0.1:11434/api/chat")
-        .json(&serde_json::json!({
-            "model": "gpt-oss:20b",
-            "stream": false,
-            "messages": msgs,
-        }))
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-    let json: Value = resp.json().await.map_err(|e| e.to_string())?;
-    let content = json["message"]["content"]
-        .as_str()
-        .or_else(|| json["content"].as_str())
-        .ok_or("no content")?;
-    parse_npc_event(content)
-}
-
-#[cfg(test)]
-#[tauri::command]
-pub async fn npc_event_chat<R: Runtime>(
-    _app: AppHandle<R>,
-    _messages: Vec<ChatMessage>,
-) -> Result<NpcEvent, String> {
-    parse_npc_event("{\"who\":\"npc\",\"action\":\"say\",\"targets\":[],\"effects\":[],\"narration\":\"hi\"}")
+    let model = ollama::model_for(ModelFeature::NpcEvent);
+    npc_event_reply(llm::backend().as_ref(), &model, msgs).await
 }
 
+/// Intent of `query`, one of the configured intent names.
+///
+/// See [`intent::classify_intent`] for the confidence and classifier used.
 #[tauri::command]
-pub async fn detect_intent(query: String) -> Result<String, String> {
-    let client = reqwest::Client::new();
-    let resp = client
-        .post("http://127.0.0.1:11434/api/chat")
-        .json(&serde_json::json!({
-            "model": "gpt-oss:20b",
-            "stream": false,
-            "messages": [
-                {
-                    "role": "system",
-                    "content": "You are an intent classifier. Reply ONLY in JSON with fields intent and confidence (0-1). intent must be one of npc, rules, lore, or notes. npc = questions about non-player characters, rules = game mechanics or rules, lore = world or setting information, notes = personal or miscellaneous notes.",
-                },
-                { "role": "user", "content": query },
-            ],
-        }))
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let json: Value = resp.json().await.map_err(|e| e.to_string())?;
-    let content = json["message"]["content"]
-        .as_str()
-        .or_else(|| json["content"].as_str())
-        .unwrap_or("notes");
-    Ok(extract_intent(content))
-}
-
-fn extract_intent(content: &str) -> String {
-    let trimmed = content.trim();
-    const INTENTS: [&str; 4] = ["npc", "rules", "lore", "notes"];
-    if let Ok(v) = serde_json::from_str::<Value>(trimmed) {
-        if let Some(intent) = v["intent"].as_str() {
-            let confidence = v["confidence"].as_f64().unwrap_or(0.0);
-            if confidence >= 0.5 && INTENTS.contains(&intent) {
-                return intent.to_string();
-            }
-        }
-        return "notes".to_string();
-    }
-    let lower = trimmed.to_lowercase();
-    if INTENTS.contains(&lower.as_str()) {
-        lower
-    } else {
-        "notes".to_string()
-    }
+pub async fn detect_intent<R: Runtime>(
+    app: AppHandle<R>,
+    query: String,
+    world: Option<String>,
+) -> Result<String, String> {
+    Ok(intent::classify(&app, &query, world.as_deref())
+        .await?
+        .intent)
 }
 
-#[cfg(test)]
-mod intent_tests {
-    use super::extract_intent;
+/* ==============================
+Streaming chat
+============================== */
 
-    #[test]
-    fn parses_npc() {
-        assert_eq!(
-            extract_intent("{\"intent\":\"npc\",\"confidence\":0.9}"),
-            "npc"
-        );
-    }
+static CHAT_STREAMS: OnceLock<Mutex<HashMap<String, Arc<llm::CancelToken>>>> = OnceLock::new();
 
-    #[test]
-    fn parses_rules() {
-        assert_eq!(
-            extract_intent("{\"intent\":\"rules\",\"confidence\":0.8}"),
-            "rules"
-        );
-    }
+fn chat_streams() -> &'static Mutex<HashMap<String, Arc<llm::CancelToken>>> {
+    CHAT_STREAMS.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
-    #[test]
-    fn parses_lore() {
-        assert_eq!(
-            extract_intent("{\"intent\":\"lore\",\"confidence\":0.95}"),
-            "lore"
+/// Payload of the `chat_stream` event.
+///
+/// Chunks arrive with `done == false`; a final event with an empty `delta`
+/// and `done == true` closes the stream.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatStreamEvent {
+    pub request_id: String,
+    pub delta: String,
+    pub done: bool,
+    pub cancelled: bool,
+    pub error: Option<String>,
+}
+
+async fn run_chat_stream<R: Runtime>(
+    app: &AppHandle<R>,
+    request_id: &str,
+    feature: ModelFeature,
+    messages: &[ChatMessage],
+) -> Result<llm::StreamedReply, String> {
+    // Registered before fitting, which may ask the model for a summary, so
+    // the stream can be cancelled from the start.
+    let cancel = Arc::new(llm::CancelToken::new());
+    {
+        let mut streams = chat_streams().lock().unwrap();
+        if streams.contains_key(request_id) {
+            return Err(format!("chat stream {request_id} already running"));
+        }
+        streams.insert(request_id.to_string(), cancel.clone());
+    }
+    let backend = llm::backend();
+    let model = ollama::model_for(feature);
+    let mut on_delta = |delta: &str| {
+        let _ = app.emit(
+            "chat_stream",
+            ChatStreamEvent {
+                request_id: request_id.to_string(),
+                delta: delta.to_string(),
+                done: false,
+                cancelled: false,
+                error: None,
+            },
         );
-    }
+    };
+    let res = match context::fit_request(backend.as_ref(), &model, messages.to_vec()).await {
+        Ok(_) if cancel.is_cancelled() => Ok(llm::StreamedReply {
+            content: String::new(),
+            cancelled: true,
+        }),
+        Ok(req) => backend.chat_stream(&req, &cancel, &mut on_delta).await,
+        Err(e) => Err(e),
+    };
+    chat_streams().lock().unwrap().remove(request_id);
+    let _ = app.emit(
+        "chat_stream",
+        ChatStreamEvent {
+            request_id: request_id.to_string(),
+            delta: String::new(),
+            done: true,
+            cancelled: res.as_ref().map(|r| r.cancelled).unwrap_or(false),
+            error: res.as_ref().err().cloned(),
+        },
+    );
+    res
+}
+
+/// Streaming variant of [`general_chat`]. Token chunks are emitted as
+/// `chat_stream` events tagged with `request_id`; the assembled reply is
+/// returned when the stream ends (partial if it was cancelled).
+#[tauri::command]
+pub async fn general_chat_stream<R: Runtime>(
+    app: AppHandle<R>,
+    request_id: String,
+    messages: Vec<ChatMessage>,
+) -> Result<String, String> {
+    let reply = run_chat_stream(&app, &request_id, ModelFeature::Chat, &messages).await?;
+    Ok(reply.content)
+}
 
-    #[test]
-    fn parses_notes() {
-        assert_eq!(
-            extract_intent("{\"intent\":\"notes\",\"confidence\":0.99}"),
-            "notes"
-        );
+/// Streaming variant of [`npc_event_chat`]; the JSON is parsed once complete.
+#[tauri::command]
+pub async fn npc_event_chat_stream<R: Runtime>(
+    app: AppHandle<R>,
+    request_id: String,
+    messages: Vec<ChatMessage>,
+    world: Option<String>,
+) -> Result<NpcEvent, String> {
+    let mut msgs = messages;
+    msgs.push(ChatMessage {
+        role: "system".into(),
+        content: npc_event_prompt(&app, world.as_deref())?,
+    });
+    let reply = run_chat_stream(&app, &request_id, ModelFeature::NpcEvent, &msgs).await?;
+    if reply.cancelled {
+        return Err("cancelled".into());
     }
+    parse_npc_event(&reply.content)
+}
 
-    #[test]
-    fn defaults_to_notes_on_low_confidence() {
-        assert_eq!(
-            extract_intent("{\"intent\":\"npc\",\"confidence\":0.2}"),
-            "notes"
-        );
+/// Stop a running chat stream. Returns `false` if no such stream exists.
+#[tauri::command]
+pub async fn cancel_chat_stream(request_id: String) -> Result<bool, String> {
+    match chat_streams().lock().unwrap().get(&request_id) {
+        Some(cancel) => {
+            cancel.cancel();
+            Ok(true)
+        }
+        None => Ok(false),
     }
 }
 
@@ -0,0 +1,179 @@
+use crate::commands::ChatMessage;
+use crate::llm::{ChatRequest, LlmBackend};
+use crate::python_helpers::get_config;
+
+/// Context window assumed for models without a configured budget. Matches
+/// Ollama's default `num_ctx`.
+pub const DEFAULT_CONTEXT_TOKENS: usize = 4096;
+
+/// Tokens added per message for role markers and separators.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Rough token count of `text`: about four characters per token, which is
+/// close enough for English prose with common tokenizers.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+pub fn estimate_message_tokens(message: &ChatMessage) -> usize {
+    estimate_tokens(&message.content) + MESSAGE_OVERHEAD_TOKENS
+}
+
+pub fn estimate_messages_tokens(messages: &[ChatMessage]) -> usize {
+    messages.iter().map(estimate_message_tokens).sum()
+}
+
+/// Configured budget for `model`, looked up by full name and then by name
+/// without the tag (`llama3` for `llama3:8b`).
+fn configured_budget(model: &str) -> Option<usize> {
+    let budgets = get_config().context_budgets?;
+    budgets
+        .get(model)
+        .or_else(|| budgets.get(model.split(':').next().unwrap_or(model)))
+        .copied()
+        .filter(|n| *n > 0)
+}
+
+/// Context window in tokens for `model`.
+pub fn context_budget(model: &str) -> usize {
+    configured_budget(model).unwrap_or(DEFAULT_CONTEXT_TOKENS)
+}
+
+/// Tokens kept free for the model's reply.
+fn reply_reserve(budget: usize) -> usize {
+    (budget / 4).min(1024)
+}
+
+/// Tokens left in `model`'s context once `messages` and the room kept for
+/// the reply are taken out.
+pub fn room_left(model: &str, messages: &[ChatMessage]) -> usize {
+    let budget = context_budget(model);
+    budget.saturating_sub(reply_reserve(budget) + estimate_messages_tokens(messages))
+}
+
+/// Cut `text` to roughly `max_tokens`, dropping whole lines from the end
+/// where possible.
+pub fn trim_to_tokens(text: &str, max_tokens: usize) -> String {
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    for line in text.lines() {
+        let candidate = if out.is_empty() {
+            line.to_string()
+        } else {
+            format!("{out}\n{line}")
+        };
+        if estimate_tokens(&candidate) > max_tokens {
+            break;
+        }
+        out = candidate;
+    }
+    if out.is_empty() {
+        // A single line longer than the budget.
+        out = text.chars().take(max_tokens * 4).collect();
+    }
+    out.push('…');
+    out
+}
+
+fn transcript(messages: &[ChatMessage]) -> String {
+    messages
+        .iter()
+        .map(|m| format!("{}: {}", m.role, m.content))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn summarize(
+    backend: &dyn LlmBackend,
+    model: &str,
+    turns: &[ChatMessage],
+    budget: usize,
+    summary_tokens: usize,
+) -> Result<String, String> {
+    let text = trim_to_tokens(
+        &transcript(turns),
+        budget.saturating_sub(summary_tokens + 256),
+    );
+    let req = ChatRequest::new(
+        model,
+        vec![
+            ChatMessage {
+                role: "system".into(),
+                content: format!(
+                    "Summarize the conversation below in at most {} words. Keep names, decisions, open questions and facts established about the world.",
+                    summary_tokens * 3 / 4
+                ),
+            },
+            ChatMessage {
+                role: "user".into(),
+                content: text,
+            },
+        ],
+    );
+    let summary = backend.chat(&req).await?;
+    Ok(trim_to_tokens(summary.trim(), summary_tokens))
+}
+
+/// Make `messages` fit into `budget` tokens, leaving room for the reply.
+///
+/// Leading system messages and the most recent turns are kept as they are.
+/// Older turns are replaced by an LLM-written summary, or dropped if the
+/// summary can't be produced.
+pub async fn fit_messages(
+    backend: &dyn LlmBackend,
+    model: &str,
+    messages: Vec<ChatMessage>,
+    budget: usize,
+) -> Result<Vec<ChatMessage>, String> {
+    let limit = budget.saturating_sub(reply_reserve(budget));
+    if estimate_messages_tokens(&messages) <= limit {
+        return Ok(messages);
+    }
+    let split = messages.iter().take_while(|m| m.role == "system").count();
+    let (system, turns) = messages.split_at(split);
+    let summary_tokens = (limit / 8).max(64);
+    let mut available = limit.saturating_sub(estimate_messages_tokens(system) + summary_tokens);
+    // Walk back from the newest turn; the last one is always kept.
+    let mut keep_from = turns.len();
+    while keep_from > 0 {
+        let tokens = estimate_message_tokens(&turns[keep_from - 1]);
+        if tokens > available && keep_from < turns.len() {
+            break;
+        }
+        available = available.saturating_sub(tokens);
+        keep_from -= 1;
+    }
+    // Start the kept part on a user turn rather than mid-exchange.
+    while keep_from + 1 < turns.len() && turns[keep_from].role != "user" {
+        keep_from += 1;
+    }
+    let (older, recent) = turns.split_at(keep_from);
+    let mut fitted = system.to_vec();
+    if !older.is_empty() {
+        match summarize(backend, model, older, budget, summary_tokens).await {
+            Ok(summary) if !summary.is_empty() => fitted.push(ChatMessage {
+                role: "system".into(),
+                content: format!("Summary of the earlier conversation:\n{summary}"),
+            }),
+            Ok(_) => {}
+            Err(e) => log::warn!("could not summarize older turns, dropping them: {e}"),
+        }
+    }
+    fitted.extend_from_slice(recent);
+    Ok(fitted)
+}
+
+/// Build a request for `model` with `messages` fitted to its context budget.
+pub async fn fit_request(
+    backend: &dyn LlmBackend,
+    model: &str,
+    messages: Vec<ChatMessage>,
+) -> Result<ChatRequest, String> {
+    let budget = context_budget(model);
+    let messages = fit_messages(backend, model, messages, budget).await?;
+    let mut req = ChatRequest::new(model, messages);
+    req.context_tokens = configured_budget(model);
+    Ok(req)
+}
@@ -0,0 +1,447 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteRow},
+    Connection, Executor, Row, SqliteConnection,
+};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::commands::ChatMessage;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS conversations (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL DEFAULT '',
+    model TEXT,
+    world TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS conversation_messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS conversation_messages_by_conversation
+    ON conversation_messages(conversation_id, id);
+CREATE VIRTUAL TABLE IF NOT EXISTS conversation_messages_fts USING fts5(
+    content,
+    content = 'conversation_messages',
+    content_rowid = 'id'
+);
+CREATE TRIGGER IF NOT EXISTS conversation_messages_ai AFTER INSERT ON conversation_messages BEGIN
+    INSERT INTO conversation_messages_fts(rowid, content) VALUES (new.id, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS conversation_messages_ad AFTER DELETE ON conversation_messages BEGIN
+    INSERT INTO conversation_messages_fts(conversation_messages_fts, rowid, content)
+        VALUES ('delete', old.id, old.content);
+END;
+"#;
+
+/// Longest automatic title taken from the first user message.
+const AUTO_TITLE_CHARS: usize = 40;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: String,
+    pub model: Option<String>,
+    pub world: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub id: i64,
+    pub role: String,
+    pub content: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    #[serde(flatten)]
+    pub summary: ConversationSummary,
+    pub messages: Vec<StoredMessage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSearchHit {
+    pub conversation: ConversationSummary,
+    /// Matching message, or `None` when only the title matched.
+    pub message_id: Option<i64>,
+    pub snippet: String,
+}
+
+fn summary_from_row(row: &SqliteRow) -> ConversationSummary {
+    ConversationSummary {
+        id: row.get("id"),
+        title: row.get("title"),
+        model: row.get("model"),
+        world: row.get("world"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+fn new_id() -> String {
+    let rand: u32 = thread_rng().gen();
+    format!("{:x}{:08x}", Utc::now().timestamp_millis(), rand)
+}
+
+/// Quote each word so user input can't be parsed as FTS5 syntax.
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|t| format!("\"{}\"", t.replace('"', "")))
+        .filter(|t| t != "\"\"")
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Open (and create if needed) the conversation store at `path`.
+pub async fn open_store(path: &Path) -> Result<SqliteConnection, String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let opts = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .foreign_keys(true);
+    let mut conn = SqliteConnection::connect_with(&opts)
+        .await
+        .map_err(|e| e.to_string())?;
+    conn.execute(SCHEMA).await.map_err(|e| e.to_string())?;
+    Ok(conn)
+}
+
+pub async fn create(
+    conn: &mut SqliteConnection,
+    title: Option<String>,
+    model: Option<String>,
+    world: Option<String>,
+) -> Result<ConversationSummary, String> {
+    let now = Utc::now().to_rfc3339();
+    let summary = ConversationSummary {
+        id: new_id(),
+        title: title.unwrap_or_default().trim().to_string(),
+        model,
+        world,
+        created_at: now.clone(),
+        updated_at: now,
+    };
+    sqlx::query(
+        "INSERT INTO conversations (id, title, model, world, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&summary.id)
+    .bind(&summary.title)
+    .bind(&summary.model)
+    .bind(&summary.world)
+    .bind(&summary.created_at)
+    .bind(&summary.updated_at)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+pub async fn summary(conn: &mut SqliteConnection, id: &str) -> Result<ConversationSummary, String> {
+    sqlx::query("SELECT * FROM conversations WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .map(|row| summary_from_row(&row))
+        .ok_or_else(|| format!("conversation not found: {id}"))
+}
+
+pub async fn get(conn: &mut SqliteConnection, id: &str) -> Result<Conversation, String> {
+    let summary = summary(conn, id).await?;
+    let rows = sqlx::query(
+        "SELECT id, role, content, created_at FROM conversation_messages WHERE conversation_id = ? ORDER BY id",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    let messages = rows
+        .iter()
+        .map(|row| StoredMessage {
+            id: row.get("id"),
+            role: row.get("role"),
+            content: row.get("content"),
+            created_at: row.get("created_at"),
+        })
+        .collect();
+    Ok(Conversation { summary, messages })
+}
+
+/// History of `id` in the shape the LLM backends expect.
+pub async fn history(conn: &mut SqliteConnection, id: &str) -> Result<Vec<ChatMessage>, String> {
+    Ok(get(conn, id)
+        .await?
+        .messages
+        .into_iter()
+        .map(|m| ChatMessage {
+            role: m.role,
+            content: m.content,
+        })
+        .collect())
+}
+
+/// Append `messages` and bump `updated_at`. An untitled conversation takes
+/// its title from the first user message.
+pub async fn append(
+    conn: &mut SqliteConnection,
+    id: &str,
+    messages: &[ChatMessage],
+) -> Result<ConversationSummary, String> {
+    let mut summary = summary(conn, id).await?;
+    let now = Utc::now().to_rfc3339();
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+    for m in messages {
+        sqlx::query(
+            "INSERT INTO conversation_messages (conversation_id, role, content, created_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(&m.role)
+        .bind(&m.content)
+        .bind(&now)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+    if summary.title.is_empty() {
+        if let Some(first) = messages.iter().find(|m| m.role == "user") {
+            summary.title = first
+                .content
+                .trim()
+                .chars()
+                .take(AUTO_TITLE_CHARS)
+                .collect();
+        }
+    }
+    summary.updated_at = now;
+    sqlx::query("UPDATE conversations SET title = ?, updated_at = ? WHERE id = ?")
+        .bind(&summary.title)
+        .bind(&summary.updated_at)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+pub async fn set_model(conn: &mut SqliteConnection, id: &str, model: &str) -> Result<(), String> {
+    sqlx::query("UPDATE conversations SET model = ? WHERE id = ?")
+        .bind(model)
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// All conversations, most recently updated first, optionally for one world.
+pub async fn list(
+    conn: &mut SqliteConnection,
+    world: Option<&str>,
+) -> Result<Vec<ConversationSummary>, String> {
+    let rows = sqlx::query(
+        "SELECT * FROM conversations WHERE (?1 IS NULL OR world = ?1) ORDER BY updated_at DESC, id DESC",
+    )
+    .bind(world.map(str::to_string))
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(rows.iter().map(summary_from_row).collect())
+}
+
+pub async fn rename(conn: &mut SqliteConnection, id: &str, title: &str) -> Result<(), String> {
+    let res = sqlx::query("UPDATE conversations SET title = ?, updated_at = ? WHERE id = ?")
+        .bind(title.trim())
+        .bind(Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.rows_affected() == 0 {
+        return Err(format!("conversation not found: {id}"));
+    }
+    Ok(())
+}
+
+pub async fn delete(conn: &mut SqliteConnection, id: &str) -> Result<(), String> {
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM conversation_messages WHERE conversation_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let res = sqlx::query("DELETE FROM conversations WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.rows_affected() == 0 {
+        return Err(format!("conversation not found: {id}"));
+    }
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+/// `text` with the `LIKE` wildcards escaped by `\`, so it matches literally.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Full-text search over message content, plus substring matches on titles.
+pub async fn search(
+    conn: &mut SqliteConnection,
+    query: &str,
+    world: Option<&str>,
+    limit: usize,
+) -> Result<Vec<ConversationSearchHit>, String> {
+    let mut hits = Vec::new();
+    let q = query.trim();
+    if q.is_empty() {
+        return Ok(hits);
+    }
+    let title_rows = sqlx::query(
+        "SELECT * FROM conversations WHERE title LIKE '%' || ?1 || '%' ESCAPE '\\' AND (?2 IS NULL OR world = ?2) ORDER BY updated_at DESC LIMIT ?3",
+    )
+    .bind(escape_like(q))
+    .bind(world.map(str::to_string))
+    .bind(limit as i64)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    for row in &title_rows {
+        let conversation = summary_from_row(row);
+        hits.push(ConversationSearchHit {
+            snippet: conversation.title.clone(),
+            conversation,
+            message_id: None,
+        });
+    }
+    let fts = fts_query(q);
+    if fts.is_empty() {
+        return Ok(hits);
+    }
+    let rows = sqlx::query(
+        r#"SELECT c.*, m.id AS message_id,
+                  snippet(conversation_messages_fts, 0, '[', ']', '…', 12) AS snippet
+           FROM conversation_messages_fts
+           JOIN conversation_messages m ON m.id = conversation_messages_fts.rowid
+           JOIN conversations c ON c.id = m.conversation_id
+           WHERE conversation_messages_fts MATCH ?1 AND (?2 IS NULL OR c.world = ?2)
+           ORDER BY bm25(conversation_messages_fts)
+           LIMIT ?3"#,
+    )
+    .bind(&fts)
+    .bind(world.map(str::to_string))
+    .bind(limit as i64)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    for row in &rows {
+        hits.push(ConversationSearchHit {
+            conversation: summary_from_row(row),
+            message_id: Some(row.get("message_id")),
+            snippet: row.get("snippet"),
+        });
+    }
+    hits.truncate(limit);
+    Ok(hits)
+}
+
+pub fn store_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    Ok(app
+        .path()
+        .app_data_dir()
+        .map_err(|_| "app data dir".to_string())?
+        .join("conversations.sqlite"))
+}
+
+pub async fn open<R: Runtime>(app: &AppHandle<R>) -> Result<SqliteConnection, String> {
+    open_store(&store_path(app)?).await
+}
+
+#[tauri::command]
+pub async fn create_conversation<R: Runtime>(
+    app: AppHandle<R>,
+    title: Option<String>,
+    model: Option<String>,
+    world: Option<String>,
+) -> Result<ConversationSummary, String> {
+    let mut conn = open(&app).await?;
+    create(&mut conn, title, model, world).await
+}
+
+#[tauri::command]
+pub async fn append_conversation<R: Runtime>(
+    app: AppHandle<R>,
+    id: String,
+    messages: Vec<ChatMessage>,
+) -> Result<ConversationSummary, String> {
+    let mut conn = open(&app).await?;
+    append(&mut conn, &id, &messages).await
+}
+
+#[tauri::command]
+pub async fn get_conversation<R: Runtime>(
+    app: AppHandle<R>,
+    id: String,
+) -> Result<Conversation, String> {
+    let mut conn = open(&app).await?;
+    get(&mut conn, &id).await
+}
+
+#[tauri::command]
+pub async fn list_conversations<R: Runtime>(
+    app: AppHandle<R>,
+    world: Option<String>,
+) -> Result<Vec<ConversationSummary>, String> {
+    let mut conn = open(&app).await?;
+    list(&mut conn, world.as_deref()).await
+}
+
+#[tauri::command]
+pub async fn rename_conversation<R: Runtime>(
+    app: AppHandle<R>,
+    id: String,
+    title: String,
+) -> Result<(), String> {
+    let mut conn = open(&app).await?;
+    rename(&mut conn, &id, &title).await
+}
+
+#[tauri::command]
+pub async fn delete_conversation<R: Runtime>(app: AppHandle<R>, id: String) -> Result<(), String> {
+    let mut conn = open(&app).await?;
+    delete(&mut conn, &id).await
+}
+
+#[tauri::command]
+pub async fn search_conversations<R: Runtime>(
+    app: AppHandle<R>,
+    query: String,
+    world: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<ConversationSearchHit>, String> {
+    let mut conn = open(&app).await?;
+    search(&mut conn, &query, world.as_deref(), limit.unwrap_or(20)).await
+}
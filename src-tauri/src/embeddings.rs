@@ -0,0 +1,225 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Executor, Row, SqliteConnection};
+
+use crate::commands::hash_embed;
+use crate::python_helpers::get_config;
+
+/// Embedding model used when nothing is configured.
+pub const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
+
+/// Name under which the built-in hashed bag-of-words embedding is recorded.
+pub const HASH_MODEL: &str = "hash";
+
+/// Chunks embedded per batch before the progress is stored.
+const EMBED_BATCH: usize = 32;
+
+/// Model vectors live beside the Python-managed `embeddings` table, whose
+/// `embedding` column keeps the hashed vectors used as the offline fallback.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS index_meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chunk_vectors (
+    chunk_id TEXT PRIMARY KEY,
+    model TEXT NOT NULL,
+    embedding BLOB NOT NULL
+);
+";
+
+/// The model and dimension an index's vectors were built with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IndexModel {
+    pub model: String,
+    pub dimension: usize,
+}
+
+/// The configured embedding model. `hash` selects the offline embedding.
+pub fn embedding_model() -> String {
+    get_config()
+        .embedding_model
+        .filter(|m| !m.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string())
+}
+
+pub fn encode_vector(v: &[f32]) -> Vec<u8> {
+    v.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub fn decode_vector(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+        .collect()
+}
+
+fn normalize(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for x in &mut v {
+            *x /= norm;
+        }
+    }
+    v
+}
+
+/// Embed `text` with `model` through Ollama's `/api/embeddings`, scaled to
+/// unit length so dot products are cosine similarities.
+pub async fn ollama_embed(base_url: &str, model: &str, text: &str) -> Result<Vec<f32>, String> {
+    let resp = reqwest::Client::new()
+        .post(format!("{base_url}/api/embeddings"))
+        .json(&json!({ "model": model, "prompt": text }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        return Err(format!("Ollama returned {status}: {body}"));
+    }
+    let json: Value = resp.json().await.map_err(|e| e.to_string())?;
+    let vector: Vec<f32> =
+        serde_json::from_value(json["embedding"].clone()).map_err(|e| e.to_string())?;
+    if vector.is_empty() {
+        return Err(format!("{model} returned an empty embedding"));
+    }
+    Ok(normalize(vector))
+}
+
+/// Embed `text` with `model`, using [`hash_embed`] for [`HASH_MODEL`].
+pub async fn embed(base_url: &str, model: &str, text: &str) -> Result<Vec<f32>, String> {
+    if model == HASH_MODEL {
+        return Ok(hash_embed(text));
+    }
+    ollama_embed(base_url, model, text).await
+}
+
+pub async fn set_meta(conn: &mut SqliteConnection, key: &str, value: &str) -> Result<(), String> {
+    sqlx::query("INSERT OR REPLACE INTO index_meta (key, value) VALUES (?, ?)")
+        .bind(key)
+        .bind(value)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub async fn get_meta(conn: &mut SqliteConnection, key: &str) -> Result<Option<String>, String> {
+    let row = sqlx::query("SELECT value FROM index_meta WHERE key = ?")
+        .bind(key)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(row.map(|r| r.get("value")))
+}
+
+/// Create the metadata and model vector tables if they are missing.
+pub async fn ensure_schema(conn: &mut SqliteConnection) -> Result<(), String> {
+    conn.execute(SCHEMA).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// The model recorded for the index's vectors, if any were built.
+pub async fn index_model(conn: &mut SqliteConnection) -> Result<Option<IndexModel>, String> {
+    ensure_schema(conn).await?;
+    let model = get_meta(conn, "embedding_model").await?;
+    let dimension = get_meta(conn, "embedding_dim").await?;
+    Ok(match (model, dimension) {
+        (Some(model), Some(dim)) => Some(IndexModel {
+            model,
+            dimension: dim
+                .parse()
+                .map_err(|_| format!("bad embedding_dim: {dim}"))?,
+        }),
+        _ => None,
+    })
+}
+
+/// Bring the index's vectors up to date with `model`.
+///
+/// If the index was built with a different model, or holds vectors of a
+/// different model or dimension, every chunk is embedded again. Otherwise
+/// only chunks without a vector are embedded, and vectors of removed chunks
+/// are dropped. Returns the number of chunks embedded.
+pub async fn sync_vectors(
+    conn: &mut SqliteConnection,
+    base_url: &str,
+    model: &str,
+) -> Result<usize, String> {
+    let current = index_model(conn).await?;
+    let mixed: i64 = sqlx::query("SELECT COUNT(*) AS n FROM chunk_vectors WHERE model != ?")
+        .bind(model)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .get("n");
+    let mut dimension = match &current {
+        Some(m) if m.model == model && mixed == 0 => Some(m.dimension),
+        _ => {
+            if let Some(m) = &current {
+                log::info!(
+                    "embedding model changed from {} to {model}, reindexing",
+                    m.model
+                );
+            }
+            conn.execute("DELETE FROM chunk_vectors")
+                .await
+                .map_err(|e| e.to_string())?;
+            None
+        }
+    };
+    conn.execute(
+        "DELETE FROM chunk_vectors WHERE chunk_id NOT IN (SELECT chunk_id FROM embeddings)",
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let pending: Vec<(String, String)> = sqlx::query(
+        "SELECT e.chunk_id, e.text FROM embeddings e
+         LEFT JOIN chunk_vectors v ON v.chunk_id = e.chunk_id
+         WHERE v.chunk_id IS NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?
+    .into_iter()
+    .map(|r| (r.get("chunk_id"), r.get("text")))
+    .collect();
+
+    for batch in pending.chunks(EMBED_BATCH) {
+        let mut vectors = Vec::with_capacity(batch.len());
+        for (chunk_id, text) in batch {
+            let v = embed(base_url, model, text).await?;
+            match dimension {
+                Some(d) if d != v.len() => {
+                    return Err(format!(
+                        "{model} returned {} dimensions, index has {d}",
+                        v.len()
+                    ))
+                }
+                _ => dimension = Some(v.len()),
+            }
+            vectors.push((chunk_id, v));
+        }
+        let mut tx = sqlx::Connection::begin(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+        for (chunk_id, v) in vectors {
+            sqlx::query(
+                "INSERT OR REPLACE INTO chunk_vectors (chunk_id, model, embedding) VALUES (?, ?, ?)",
+            )
+            .bind(chunk_id)
+            .bind(model)
+            .bind(encode_vector(&v))
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().await.map_err(|e| e.to_string())?;
+        if let Some(d) = dimension {
+            set_meta(conn, "embedding_model", model).await?;
+            set_meta(conn, "embedding_dim", &d.to_string()).await?;
+        }
+    }
+    Ok(pending.len())
+}
@@ -0,0 +1,267 @@
+//! Format of the shared index at `Index/index.sqlite`.
+//!
+//! The format version and vector byte order are kept in `index_meta` beside
+//! the embedding model and dimension. Indexing runs the migrations an index
+//! is missing; searches only check the format, so reading never rewrites
+//! the index. An index that can't be read correctly is refused with an error
+//! saying why instead of returning wrong scores.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, Executor, Row, SqliteConnection};
+
+use crate::commands::EMBED_DIM;
+use crate::embeddings::{self, get_meta, set_meta};
+
+/// Format written by this build.
+///
+/// 1. Unversioned indexes: vectors in the writer's native byte order.
+/// 2. Every vector stored little-endian, with `byte_order` recorded.
+/// 3. Wiki links and inline tags of notes in `note_links` and `note_tags`.
+/// 4. Chunk text searchable through the `chunks_fts` table.
+/// 5. Duplicate and edition links of PDFs in `pdf_documents`.
+pub const FORMAT_VERSION: u32 = 5;
+
+/// Byte order of every stored vector from format 2 on.
+pub const BYTE_ORDER: &str = "little";
+
+/// FTS5 copy of the chunk text, keyed by the `embeddings` rowid and kept in
+/// sync by triggers so chunks written by `pdf_tools.py` are covered too.
+/// `OR REPLACE` covers `INSERT OR REPLACE` into `embeddings`, which doesn't
+/// fire the delete trigger for the row it replaces.
+const FTS_SCHEMA: &str = "
+CREATE VIRTUAL TABLE IF NOT EXISTS chunks_fts USING fts5(text);
+CREATE TRIGGER IF NOT EXISTS chunks_fts_insert AFTER INSERT ON embeddings
+BEGIN INSERT OR REPLACE INTO chunks_fts (rowid, text) VALUES (new.rowid, new.text); END;
+CREATE TRIGGER IF NOT EXISTS chunks_fts_update AFTER UPDATE ON embeddings
+BEGIN
+    DELETE FROM chunks_fts WHERE rowid = old.rowid;
+    INSERT OR REPLACE INTO chunks_fts (rowid, text) VALUES (new.rowid, new.text);
+END;
+CREATE TRIGGER IF NOT EXISTS chunks_fts_delete AFTER DELETE ON embeddings
+BEGIN DELETE FROM chunks_fts WHERE rowid = old.rowid; END;
+";
+
+/// What an index records about its own format.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IndexInfo {
+    pub format_version: u32,
+    pub byte_order: String,
+    /// Model of the vectors in `chunk_vectors`, once any were built.
+    pub embedding_model: Option<String>,
+    pub dimension: Option<usize>,
+}
+
+/// The index's recorded format. An index without one predates versioning.
+pub async fn index_info(conn: &mut SqliteConnection) -> Result<IndexInfo, String> {
+    embeddings::ensure_schema(conn).await?;
+    let format_version = match get_meta(conn, "format_version").await? {
+        Some(v) => v
+            .parse()
+            .map_err(|_| format!("index has an unreadable format_version: {v}"))?,
+        None => 1,
+    };
+    let model = embeddings::index_model(conn).await?;
+    Ok(IndexInfo {
+        format_version,
+        byte_order: get_meta(conn, "byte_order")
+            .await?
+            .unwrap_or_else(|| native_byte_order().into()),
+        embedding_model: model.as_ref().map(|m| m.model.clone()),
+        dimension: model.map(|m| m.dimension),
+    })
+}
+
+fn native_byte_order() -> &'static str {
+    if cfg!(target_endian = "big") {
+        "big"
+    } else {
+        "little"
+    }
+}
+
+fn refuse_newer(info: &IndexInfo) -> Result<(), String> {
+    if info.format_version > FORMAT_VERSION {
+        return Err(format!(
+            "the index uses format {}, newer than the {FORMAT_VERSION} this version reads; \
+             update the app or rebuild the index",
+            info.format_version
+        ));
+    }
+    Ok(())
+}
+
+/// Upgrade the index to [`FORMAT_VERSION`], one version at a time, and check
+/// that this build can read it.
+pub async fn migrate(conn: &mut SqliteConnection) -> Result<IndexInfo, String> {
+    let mut info = index_info(conn).await?;
+    refuse_newer(&info)?;
+    while info.format_version < FORMAT_VERSION {
+        let next = info.format_version + 1;
+        let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+        match next {
+            2 => to_little_endian(&mut tx, &info.byte_order).await?,
+            3 => reread_notes(&mut tx).await?,
+            4 => create_fts(&mut tx).await?,
+            5 => add_pdf_duplicate_columns(&mut tx).await?,
+            _ => unreachable!("no migration to format {next}"),
+        }
+        set_meta(&mut tx, "format_version", &next.to_string()).await?;
+        tx.commit().await.map_err(|e| e.to_string())?;
+        info = index_info(conn).await?;
+    }
+    check(conn, &info).await?;
+    Ok(info)
+}
+
+/// Check, without changing the index, that it is at [`FORMAT_VERSION`] and
+/// can be read. An older index is left for the next indexing run to
+/// [`migrate`].
+pub async fn check_current(conn: &mut SqliteConnection) -> Result<IndexInfo, String> {
+    let versioned = sqlx::query("SELECT 1 FROM sqlite_master WHERE name = 'index_meta'")
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .is_some();
+    let info = if versioned {
+        index_info(conn).await?
+    } else {
+        IndexInfo {
+            format_version: 1,
+            byte_order: native_byte_order().into(),
+            embedding_model: None,
+            dimension: None,
+        }
+    };
+    refuse_newer(&info)?;
+    if info.format_version < FORMAT_VERSION {
+        return Err(format!(
+            "index needs reindexing: it uses format {}, this version reads {FORMAT_VERSION}",
+            info.format_version
+        ));
+    }
+    check(conn, &info).await?;
+    Ok(info)
+}
+
+/// Format 2: rewrite the hashed vectors written in native byte order.
+async fn to_little_endian(conn: &mut SqliteConnection, from: &str) -> Result<(), String> {
+    match from {
+        "little" => {}
+        "big" => {
+            let rows = sqlx::query("SELECT rowid, embedding FROM embeddings")
+                .fetch_all(&mut *conn)
+                .await
+                .map_err(|e| e.to_string())?;
+            for row in rows {
+                let Some(blob) = row.get::<Option<Vec<u8>>, _>("embedding") else {
+                    continue;
+                };
+                let swapped: Vec<u8> = blob
+                    .chunks_exact(4)
+                    .flat_map(|c| [c[3], c[2], c[1], c[0]])
+                    .collect();
+                sqlx::query("UPDATE embeddings SET embedding = ? WHERE rowid = ?")
+                    .bind(swapped)
+                    .bind(row.get::<i64, _>("rowid"))
+                    .execute(&mut *conn)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        other => return Err(format!("index has an unknown byte order: {other}")),
+    }
+    set_meta(conn, "byte_order", BYTE_ORDER).await
+}
+
+/// Format 3: have the next indexing run read every note again, so links and
+/// inline tags are picked up from notes indexed before.
+///
+/// That run re-chunks the whole vault, so every note is embedded again: a
+/// one-off full re-embed. Only code that writes the index migrates it
+/// (through [`crate::vault_index::ensure_schema`]); searches stop at
+/// [`check_current`], as do the link graph queries, and never get here.
+async fn reread_notes(conn: &mut SqliteConnection) -> Result<(), String> {
+    sqlx::query("UPDATE vault_files SET mtime = 0, hash = ''")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Format 4: create the FTS table and its triggers, and fill it with the
+/// chunks already indexed.
+async fn create_fts(conn: &mut SqliteConnection) -> Result<(), String> {
+    conn.execute(FTS_SCHEMA).await.map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM chunks_fts; INSERT INTO chunks_fts (rowid, text) SELECT rowid, text FROM embeddings;",
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Format 5: add the columns `pdf_library` uses to track duplicates and
+/// editions to a `pdf_documents` table created before them. A missing table
+/// is created later with every column.
+async fn add_pdf_duplicate_columns(conn: &mut SqliteConnection) -> Result<(), String> {
+    let columns: Vec<String> = sqlx::query("SELECT name FROM pragma_table_info('pdf_documents')")
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|r| r.get("name"))
+        .collect();
+    if columns.is_empty() {
+        return Ok(());
+    }
+    for column in ["content_hash", "edition_of", "duplicate_of"] {
+        if !columns.iter().any(|c| c == column) {
+            conn.execute(format!("ALTER TABLE pdf_documents ADD COLUMN {column} TEXT").as_str())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Refuse an index whose vectors don't match what it records.
+async fn check(conn: &mut SqliteConnection, info: &IndexInfo) -> Result<(), String> {
+    if info.byte_order != BYTE_ORDER {
+        return Err(format!(
+            "index vectors are stored {}-endian, expected {BYTE_ORDER}-endian; rebuild the index",
+            info.byte_order
+        ));
+    }
+    // Vectors are written whole, so one of each kind stands for the rest;
+    // empty ones are chunks without a vector.
+    let hashed = stored_dimension(conn, "embeddings").await?;
+    if let Some(dim) = hashed.filter(|d| *d != EMBED_DIM) {
+        return Err(format!(
+            "index has {dim}-dimensional hashed vectors, expected {EMBED_DIM}; rebuild the index"
+        ));
+    }
+    let model = stored_dimension(conn, "chunk_vectors").await?;
+    if let (Some(dim), Some(recorded)) = (model, info.dimension) {
+        if dim != recorded {
+            return Err(format!(
+                "index has {dim}-dimensional {} vectors but records {recorded}; \
+                 rebuild the index",
+                info.embedding_model.as_deref().unwrap_or("model")
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn stored_dimension(
+    conn: &mut SqliteConnection,
+    table: &str,
+) -> Result<Option<usize>, String> {
+    let row = sqlx::query(&format!(
+        "SELECT length(embedding) AS n FROM {table} WHERE length(embedding) > 0 LIMIT 1"
+    ))
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(row.map(|r| r.get::<i64, _>("n") as usize / 4))
+}
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Runtime};
+
+use crate::commands::{hash_embed, ChatMessage};
+use crate::llm::{self, ChatRequest};
+use crate::ollama::{self, ModelFeature};
+use crate::prompts;
+use crate::python_helpers::{get_config, save_config};
+use crate::structured::strip_code_fences;
+
+/// Few-shot examples sent to the model per intent.
+const MAX_EXAMPLES_PER_INTENT: usize = 3;
+
+/// Lowest similarity at which the embedding fallback picks an intent.
+const MIN_EMBEDDING_SCORE: f32 = 0.2;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IntentDef {
+    pub name: String,
+    pub description: String,
+    /// Words or phrases that point at this intent for the offline classifier.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Example questions, used as few-shot examples and by the offline classifier.
+    #[serde(default)]
+    pub examples: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct IntentSettings {
+    pub intents: Vec<IntentDef>,
+    /// LLM answers below this confidence go to the offline classifier.
+    pub confidence_threshold: f64,
+    /// Intent used when nothing matches.
+    pub fallback_intent: String,
+}
+
+fn intent(name: &str, description: &str, keywords: &[&str], examples: &[&str]) -> IntentDef {
+    IntentDef {
+        name: name.into(),
+        description: description.into(),
+        keywords: keywords.iter().map(|s| s.to_string()).collect(),
+        examples: examples.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+impl Default for IntentSettings {
+    fn default() -> Self {
+        IntentSettings {
+            intents: vec![
+                intent(
+                    "npc",
+                    "questions about non-player characters",
+                    &[
+                        "npc",
+                        "character",
+                        "who is",
+                        "innkeeper",
+                        "merchant",
+                        "villain",
+                        "guard",
+                    ],
+                    &["What does the blacksmith think of the baron?"],
+                ),
+                intent(
+                    "rules",
+                    "game mechanics or rules",
+                    &[
+                        "rule",
+                        "rules",
+                        "roll",
+                        "saving throw",
+                        "damage",
+                        "initiative",
+                        "advantage",
+                        "spell slot",
+                    ],
+                    &["How does grappling work?"],
+                ),
+                intent(
+                    "lore",
+                    "world or setting information",
+                    &[
+                        "lore", "history", "kingdom", "legend", "faction", "god", "empire", "city",
+                    ],
+                    &["What happened to the old empire?"],
+                ),
+                intent(
+                    "notes",
+                    "personal or miscellaneous notes",
+                    &[
+                        "note",
+                        "notes",
+                        "remember",
+                        "remind",
+                        "todo",
+                        "last session",
+                    ],
+                    &["Remind me what we did last session."],
+                ),
+            ],
+            confidence_threshold: 0.5,
+            fallback_intent: "notes".into(),
+        }
+    }
+}
+
+impl IntentSettings {
+    fn validate(&self) -> Result<(), String> {
+        if self.intents.is_empty() {
+            return Err("at least one intent is required".into());
+        }
+        let mut seen = Vec::new();
+        for i in &self.intents {
+            let name = i.name.trim();
+            if name.is_empty() {
+                return Err("intent names must not be empty".into());
+            }
+            if seen.contains(&name) {
+                return Err(format!("duplicate intent: {name}"));
+            }
+            seen.push(name);
+        }
+        if !(0.0..=1.0).contains(&self.confidence_threshold) {
+            return Err("confidence threshold must be between 0 and 1".into());
+        }
+        if !seen.contains(&self.fallback_intent.trim()) {
+            return Err(format!(
+                "fallback intent {} is not in the list",
+                self.fallback_intent
+            ));
+        }
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Option<&IntentDef> {
+        self.intents.iter().find(|i| i.name == name)
+    }
+}
+
+/// Which classifier produced an intent.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntentClassifier {
+    Llm,
+    Keyword,
+    Embedding,
+    /// Nothing matched; the configured fallback intent was used.
+    Fallback,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct IntentResult {
+    pub intent: String,
+    pub confidence: f64,
+    pub classifier: IntentClassifier,
+}
+
+impl IntentResult {
+    fn fallback(settings: &IntentSettings) -> Self {
+        IntentResult {
+            intent: settings.fallback_intent.clone(),
+            confidence: 0.0,
+            classifier: IntentClassifier::Fallback,
+        }
+    }
+}
+
+/// Intent settings from the app config, or the defaults.
+pub fn intent_settings() -> IntentSettings {
+    get_config()
+        .intents
+        .filter(|s| s.validate().is_ok())
+        .unwrap_or_default()
+}
+
+/// Read the model's reply. Unknown intents and answers below the confidence
+/// threshold yield the fallback intent.
+pub fn extract_intent(content: &str, settings: &IntentSettings) -> IntentResult {
+    let trimmed = strip_code_fences(content);
+    let (intent, confidence) = match serde_json::from_str::<Value>(trimmed) {
+        Ok(v) => match v["intent"].as_str() {
+            Some(intent) => (
+                intent.trim().to_lowercase(),
+                v["confidence"].as_f64().unwrap_or(0.0),
+            ),
+            None => return IntentResult::fallback(settings),
+        },
+        // A bare intent name counts as a confident answer.
+        Err(_) => (trimmed.to_lowercase(), 1.0),
+    };
+    if confidence >= settings.confidence_threshold && settings.find(&intent).is_some() {
+        return IntentResult {
+            intent,
+            confidence,
+            classifier: IntentClassifier::Llm,
+        };
+    }
+    IntentResult::fallback(settings)
+}
+
+fn normalize(text: &str) -> String {
+    let words: Vec<String> = text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_string())
+        .collect();
+    format!(" {} ", words.join(" "))
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Classify `query` without an LLM: keyword matches first, then similarity
+/// to each intent's description and examples.
+pub fn classify_offline(query: &str, settings: &IntentSettings) -> IntentResult {
+    let text = normalize(query);
+    let mut hits: Vec<(usize, &IntentDef)> = settings
+        .intents
+        .iter()
+        .map(|i| {
+            let n = i
+                .keywords
+                .iter()
+                .filter(|k| {
+                    let k = normalize(k);
+                    k.trim() != "" && text.contains(&k)
+                })
+                .count();
+            (n, i)
+        })
+        .filter(|(n, _)| *n > 0)
+        .collect();
+    hits.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+    if let Some((best, intent)) = hits.first() {
+        let runner_up = hits.get(1).map(|(n, _)| *n).unwrap_or(0);
+        if *best > runner_up {
+            let margin = (*best - runner_up) as f64;
+            return IntentResult {
+                intent: intent.name.clone(),
+                confidence: (0.5 + 0.15 * margin).min(0.9),
+                classifier: IntentClassifier::Keyword,
+            };
+        }
+    }
+
+    let qvec = hash_embed(query);
+    let best = settings
+        .intents
+        .iter()
+        .map(|i| {
+            let profile = format!(
+                "{} {} {}",
+                i.description,
+                i.keywords.join(" "),
+                i.examples.join(" ")
+            );
+            (cosine(&qvec, &hash_embed(&profile)), i)
+        })
+        .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    match best {
+        Some((score, intent)) if score >= MIN_EMBEDDING_SCORE => IntentResult {
+            intent: intent.name.clone(),
+            confidence: score as f64,
+            classifier: IntentClassifier::Embedding,
+        },
+        _ => IntentResult::fallback(settings),
+    }
+}
+
+fn intent_prompt<R: Runtime>(
+    app: &AppHandle<R>,
+    settings: &IntentSettings,
+    world: Option<&str>,
+) -> Result<String, String> {
+    let names: Vec<&str> = settings.intents.iter().map(|i| i.name.as_str()).collect();
+    let intents = match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{}, or {last}", rest.join(", ")),
+        Some((last, _)) => last.to_string(),
+        None => return Err("no intents configured".into()),
+    };
+    let descriptions: Vec<String> = settings
+        .intents
+        .iter()
+        .map(|i| format!("{} = {}", i.name, i.description))
+        .collect();
+    let vars = BTreeMap::from([
+        ("intents", intents),
+        (
+            "intent_descriptions",
+            format!("{}.", descriptions.join(", ")),
+        ),
+    ]);
+    prompts::render_prompt(app, "detect_intent", "detect_intent", world, &vars)
+}
+
+/// System prompt, few-shot examples and the query, ready for the model.
+fn intent_messages(system: String, settings: &IntentSettings, query: &str) -> Vec<ChatMessage> {
+    let mut messages = vec![ChatMessage {
+        role: "system".into(),
+        content: system,
+    }];
+    for i in &settings.intents {
+        for example in i.examples.iter().take(MAX_EXAMPLES_PER_INTENT) {
+            messages.push(ChatMessage {
+                role: "user".into(),
+                content: example.clone(),
+            });
+            messages.push(ChatMessage {
+                role: "assistant".into(),
+                content: json!({ "intent": i.name, "confidence": 0.95 }).to_string(),
+            });
+        }
+    }
+    messages.push(ChatMessage {
+        role: "user".into(),
+        content: query.to_string(),
+    });
+    messages
+}
+
+/// Classify `query` with the LLM, falling back to the offline classifier
+/// when the model is unreachable or unsure.
+pub async fn classify<R: Runtime>(
+    app: &AppHandle<R>,
+    query: &str,
+    world: Option<&str>,
+) -> Result<IntentResult, String> {
+    let settings = intent_settings();
+    let system = intent_prompt(app, &settings, world)?;
+    let req = ChatRequest::new(
+        ollama::model_for(ModelFeature::Intent),
+        intent_messages(system, &settings, query),
+    );
+    match llm::backend().chat(&req).await {
+        Ok(content) => {
+            let result = extract_intent(&content, &settings);
+            if result.classifier == IntentClassifier::Llm {
+                return Ok(result);
+            }
+        }
+        Err(e) => log::warn!("intent model unavailable, using offline classifier: {e}"),
+    }
+    Ok(classify_offline(query, &settings))
+}
+
+/// Like `detect_intent`, but with the confidence and the classifier that answered.
+#[tauri::command]
+pub async fn classify_intent<R: Runtime>(
+    app: AppHandle<R>,
+    query: String,
+    world: Option<String>,
+) -> Result<IntentResult, String> {
+    classify(&app, &query, world.as_deref()).await
+}
+
+#[tauri::command]
+pub async fn load_intent_settings() -> Result<IntentSettings, String> {
+    Ok(intent_settings())
+}
+
+#[tauri::command]
+pub async fn save_intent_settings(settings: IntentSettings) -> Result<(), String> {
+    settings.validate()?;
+    let mut cfg = get_config();
+    cfg.intents = Some(settings);
+    save_config(&cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify_offline, extract_intent, IntentClassifier, IntentSettings};
+
+    fn extract(content: &str) -> String {
+        extract_intent(content, &IntentSettings::default()).intent
+    }
+
+    #[test]
+    fn parses_npc() {
+        assert_eq!(extract("{\"intent\":\"npc\",\"confidence\":0.9}"), "npc");
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert_eq!(
+            extract("{\"intent\":\"rules\",\"confidence\":0.8}"),
+            "rules"
+        );
+    }
+
+    #[test]
+    fn parses_lore() {
+        assert_eq!(extract("{\"intent\":\"lore\",\"confidence\":0.95}"), "lore");
+    }
+
+    #[test]
+    fn parses_notes() {
+        assert_eq!(
+            extract("{\"intent\":\"notes\",\"confidence\":0.99}"),
+            "notes"
+        );
+    }
+
+    #[test]
+    fn defaults_to_notes_on_low_confidence() {
+        assert_eq!(extract("{\"intent\":\"npc\",\"confidence\":0.2}"), "notes");
+    }
+
+    #[test]
+    fn honours_configured_threshold() {
+        let settings = IntentSettings {
+            confidence_threshold: 0.1,
+            ..IntentSettings::default()
+        };
+        let result = extract_intent("{\"intent\":\"npc\",\"confidence\":0.2}", &settings);
+        assert_eq!(result.intent, "npc");
+        assert_eq!(result.classifier, IntentClassifier::Llm);
+    }
+
+    #[test]
+    fn partial_settings_keep_defaults() {
+        let settings: IntentSettings =
+            serde_json::from_str("{\"confidence_threshold\":0.3}").unwrap();
+        assert_eq!(settings.confidence_threshold, 0.3);
+        assert_eq!(settings.intents, IntentSettings::default().intents);
+        assert_eq!(settings.fallback_intent, "notes");
+    }
+
+    #[test]
+    fn offline_classifier_uses_keywords() {
+        let settings = IntentSettings::default();
+        let result = classify_offline("Do I add my bonus to the saving throw roll?", &settings);
+        assert_eq!(result.intent, "rules");
+        assert_eq!(result.classifier, IntentClassifier::Keyword);
+
+        let result = classify_offline("zzz qqq", &settings);
+        assert_eq!(result.intent, "notes");
+        assert_eq!(result.classifier, IntentClassifier::Fallback);
+    }
+}
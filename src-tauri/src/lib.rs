@@ -4,10 +4,30 @@ fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+pub mod ann;
+pub mod comfy;
 pub mod commands;
+pub mod context;
+pub mod conversations;
+pub mod embeddings;
+pub mod index_format;
+pub mod intent;
+pub mod llm;
+pub mod ollama;
+pub mod pdf_library;
+pub mod prompts;
 pub mod python_helpers;
+pub mod records;
+pub mod retrieval;
+pub mod search;
+pub mod structured;
 mod task_queue;
+pub mod tools;
+pub mod vault_graph;
+pub mod vault_index;
 pub mod video_tools;
+pub mod workflow_templates;
+pub mod world_search;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -0,0 +1,588 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+
+use crate::commands::ChatMessage;
+use crate::ollama;
+use crate::python_helpers::{get_config, save_config};
+
+/// A single chat completion request, independent of the server flavour.
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    /// JSON schema the reply must follow, if the server supports constrained
+    /// output.
+    pub format: Option<Value>,
+    /// Context window to ask the server for, when it can be set per request.
+    pub context_tokens: Option<usize>,
+}
+
+impl ChatRequest {
+    pub fn new(model: impl Into<String>, messages: Vec<ChatMessage>) -> Self {
+        ChatRequest {
+            model: model.into(),
+            messages,
+            format: None,
+            context_tokens: None,
+        }
+    }
+
+    pub fn with_format(mut self, schema: Value) -> Self {
+        self.format = Some(schema);
+        self
+    }
+}
+
+/// Stops a streamed request, including one still waiting for its first token.
+#[derive(Debug, Default)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once `cancel` has been called.
+    pub async fn cancelled(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Send `request`, giving up with `None` if `cancel` fires first.
+async fn send_cancellable(
+    request: reqwest::RequestBuilder,
+    cancel: &CancelToken,
+) -> Result<Option<reqwest::Response>, String> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Ok(None),
+        resp = request.send() => check(resp.map_err(|e| e.to_string())?).await.map(Some),
+    }
+}
+
+/// Call `f` with each trimmed line of `resp` until it returns `false` or the
+/// body ends. Returns `false` if `cancel` fired while waiting for data.
+async fn for_each_line<F>(
+    mut resp: reqwest::Response,
+    cancel: &CancelToken,
+    mut f: F,
+) -> Result<bool, String>
+where
+    F: FnMut(&str) -> Result<bool, String>,
+{
+    let mut buf: Vec<u8> = Vec::new();
+    loop {
+        let chunk = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Ok(false),
+            chunk = resp.chunk() => chunk.map_err(|e| e.to_string())?,
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        buf.extend_from_slice(&chunk);
+        while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=pos).collect();
+            if !f(String::from_utf8_lossy(&line).trim())? {
+                return Ok(true);
+            }
+        }
+    }
+    let rest = String::from_utf8_lossy(&buf);
+    if !rest.trim().is_empty() {
+        f(rest.trim())?;
+    }
+    Ok(true)
+}
+
+/// Result of a streamed chat request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamedReply {
+    /// Everything received, assembled in order.
+    pub content: String,
+    /// `true` when the stream was stopped early through `cancel`.
+    pub cancelled: bool,
+}
+
+/// A function the model may call, described by a JSON schema.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments object.
+    pub parameters: Value,
+}
+
+/// One function call requested by the model.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// Tool calls made by the model in one step, with the results sent back.
+#[derive(Debug, Clone)]
+pub struct ToolRound {
+    pub content: String,
+    pub calls: Vec<ToolCall>,
+    /// Output of each call, in the same order as `calls`.
+    pub results: Vec<String>,
+}
+
+/// What the model did with a tool-enabled request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolTurn {
+    /// The model wants `calls` run; `content` is any text sent alongside.
+    Calls {
+        calls: Vec<ToolCall>,
+        content: String,
+    },
+    Answer(String),
+}
+
+fn tools_json(tools: &[ToolSpec]) -> Value {
+    tools
+        .iter()
+        .map(|t| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": t.name,
+                    "description": t.description,
+                    "parameters": t.parameters,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Arguments arrive as an object from Ollama and as a JSON string from
+/// OpenAI-compatible servers.
+fn call_arguments(v: &Value) -> Value {
+    match v {
+        Value::String(s) => serde_json::from_str(s).unwrap_or_else(|_| json!({})),
+        Value::Null => json!({}),
+        other => other.clone(),
+    }
+}
+
+fn parse_tool_turn(message: &Value) -> Result<ToolTurn, String> {
+    let content = message["content"].as_str().unwrap_or_default().to_string();
+    let calls: Vec<ToolCall> = message["tool_calls"]
+        .as_array()
+        .map(|calls| {
+            calls
+                .iter()
+                .enumerate()
+                .filter_map(|(i, c)| {
+                    Some(ToolCall {
+                        id: c["id"]
+                            .as_str()
+                            .map(|s| s.to_string())
+                            .unwrap_or_else(|| format!("call_{i}")),
+                        name: c["function"]["name"].as_str()?.to_string(),
+                        arguments: call_arguments(&c["function"]["arguments"]),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if !calls.is_empty() {
+        return Ok(ToolTurn::Calls { calls, content });
+    }
+    if message.is_null() {
+        return Err("no content".into());
+    }
+    Ok(ToolTurn::Answer(content))
+}
+
+/// A chat-capable LLM server.
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// Run `req` to completion and return the assistant message.
+    async fn chat(&self, req: &ChatRequest) -> Result<String, String>;
+
+    /// Run `req`, calling `on_delta` for every token chunk. Cancelling
+    /// `cancel` stops the request at once, even before the first token, and
+    /// drops the connection.
+    async fn chat_stream(
+        &self,
+        req: &ChatRequest,
+        cancel: &CancelToken,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<StreamedReply, String>;
+
+    /// Run `req` with `tools` available. `rounds` holds the tool calls made so
+    /// far and their results. An empty `tools` forces a final answer.
+    async fn chat_tools(
+        &self,
+        req: &ChatRequest,
+        tools: &[ToolSpec],
+        rounds: &[ToolRound],
+    ) -> Result<ToolTurn, String>;
+}
+
+async fn check(resp: reqwest::Response) -> Result<reqwest::Response, String> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    Err(format!("LLM server returned {status}: {body}"))
+}
+
+/// Ollama's native `/api/chat`.
+pub struct OllamaBackend {
+    pub base_url: String,
+}
+
+impl OllamaBackend {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        OllamaBackend {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn body(req: &ChatRequest, stream: bool) -> Value {
+        let mut body = json!({
+            "model": req.model,
+            "stream": stream,
+            "messages": req.messages,
+        });
+        if let Some(schema) = &req.format {
+            body["format"] = schema.clone();
+        }
+        if let Some(n) = req.context_tokens {
+            // Without this Ollama uses its default window and drops the
+            // start of longer prompts.
+            body["options"] = json!({ "num_ctx": n });
+        }
+        body
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OllamaBackend {
+    async fn chat(&self, req: &ChatRequest) -> Result<String, String> {
+        let resp = reqwest::Client::new()
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&Self::body(req, false))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let json: Value = check(resp).await?.json().await.map_err(|e| e.to_string())?;
+        json["message"]["content"]
+            .as_str()
+            .or_else(|| json["content"].as_str())
+            .map(|s| s.to_string())
+            .ok_or_else(|| "no content".to_string())
+    }
+
+    async fn chat_stream(
+        &self,
+        req: &ChatRequest,
+        cancel: &CancelToken,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<StreamedReply, String> {
+        let mut reply = StreamedReply {
+            content: String::new(),
+            cancelled: false,
+        };
+        let request = reqwest::Client::new()
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&Self::body(req, true));
+        let Some(resp) = send_cancellable(request, cancel).await? else {
+            reply.cancelled = true;
+            return Ok(reply);
+        };
+        let finished = for_each_line(resp, cancel, |line| {
+            if line.is_empty() {
+                return Ok(true);
+            }
+            let v: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+            if let Some(err) = v["error"].as_str() {
+                return Err(err.to_string());
+            }
+            if let Some(delta) = v["message"]["content"].as_str() {
+                if !delta.is_empty() {
+                    reply.content.push_str(delta);
+                    on_delta(delta);
+                }
+            }
+            if cancel.is_cancelled() {
+                return Ok(false);
+            }
+            Ok(!v["done"].as_bool().unwrap_or(false))
+        })
+        .await?;
+        reply.cancelled = !finished || cancel.is_cancelled();
+        Ok(reply)
+    }
+
+    async fn chat_tools(
+        &self,
+        req: &ChatRequest,
+        tools: &[ToolSpec],
+        rounds: &[ToolRound],
+    ) -> Result<ToolTurn, String> {
+        let mut messages: Vec<Value> = req.messages.iter().map(|m| json!(m)).collect();
+        for round in rounds {
+            let calls: Vec<Value> = round
+                .calls
+                .iter()
+                .map(|c| json!({ "function": { "name": c.name, "arguments": c.arguments } }))
+                .collect();
+            messages.push(json!({
+                "role": "assistant",
+                "content": round.content,
+                "tool_calls": calls,
+            }));
+            for (call, result) in round.calls.iter().zip(&round.results) {
+                messages.push(json!({ "role": "tool", "tool_name": call.name, "content": result }));
+            }
+        }
+        let mut body = Self::body(req, false);
+        body["messages"] = Value::Array(messages);
+        if !tools.is_empty() {
+            body["tools"] = tools_json(tools);
+        }
+        let resp = reqwest::Client::new()
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let json: Value = check(resp).await?.json().await.map_err(|e| e.to_string())?;
+        parse_tool_turn(&json["message"])
+    }
+}
+
+/// OpenAI-compatible `/v1/chat/completions`, as served by llama.cpp's
+/// server, LM Studio, vLLM and others.
+pub struct OpenAiBackend {
+    pub base_url: String,
+    pub api_key: Option<String>,
+}
+
+impl OpenAiBackend {
+    pub fn new(base_url: impl Into<String>, api_key: Option<String>) -> Self {
+        let base = base_url.into();
+        let base = base.trim_end_matches('/');
+        OpenAiBackend {
+            base_url: base.strip_suffix("/v1").unwrap_or(base).to_string(),
+            api_key: api_key.filter(|k| !k.trim().is_empty()),
+        }
+    }
+
+    fn body(req: &ChatRequest, stream: bool) -> Value {
+        let mut body = json!({
+            "model": req.model,
+            "stream": stream,
+            "messages": req.messages,
+        });
+        if let Some(schema) = &req.format {
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": { "name": "reply", "schema": schema },
+            });
+        }
+        body
+    }
+
+    fn request(&self, body: &Value) -> reqwest::RequestBuilder {
+        let mut builder = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", self.base_url))
+            .json(body);
+        if let Some(key) = &self.api_key {
+            builder = builder.bearer_auth(key);
+        }
+        builder
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    async fn chat(&self, req: &ChatRequest) -> Result<String, String> {
+        let resp = self
+            .request(&Self::body(req, false))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let json: Value = check(resp).await?.json().await.map_err(|e| e.to_string())?;
+        json["choices"][0]["message"]["content"]
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| "no content".to_string())
+    }
+
+    async fn chat_stream(
+        &self,
+        req: &ChatRequest,
+        cancel: &CancelToken,
+        on_delta: &mut (dyn for<'d> FnMut(&'d str) + Send),
+    ) -> Result<StreamedReply, String> {
+        let mut reply = StreamedReply {
+            content: String::new(),
+            cancelled: false,
+        };
+        let request = self.request(&Self::body(req, true));
+        let Some(resp) = send_cancellable(request, cancel).await? else {
+            reply.cancelled = true;
+            return Ok(reply);
+        };
+        // Server-sent events: `data: {json}` lines, terminated by `data: [DONE]`.
+        let finished = for_each_line(resp, cancel, |line| {
+            let data = match line.strip_prefix("data:") {
+                Some(d) => d.trim(),
+                None => return Ok(true),
+            };
+            if data == "[DONE]" {
+                return Ok(false);
+            }
+            let v: Value = serde_json::from_str(data).map_err(|e| e.to_string())?;
+            if let Some(err) = v["error"]["message"].as_str() {
+                return Err(err.to_string());
+            }
+            if let Some(delta) = v["choices"][0]["delta"]["content"].as_str() {
+                if !delta.is_empty() {
+                    reply.content.push_str(delta);
+                    on_delta(delta);
+                }
+            }
+            Ok(!cancel.is_cancelled())
+        })
+        .await?;
+        reply.cancelled = !finished || cancel.is_cancelled();
+        Ok(reply)
+    }
+
+    async fn chat_tools(
+        &self,
+        req: &ChatRequest,
+        tools: &[ToolSpec],
+        rounds: &[ToolRound],
+    ) -> Result<ToolTurn, String> {
+        let mut messages: Vec<Value> = req.messages.iter().map(|m| json!(m)).collect();
+        for round in rounds {
+            let calls: Vec<Value> = round
+                .calls
+                .iter()
+                .map(|c| {
+                    json!({
+                        "id": c.id,
+                        "type": "function",
+                        "function": { "name": c.name, "arguments": c.arguments.to_string() },
+                    })
+                })
+                .collect();
+            messages.push(json!({
+                "role": "assistant",
+                "content": round.content,
+                "tool_calls": calls,
+            }));
+            for (call, result) in round.calls.iter().zip(&round.results) {
+                messages
+                    .push(json!({ "role": "tool", "tool_call_id": call.id, "content": result }));
+            }
+        }
+        let mut body = Self::body(req, false);
+        body["messages"] = Value::Array(messages);
+        if !tools.is_empty() {
+            body["tools"] = tools_json(tools);
+        }
+        let resp = self
+            .request(&body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let json: Value = check(resp).await?.json().await.map_err(|e| e.to_string())?;
+        parse_tool_turn(&json["choices"][0]["message"])
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    #[default]
+    Ollama,
+    Openai,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LlmSettings {
+    pub backend: BackendKind,
+    /// Base URL of the OpenAI-compatible server, e.g. `http://127.0.0.1:8080`.
+    pub openai_base_url: Option<String>,
+    pub openai_api_key: Option<String>,
+}
+
+fn llm_settings() -> LlmSettings {
+    let cfg = get_config();
+    let backend = match cfg.llm_backend.as_deref() {
+        Some("openai") => BackendKind::Openai,
+        _ => BackendKind::Ollama,
+    };
+    LlmSettings {
+        backend,
+        openai_base_url: cfg.openai_base_url,
+        openai_api_key: cfg.openai_api_key,
+    }
+}
+
+/// The backend selected in the app config.
+pub fn backend() -> Box<dyn LlmBackend> {
+    let settings = llm_settings();
+    match settings.backend {
+        BackendKind::Ollama => Box::new(OllamaBackend::new(ollama::ollama_url())),
+        BackendKind::Openai => Box::new(OpenAiBackend::new(
+            settings
+                .openai_base_url
+                .filter(|u| !u.trim().is_empty())
+                .unwrap_or_else(|| "http://127.0.0.1:8080".into()),
+            settings.openai_api_key,
+        )),
+    }
+}
+
+#[tauri::command]
+pub async fn load_llm_settings() -> Result<LlmSettings, String> {
+    Ok(llm_settings())
+}
+
+#[tauri::command]
+pub async fn save_llm_settings(settings: LlmSettings) -> Result<(), String> {
+    let mut cfg = get_config();
+    cfg.llm_backend = Some(
+        match settings.backend {
+            BackendKind::Ollama => "ollama",
+            BackendKind::Openai => "openai",
+        }
+        .into(),
+    );
+    cfg.openai_base_url = settings.openai_base_url.filter(|u| !u.trim().is_empty());
+    cfg.openai_api_key = settings.openai_api_key.filter(|k| !k.trim().is_empty());
+    save_config(&cfg)
+}
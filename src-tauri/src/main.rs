@@ -1,10 +1,30 @@
 // src-tauri/src/main.rs
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod ann;
+mod comfy;
 mod commands;
+mod context;
+mod conversations;
+mod embeddings;
+mod index_format;
+mod intent;
+mod llm;
+mod ollama;
+mod pdf_library;
+mod prompts;
 mod python_helpers;
+mod records;
+mod retrieval;
+mod search;
+mod structured;
 mod task_queue;
+mod tools;
+mod vault_graph;
+mod vault_index;
 mod video_tools;
+mod workflow_templates;
+mod world_search;
 
 use task_queue::TaskQueue;
 use tauri::Manager;
@@ -50,19 +70,63 @@ fn main() {
             commands::comfy_status,
             commands::comfy_start,
             commands::comfy_stop,
+            commands::comfy_submit_workflow,
+            workflow_templates::list_workflow_templates,
+            workflow_templates::render_workflow_template,
             // Ollama general chat:
             commands::start_ollama,
             commands::stop_ollama,
             commands::general_chat,
             commands::npc_event_chat,
+            commands::general_chat_stream,
+            commands::npc_event_chat_stream,
+            commands::cancel_chat_stream,
+            tools::chat_with_tools,
+            // Conversations:
+            conversations::create_conversation,
+            conversations::append_conversation,
+            conversations::get_conversation,
+            conversations::list_conversations,
+            conversations::rename_conversation,
+            conversations::delete_conversation,
+            conversations::search_conversations,
             commands::detect_intent,
+            intent::classify_intent,
+            intent::load_intent_settings,
+            intent::save_intent_settings,
             commands::retrieve_context,
+            retrieval::retrieve_passages,
+            retrieval::chat_with_citations,
+            world_search::search_world,
+            vault_graph::vault_backlinks,
+            vault_graph::vault_outgoing_links,
+            vault_graph::vault_orphans,
+            vault_graph::vault_broken_links,
+            vault_graph::vault_neighborhood,
+            // Prompt templates:
+            prompts::list_prompt_templates,
+            prompts::get_prompt_template,
+            prompts::save_prompt_template,
+            prompts::reset_prompt_template,
+            prompts::read_prompt_usage,
+            // LLM backend:
+            llm::load_llm_settings,
+            llm::save_llm_settings,
+            // Ollama models:
+            ollama::ollama_list_models,
+            ollama::ollama_show_model,
+            ollama::ollama_pull_model,
+            ollama::ollama_delete_model,
+            ollama::ollama_disk_usage,
+            ollama::load_model_settings,
+            ollama::save_model_settings,
             // PDF tools:
             commands::pdf_add,
             commands::pdf_remove,
             commands::pdf_list,
             commands::pdf_search,
             commands::vault_search,
+            commands::vault_index,
             commands::pdf_ingest,
             commands::parse_spell_pdf,
             commands::parse_rule_pdf,
@@ -80,6 +144,7 @@ fn main() {
             commands::list_spells,
             commands::save_lore,
             commands::list_lore,
+            records::validate_record,
             // Paths:
             python_helpers::load_paths,
             python_helpers::save_paths,
@@ -0,0 +1,253 @@
+use std::{collections::HashMap, env, fs, path::Path, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+
+use crate::python_helpers::{get_config, save_config};
+
+/// Model used when nothing is configured.
+pub const DEFAULT_MODEL: &str = "gpt-oss:20b";
+
+/// Base URL of the local Ollama server.
+///
+/// Can be overridden with `BLOSSOM_OLLAMA_URL`, which is mostly useful for
+/// pointing the app (or tests) at a different host.
+pub fn ollama_url() -> String {
+    if let Ok(url) = env::var("BLOSSOM_OLLAMA_URL") {
+        if !url.trim().is_empty() {
+            return url.trim_end_matches('/').to_string();
+        }
+    }
+    "http://127.0.0.1:11434".to_string()
+}
+
+/// Features that can use their own model instead of the default one.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelFeature {
+    Chat,
+    NpcEvent,
+    Intent,
+}
+
+/// The configured default model, falling back to [`DEFAULT_MODEL`].
+pub fn default_model() -> String {
+    non_empty(get_config().default_model).unwrap_or_else(|| DEFAULT_MODEL.to_string())
+}
+
+/// Resolve the model for `feature`: its override if set, else the default.
+pub fn model_for(feature: ModelFeature) -> String {
+    let cfg = get_config();
+    let over = match feature {
+        ModelFeature::Chat => cfg.chat_model,
+        ModelFeature::NpcEvent => cfg.npc_event_model,
+        ModelFeature::Intent => cfg.intent_model,
+    };
+    non_empty(over)
+        .or_else(|| non_empty(cfg.default_model))
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string())
+}
+
+fn non_empty(s: Option<String>) -> Option<String> {
+    s.filter(|s| !s.trim().is_empty())
+}
+
+pub fn models_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|_| "app data dir".to_string())?
+        .join("ollama-models");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaModel {
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub modified_at: Option<String>,
+    #[serde(default)]
+    pub details: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelSettings {
+    pub default_model: Option<String>,
+    pub chat_model: Option<String>,
+    pub npc_event_model: Option<String>,
+    pub intent_model: Option<String>,
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    /// Context window in tokens per model name.
+    #[serde(default)]
+    pub context_budgets: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelsDiskUsage {
+    pub path: String,
+    pub bytes: u64,
+}
+
+async fn check(resp: reqwest::Response) -> Result<reqwest::Response, String> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    Err(format!("Ollama returned {status}: {body}"))
+}
+
+/// Call `f` for every JSON object in a newline-delimited JSON response.
+///
+/// Stops early and returns `Ok(())` when `f` returns `false`.
+pub async fn for_each_ndjson<F>(mut resp: reqwest::Response, mut f: F) -> Result<(), String>
+where
+    F: FnMut(Value) -> Result<bool, String>,
+{
+    let mut buf: Vec<u8> = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(|e| e.to_string())? {
+        buf.extend_from_slice(&chunk);
+        while let Some(pos) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let v: Value = serde_json::from_str(line.trim()).map_err(|e| e.to_string())?;
+            if !f(v)? {
+                return Ok(());
+            }
+        }
+    }
+    let rest = String::from_utf8_lossy(&buf);
+    if !rest.trim().is_empty() {
+        let v: Value = serde_json::from_str(rest.trim()).map_err(|e| e.to_string())?;
+        f(v)?;
+    }
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let mut total = 0;
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            match entry.metadata() {
+                Ok(meta) if meta.is_dir() => total += dir_size(&entry.path()),
+                Ok(meta) => total += meta.len(),
+                Err(_) => {}
+            }
+        }
+    }
+    total
+}
+
+/// Models currently installed in the Ollama server.
+pub async fn installed_models() -> Result<Vec<OllamaModel>, String> {
+    let resp = reqwest::Client::new()
+        .get(format!("{}/api/tags", ollama_url()))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let json: Value = check(resp).await?.json().await.map_err(|e| e.to_string())?;
+    serde_json::from_value(json["models"].clone()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn ollama_list_models() -> Result<Vec<OllamaModel>, String> {
+    installed_models().await
+}
+
+#[tauri::command]
+pub async fn ollama_show_model(name: String) -> Result<Value, String> {
+    let resp = reqwest::Client::new()
+        .post(format!("{}/api/show", ollama_url()))
+        .json(&json!({ "model": name }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    check(resp).await?.json().await.map_err(|e| e.to_string())
+}
+
+/// Pull `name`, forwarding progress lines to the `ollama_log` event.
+#[tauri::command]
+pub async fn ollama_pull_model<R: Runtime>(app: AppHandle<R>, name: String) -> Result<(), String> {
+    let resp = reqwest::Client::new()
+        .post(format!("{}/api/pull", ollama_url()))
+        .json(&json!({ "model": name, "stream": true }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let resp = check(resp).await?;
+    for_each_ndjson(resp, |v| {
+        if let Some(err) = v["error"].as_str() {
+            return Err(format!("ollama pull failed: {err}"));
+        }
+        let status = v["status"].as_str().unwrap_or_default();
+        let line = match (v["completed"].as_u64(), v["total"].as_u64()) {
+            (Some(done), Some(total)) => format!("[pull] {status} {done}/{total}"),
+            _ => format!("[pull] {status}"),
+        };
+        let _ = app.emit("ollama_log", line);
+        Ok(true)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn ollama_delete_model(name: String) -> Result<(), String> {
+    let resp = reqwest::Client::new()
+        .delete(format!("{}/api/delete", ollama_url()))
+        .json(&json!({ "model": name }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    check(resp).await?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn ollama_disk_usage<R: Runtime>(app: AppHandle<R>) -> Result<ModelsDiskUsage, String> {
+    let dir = models_dir(&app)?;
+    Ok(ModelsDiskUsage {
+        bytes: dir_size(&dir),
+        path: dir.to_string_lossy().to_string(),
+    })
+}
+
+#[tauri::command]
+pub async fn load_model_settings() -> Result<ModelSettings, String> {
+    let cfg = get_config();
+    Ok(ModelSettings {
+        default_model: cfg.default_model,
+        chat_model: cfg.chat_model,
+        npc_event_model: cfg.npc_event_model,
+        intent_model: cfg.intent_model,
+        embedding_model: cfg.embedding_model,
+        context_budgets: cfg.context_budgets.unwrap_or_default(),
+    })
+}
+
+/// Store model settings. Empty strings clear an override.
+#[tauri::command]
+pub async fn save_model_settings(settings: ModelSettings) -> Result<(), String> {
+    let mut cfg = get_config();
+    cfg.default_model = non_empty(settings.default_model);
+    cfg.chat_model = non_empty(settings.chat_model);
+    cfg.npc_event_model = non_empty(settings.npc_event_model);
+    cfg.intent_model = non_empty(settings.intent_model);
+    cfg.embedding_model = non_empty(settings.embedding_model);
+    let budgets: HashMap<String, usize> = settings
+        .context_budgets
+        .into_iter()
+        .filter(|(model, tokens)| !model.trim().is_empty() && *tokens > 0)
+        .collect();
+    cfg.context_budgets = (!budgets.is_empty()).then_some(budgets);
+    save_config(&cfg)
+}
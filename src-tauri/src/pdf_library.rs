@@ -0,0 +1,591 @@
+use std::{fs, io::Write, path::Path};
+
+use chrono::Utc;
+use pdf_extract::{Document, Object};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, Executor, Row, SqliteConnection};
+
+use crate::commands::hash_embed;
+use crate::embeddings::encode_vector;
+use crate::vault_index;
+
+/// Words per chunk, as `pdf_tools.py` chunks.
+const CHUNK_WORDS: usize = 500;
+
+/// Fewest words of text that tell one book from another. PDFs with less
+/// (scans, image-only files) are only duplicates of the same file.
+const MIN_CONTENT_WORDS: usize = 50;
+
+/// One row per imported PDF. The chunks themselves go in `embeddings` with
+/// the vault's notes. Index format 5 adds the last three columns to tables
+/// created without them.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pdf_documents (
+    doc_id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    author TEXT,
+    pages INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+    created TEXT,
+    added TEXT NOT NULL,
+    source TEXT,
+    content_hash TEXT,
+    edition_of TEXT,
+    duplicate_of TEXT
+);
+";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PdfDocument {
+    pub doc_id: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub pages: u32,
+    /// SHA-256 of the file.
+    pub hash: String,
+    /// When the PDF says it was made.
+    pub created: Option<String>,
+    /// When it was imported.
+    pub added: String,
+    /// Path it was imported from.
+    pub source: Option<String>,
+    /// SHA-256 of the extracted text, the same for copies of a book that
+    /// differ only in file metadata. The file hash for PDFs with too little
+    /// text to compare.
+    pub content_hash: Option<String>,
+    /// Earlier edition: a document with the same title and page count but
+    /// different text.
+    pub edition_of: Option<String>,
+    /// Document with the same text that was kept alongside this one.
+    pub duplicate_of: Option<String>,
+}
+
+/// What to do when a PDF's text is already in the library.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Keep the existing document and import nothing.
+    #[default]
+    Skip,
+    /// Remove the existing document and import the new file.
+    Replace,
+    /// Import the new file next to the existing one.
+    KeepBoth,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AddAction {
+    Added,
+    /// Added as a new edition of `existing`.
+    NewEdition,
+    Skipped,
+    Replaced,
+    KeptBoth,
+}
+
+/// What `add_pdf` did. `doc` is the document now in the library for the
+/// file, which is the existing one when it was skipped.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AddReport {
+    pub action: AddAction,
+    pub doc: PdfDocument,
+    /// Documents the file duplicated (or, for a new edition, the edition
+    /// it follows).
+    pub existing: Vec<String>,
+    /// Pages (1-based) without a text layer, most likely scans. They can't
+    /// be found by search until the PDF has been through OCR.
+    pub pages_without_text: Vec<u32>,
+}
+
+/// Text of each page, first page first, and the document's info.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PdfText {
+    pub pages: Vec<String>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub created: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PdfChunk {
+    pub page_start: u32,
+    pub page_end: u32,
+    pub text: String,
+}
+
+/// Turn a PDF date (`D:YYYYMMDDHHmmSS...`) into `YYYY-MM-DDTHH:mm:SS`, or as
+/// much of it as is there. Anything else is returned as it is.
+fn pdf_date(raw: &str) -> String {
+    let digits: String = raw
+        .trim_start_matches("D:")
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    let part = |from: usize| digits.get(from..from + 2).unwrap_or("00");
+    match digits.len() {
+        n if n >= 8 => {
+            let date = format!("{}-{}-{}", &digits[..4], part(4), part(6));
+            if n >= 12 {
+                format!("{date}T{}:{}:{}", part(8), part(10), part(12))
+            } else {
+                date
+            }
+        }
+        _ => raw.to_string(),
+    }
+}
+
+/// Hash of the text with whitespace normalised, one page after another.
+/// Text of fewer than [`MIN_CONTENT_WORDS`] words would match any other
+/// PDF with as little, so `file_hash` is used instead.
+pub fn content_hash(pages: &[String], file_hash: &str) -> String {
+    let words: usize = pages.iter().map(|p| p.split_whitespace().count()).sum();
+    if words < MIN_CONTENT_WORDS {
+        return file_hash.to_string();
+    }
+    let mut hasher = Sha256::new();
+    for page in pages {
+        for word in page.split_whitespace() {
+            hasher.update(word.as_bytes());
+            hasher.update(b" ");
+        }
+        hasher.update(b"\x0c");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn info_field(doc: &Document, key: &[u8]) -> Option<String> {
+    let info = match doc.trailer.get(b"Info").ok()? {
+        Object::Reference(id) => doc.get_dictionary(*id).ok()?,
+        Object::Dictionary(dict) => dict,
+        _ => return None,
+    };
+    let value = pdf_extract::decode_text_string(info.get(key).ok()?).ok()?;
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Extract the text of every page of a PDF. Pages without a text layer
+/// (scans) come back empty; see [`pages_without_text`].
+pub fn extract_pdf(bytes: &[u8]) -> Result<PdfText, String> {
+    let doc = Document::load_mem(bytes).map_err(|e| format!("invalid PDF: {e}"))?;
+    let pages = pdf_extract::extract_text_from_mem_by_pages(bytes)
+        .map_err(|e| format!("could not extract text: {e}"))?;
+    Ok(PdfText {
+        pages,
+        title: info_field(&doc, b"Title"),
+        author: info_field(&doc, b"Author"),
+        created: info_field(&doc, b"CreationDate").map(|d| pdf_date(&d)),
+    })
+}
+
+/// Pages (1-based) with no words in them.
+pub fn pages_without_text(pages: &[String]) -> Vec<u32> {
+    (1..)
+        .zip(pages)
+        .filter(|(_, text)| text.split_whitespace().next().is_none())
+        .map(|(page, _)| page)
+        .collect()
+}
+
+/// Split pages into chunks of [`CHUNK_WORDS`] words, each with the pages it
+/// spans.
+pub fn chunk_pages(pages: &[String]) -> Vec<PdfChunk> {
+    let mut chunks = Vec::new();
+    let mut words: Vec<&str> = Vec::new();
+    let mut start = 1;
+    for (i, text) in pages.iter().enumerate() {
+        let page = i as u32 + 1;
+        for word in text.split_whitespace() {
+            if words.len() >= CHUNK_WORDS {
+                chunks.push(PdfChunk {
+                    page_start: start,
+                    page_end: page,
+                    text: words.join(" "),
+                });
+                words.clear();
+            }
+            if words.is_empty() {
+                start = page;
+            }
+            words.push(word);
+        }
+    }
+    if !words.is_empty() {
+        chunks.push(PdfChunk {
+            page_start: start,
+            page_end: pages.len() as u32,
+            text: words.join(" "),
+        });
+    }
+    chunks
+}
+
+fn document(row: &sqlx::sqlite::SqliteRow) -> PdfDocument {
+    PdfDocument {
+        doc_id: row.get("doc_id"),
+        title: row.get("title"),
+        author: row.get("author"),
+        pages: row.get::<i64, _>("pages") as u32,
+        hash: row.get("hash"),
+        created: row.get("created"),
+        added: row.get("added"),
+        source: row.get("source"),
+        content_hash: row.get("content_hash"),
+        edition_of: row.get("edition_of"),
+        duplicate_of: row.get("duplicate_of"),
+    }
+}
+
+async fn open(index_dir: &Path) -> Result<SqliteConnection, String> {
+    fs::create_dir_all(index_dir).map_err(|e| e.to_string())?;
+    let db_path = index_dir.join("index.sqlite");
+    let mut conn =
+        SqliteConnection::connect(&format!("sqlite:{}?mode=rwc", db_path.to_string_lossy()))
+            .await
+            .map_err(|e| e.to_string())?;
+    vault_index::ensure_schema(&mut conn).await?;
+    conn.execute(SCHEMA).await.map_err(|e| e.to_string())?;
+    Ok(conn)
+}
+
+/// Record documents imported by `pdf_tools.py`, which only wrote a
+/// `doc.json` next to their page texts.
+async fn adopt_legacy(conn: &mut SqliteConnection, index_dir: &Path) -> Result<(), String> {
+    let Ok(entries) = fs::read_dir(index_dir) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        let info_path = entry.path().join("doc.json");
+        let Ok(raw) = fs::read_to_string(&info_path) else {
+            continue;
+        };
+        let Ok(info) = serde_json::from_str::<Value>(&raw) else {
+            continue;
+        };
+        let doc_id = entry.file_name().to_string_lossy().to_string();
+        let pages = info["pages"].as_i64().unwrap_or(0);
+        let texts: Option<Vec<String>> = (1..=pages)
+            .map(|i| fs::read_to_string(entry.path().join(format!("pages/{i}.txt"))).ok())
+            .collect();
+        let added = fs::metadata(&info_path)
+            .and_then(|m| m.modified())
+            .map(chrono::DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        sqlx::query(
+            "INSERT OR IGNORE INTO pdf_documents
+             (doc_id, title, author, pages, hash, created, added, source, content_hash)
+             VALUES (?, ?, ?, ?, ?, ?, ?, NULL, ?)",
+        )
+        .bind(&doc_id)
+        .bind(info["title"].as_str().unwrap_or(&doc_id))
+        .bind(info["author"].as_str())
+        .bind(pages)
+        .bind(info["hash"].as_str().unwrap_or_default())
+        .bind(info["created"].as_str().map(pdf_date))
+        .bind(added.to_rfc3339())
+        .bind(
+            texts
+                .filter(|t| !t.is_empty())
+                .map(|t| content_hash(&t, info["hash"].as_str().unwrap_or(&doc_id))),
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Write the page texts, `doc.json` and `chunks.jsonl` lines that
+/// `pdf_tools.py ingest` reads when tagging a document with the LLM.
+fn write_sidecars(
+    index_dir: &Path,
+    doc: &PdfDocument,
+    text: &PdfText,
+    chunks: &[PdfChunk],
+) -> Result<(), String> {
+    let doc_dir = index_dir.join(&doc.doc_id);
+    let pages_dir = doc_dir.join("pages");
+    fs::create_dir_all(&pages_dir).map_err(|e| e.to_string())?;
+    for (i, page) in text.pages.iter().enumerate() {
+        fs::write(pages_dir.join(format!("{}.txt", i + 1)), page).map_err(|e| e.to_string())?;
+    }
+    let info = json!({
+        "title": doc.title,
+        "author": doc.author,
+        "created": doc.created,
+        "pages": doc.pages,
+        "hash": doc.hash,
+    });
+    let info = serde_json::to_string_pretty(&info).map_err(|e| e.to_string())?;
+    fs::write(doc_dir.join("doc.json"), info).map_err(|e| e.to_string())?;
+
+    let mut lines = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index_dir.join("chunks.jsonl"))
+        .map_err(|e| e.to_string())?;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let line = json!({
+            "doc_id": doc.doc_id,
+            "page_range": [chunk.page_start, chunk.page_end],
+            "text": chunk.text,
+            "chunk_id": format!("{}_{i}", doc.doc_id),
+        });
+        writeln!(lines, "{line}").map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+async fn documents(
+    conn: &mut SqliteConnection,
+    sql: &str,
+    binds: &[&str],
+) -> Result<Vec<PdfDocument>, String> {
+    let mut query = sqlx::query(sql);
+    for bind in binds {
+        query = query.bind(*bind);
+    }
+    Ok(query
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(document)
+        .collect())
+}
+
+/// Import the PDF at `path` into the library in `index_dir`: record its
+/// metadata, chunk its text into the search index and keep the files
+/// `pdf_tools.py` uses for LLM tagging.
+///
+/// Importing the same file twice returns the existing document. A different
+/// file with the same text is a duplicate and is handled as `on_duplicate`
+/// says; one with the same title and page count but different text is added
+/// as a new edition. Pages without text are imported empty and listed in
+/// the report.
+pub async fn add_pdf(
+    index_dir: &Path,
+    path: &Path,
+    on_duplicate: DuplicatePolicy,
+) -> Result<AddReport, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let hash = format!("{:x}", Sha256::digest(&bytes));
+    let doc_id = hash[..16].to_string();
+
+    let mut conn = open(index_dir).await?;
+    adopt_legacy(&mut conn, index_dir).await?;
+    let same_file = documents(
+        &mut conn,
+        "SELECT * FROM pdf_documents WHERE doc_id = ?",
+        &[&doc_id],
+    )
+    .await?;
+    if let Some(doc) = same_file.into_iter().next() {
+        return Ok(AddReport {
+            action: AddAction::Skipped,
+            existing: vec![doc.doc_id.clone()],
+            doc,
+            pages_without_text: Vec::new(),
+        });
+    }
+
+    let text = tokio::task::spawn_blocking(move || extract_pdf(&bytes))
+        .await
+        .map_err(|e| e.to_string())??;
+    let chunks = chunk_pages(&text.pages);
+    let pages_without_text = pages_without_text(&text.pages);
+    let content = content_hash(&text.pages, &hash);
+    if !pages_without_text.is_empty() {
+        log::warn!(
+            "{}: {} of {} pages have no text layer and won't be searchable",
+            path.display(),
+            pages_without_text.len(),
+            text.pages.len()
+        );
+    }
+    let mut doc = PdfDocument {
+        doc_id: doc_id.clone(),
+        title: text.title.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| doc_id.clone())
+        }),
+        author: text.author.clone(),
+        pages: text.pages.len() as u32,
+        hash,
+        created: text.created.clone(),
+        added: Utc::now().to_rfc3339(),
+        source: Some(path.to_string_lossy().to_string()),
+        content_hash: Some(content),
+        edition_of: None,
+        duplicate_of: None,
+    };
+
+    let duplicates = documents(
+        &mut conn,
+        "SELECT * FROM pdf_documents WHERE content_hash = ? ORDER BY added, doc_id",
+        &[doc.content_hash.as_deref().unwrap_or_default()],
+    )
+    .await?;
+    let mut existing: Vec<String> = duplicates.iter().map(|d| d.doc_id.clone()).collect();
+    let action = match (duplicates.first(), on_duplicate) {
+        (Some(original), DuplicatePolicy::Skip) => {
+            return Ok(AddReport {
+                action: AddAction::Skipped,
+                doc: original.clone(),
+                existing,
+                pages_without_text,
+            });
+        }
+        (Some(_), DuplicatePolicy::Replace) => {
+            for old in &existing {
+                remove_pdf(index_dir, old).await?;
+            }
+            AddAction::Replaced
+        }
+        (Some(original), DuplicatePolicy::KeepBoth) => {
+            doc.duplicate_of = Some(original.doc_id.clone());
+            AddAction::KeptBoth
+        }
+        (None, _) => {
+            let pages = doc.pages.to_string();
+            let previous = documents(
+                &mut conn,
+                "SELECT * FROM pdf_documents
+                 WHERE title = ? COLLATE NOCASE AND pages = CAST(? AS INTEGER)
+                 ORDER BY added DESC, doc_id",
+                &[&doc.title, &pages],
+            )
+            .await?;
+            match previous.into_iter().next() {
+                Some(previous) => {
+                    doc.edition_of = Some(previous.doc_id.clone());
+                    existing.push(previous.doc_id);
+                    AddAction::NewEdition
+                }
+                None => AddAction::Added,
+            }
+        }
+    };
+    let doc = store(&mut conn, index_dir, doc, &text, &chunks).await?;
+    Ok(AddReport {
+        action,
+        doc,
+        existing,
+        pages_without_text,
+    })
+}
+
+async fn store(
+    conn: &mut SqliteConnection,
+    index_dir: &Path,
+    doc: PdfDocument,
+    text: &PdfText,
+    chunks: &[PdfChunk],
+) -> Result<PdfDocument, String> {
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let vector = encode_vector(&hash_embed(&chunk.text));
+        sqlx::query("INSERT OR REPLACE INTO embeddings VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(format!("{}_{i}", doc.doc_id))
+            .bind(vector)
+            .bind(&doc.doc_id)
+            .bind(chunk.page_start as i64)
+            .bind(chunk.page_end as i64)
+            .bind(&chunk.text)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    sqlx::query(
+        "INSERT INTO pdf_documents (doc_id, title, author, pages, hash, created, added, source,
+                                    content_hash, edition_of, duplicate_of)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&doc.doc_id)
+    .bind(&doc.title)
+    .bind(&doc.author)
+    .bind(doc.pages as i64)
+    .bind(&doc.hash)
+    .bind(&doc.created)
+    .bind(&doc.added)
+    .bind(&doc.source)
+    .bind(&doc.content_hash)
+    .bind(&doc.edition_of)
+    .bind(&doc.duplicate_of)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    write_sidecars(index_dir, &doc, text, chunks)?;
+    Ok(doc)
+}
+
+pub async fn get_pdf(index_dir: &Path, doc_id: &str) -> Result<Option<PdfDocument>, String> {
+    let mut conn = open(index_dir).await?;
+    Ok(documents(
+        &mut conn,
+        "SELECT * FROM pdf_documents WHERE doc_id = ?",
+        &[doc_id],
+    )
+    .await?
+    .pop())
+}
+
+/// Every document in the library, oldest import first.
+pub async fn list_pdfs(index_dir: &Path) -> Result<Vec<PdfDocument>, String> {
+    let mut conn = open(index_dir).await?;
+    adopt_legacy(&mut conn, index_dir).await?;
+    documents(
+        &mut conn,
+        "SELECT * FROM pdf_documents ORDER BY added, doc_id",
+        &[],
+    )
+    .await
+}
+
+/// Remove a document, its chunks and its files. Removing an unknown
+/// document does nothing.
+pub async fn remove_pdf(index_dir: &Path, doc_id: &str) -> Result<(), String> {
+    let mut conn = open(index_dir).await?;
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+    for sql in [
+        "DELETE FROM embeddings WHERE doc_id = ?",
+        "DELETE FROM pdf_documents WHERE doc_id = ?",
+    ] {
+        sqlx::query(sql)
+            .bind(doc_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    // Only ever delete a folder directly inside the index.
+    if doc_id.is_empty() || doc_id.contains(['/', '\\', '.']) {
+        return Ok(());
+    }
+    let doc_dir = index_dir.join(doc_id);
+    if doc_dir.is_dir() {
+        fs::remove_dir_all(&doc_dir).map_err(|e| e.to_string())?;
+    }
+    let lines_path = index_dir.join("chunks.jsonl");
+    if let Ok(raw) = fs::read_to_string(&lines_path) {
+        let kept: String = raw
+            .lines()
+            .filter(|line| {
+                serde_json::from_str::<Value>(line)
+                    .map(|v| v["doc_id"] != doc_id)
+                    .unwrap_or(true)
+            })
+            .map(|line| format!("{line}\n"))
+            .collect();
+        fs::write(&lines_path, kept).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
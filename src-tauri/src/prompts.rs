@@ -0,0 +1,424 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager, Runtime};
+
+/// Prompts shipped with the app. Written to the prompt folder on first use
+/// and restored by `reset_prompt_template`.
+const BUILTIN_PROMPTS: [(&str, &str); 2] = [
+    (
+        "detect_intent",
+        include_str!("../prompts/detect_intent.yaml"),
+    ),
+    ("npc_event", include_str!("../prompts/npc_event.yaml")),
+];
+
+/// `usage.log` is cut back to its newest `USAGE_LOG_KEEP` entries once it
+/// grows past this size.
+const USAGE_LOG_MAX_BYTES: u64 = 1 << 20;
+const USAGE_LOG_KEEP: usize = 2000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PromptTemplate {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// Variables the template expects, referenced as `{{name}}`.
+    #[serde(default)]
+    pub variables: Vec<String>,
+    pub template: String,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+/// Where a resolved prompt came from.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptSource {
+    Builtin,
+    Custom,
+    World,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedPrompt {
+    #[serde(flatten)]
+    pub template: PromptTemplate,
+    pub source: PromptSource,
+}
+
+/// Replace the `{{name}}` placeholders in `text` with what `lookup` gives
+/// for each name, left to right. Only `text` is scanned, so values are
+/// inserted as they are even when they contain braces.
+pub fn fill_placeholders(
+    text: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    errors: &mut Vec<String>,
+) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + len + 2];
+        out.push_str(&rest[..start]);
+        match lookup(placeholder[2..len].trim()) {
+            Some(v) => out.push_str(&v),
+            None => {
+                errors.push(format!("unresolved placeholder {placeholder}"));
+                out.push_str(placeholder);
+            }
+        }
+        rest = &rest[start + len + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+impl PromptTemplate {
+    /// Substitute `{{name}}` placeholders. Every declared variable must be
+    /// given a value.
+    pub fn render(&self, vars: &BTreeMap<&str, String>) -> Result<String, String> {
+        let mut errors = Vec::new();
+        for v in &self.variables {
+            if !vars.contains_key(v.as_str()) {
+                errors.push(format!("{v}: missing value"));
+            }
+        }
+        let out = fill_placeholders(&self.template, |key| vars.get(key).cloned(), &mut errors);
+        if !errors.is_empty() {
+            return Err(format!("prompt {}: {}", self.id, errors.join("; ")));
+        }
+        Ok(out)
+    }
+
+    /// Same prompt as `other`, whatever the version numbers.
+    fn same_content(&self, other: &PromptTemplate) -> bool {
+        PromptTemplate {
+            version: other.version,
+            ..self.clone()
+        } == *other
+    }
+}
+
+/// `id` trimmed, if it is usable as a file name.
+fn check_id(id: &str) -> Result<&str, String> {
+    let id = id.trim();
+    if id.is_empty() || id.contains(['/', '\\', '.']) {
+        return Err(format!("invalid prompt id: {id:?}"));
+    }
+    Ok(id)
+}
+
+fn builtin(id: &str) -> Option<PromptTemplate> {
+    BUILTIN_PROMPTS
+        .iter()
+        .find(|(builtin_id, _)| *builtin_id == id)
+        .and_then(|(_, yaml)| serde_yaml::from_str(yaml).ok())
+}
+
+fn read_prompt(path: &Path) -> Option<PromptTemplate> {
+    let text = fs::read_to_string(path).ok()?;
+    match serde_yaml::from_str(&text) {
+        Ok(t) => Some(t),
+        Err(e) => {
+            log::warn!("ignoring prompt template {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+fn write_prompt(path: &Path, template: &PromptTemplate) -> Result<(), String> {
+    let yaml = serde_yaml::to_string(template).map_err(|e| e.to_string())?;
+    fs::write(path, yaml).map_err(|e| e.to_string())
+}
+
+/// Write the built-in prompts into `dir` unless a file with the same id exists.
+pub fn install_builtin_prompts(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    for (id, yaml) in BUILTIN_PROMPTS {
+        let path = dir.join(format!("{id}.yaml"));
+        if !path.exists() {
+            fs::write(&path, yaml).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// The prompt `id`: the world override in `world_dir` if there is one, then
+/// the (possibly edited) copy in `dir`, then the built-in default.
+pub fn resolve_prompt(
+    dir: &Path,
+    world_dir: Option<&Path>,
+    id: &str,
+) -> Result<ResolvedPrompt, String> {
+    if let Some(world_dir) = world_dir {
+        if let Some(template) = read_prompt(&world_dir.join(format!("{id}.yaml"))) {
+            return Ok(ResolvedPrompt {
+                template,
+                source: PromptSource::World,
+            });
+        }
+    }
+    let default = builtin(id);
+    if let Some(template) = read_prompt(&dir.join(format!("{id}.yaml"))) {
+        let source = if default.as_ref().is_some_and(|d| d.same_content(&template)) {
+            PromptSource::Builtin
+        } else {
+            PromptSource::Custom
+        };
+        return Ok(ResolvedPrompt { template, source });
+    }
+    default
+        .map(|template| ResolvedPrompt {
+            template,
+            source: PromptSource::Builtin,
+        })
+        .ok_or_else(|| format!("prompt template not found: {id}"))
+}
+
+/// Highest version of `id` kept under `dir/history`, or 0.
+fn latest_history_version(dir: &Path, id: &str) -> u32 {
+    let prefix = format!("{id}.v");
+    fs::read_dir(dir.join("history"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_prefix(&prefix)?
+                .strip_suffix(".yaml")?
+                .parse()
+                .ok()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Store `template` in `dir` as the next version of its id. The replaced
+/// version is kept under `history/`.
+pub fn save_prompt(dir: &Path, mut template: PromptTemplate) -> Result<PromptTemplate, String> {
+    let id = check_id(&template.id)?.to_string();
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let path = dir.join(format!("{id}.yaml"));
+    let previous = read_prompt(&path).or_else(|| builtin(&id));
+    if let Some(prev) = &previous {
+        let history = dir.join("history");
+        fs::create_dir_all(&history).map_err(|e| e.to_string())?;
+        write_prompt(&history.join(format!("{id}.v{}.yaml", prev.version)), prev)?;
+    }
+    // Versions keep counting up across resets, so history is never overwritten.
+    let latest = previous.map_or(0, |p| p.version);
+    template.version = latest.max(latest_history_version(dir, &id)) + 1;
+    template.id = id;
+    write_prompt(&path, &template)?;
+    Ok(template)
+}
+
+/// Restore the built-in version of `id` in `dir`, saved as a new version.
+pub fn reset_prompt(dir: &Path, id: &str) -> Result<PromptTemplate, String> {
+    let id = check_id(id)?;
+    let default = builtin(id).ok_or_else(|| format!("no built-in prompt: {id}"))?;
+    match read_prompt(&dir.join(format!("{id}.yaml"))) {
+        Some(current) if current.same_content(&default) => Ok(current),
+        _ => save_prompt(dir, default),
+    }
+}
+
+/// Drop the override of `id` in `world_dir`, if there is one.
+pub fn remove_prompt_override(world_dir: &Path, id: &str) -> Result<(), String> {
+    let path = world_dir.join(format!("{}.yaml", check_id(id)?));
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Append a line to `usage.log` in `dir` noting which prompt version a call used.
+///
+/// Past `USAGE_LOG_MAX_BYTES` the log is cut back to its newest entries.
+pub fn record_prompt_use(
+    dir: &Path,
+    prompt: &ResolvedPrompt,
+    feature: &str,
+    world: Option<&str>,
+) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let path = dir.join("usage.log");
+    if fs::metadata(&path).is_ok_and(|m| m.len() > USAGE_LOG_MAX_BYTES) {
+        trim_usage_log(&path)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| e.to_string())?;
+    let entry = json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "feature": feature,
+        "id": prompt.template.id,
+        "version": prompt.template.version,
+        "source": prompt.source,
+        "world": world,
+    });
+    writeln!(file, "{entry}").map_err(|e| e.to_string())
+}
+
+fn trim_usage_log(path: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let lines: Vec<&str> = text.lines().collect();
+    let mut kept = lines[lines.len().saturating_sub(USAGE_LOG_KEEP)..].join("\n");
+    kept.push('\n');
+    fs::write(path, kept).map_err(|e| e.to_string())
+}
+
+pub fn prompt_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|_| "app data dir".to_string())?
+        .join("prompts");
+    install_builtin_prompts(&dir)?;
+    Ok(dir)
+}
+
+fn world_prompt_dir<R: Runtime>(app: &AppHandle<R>, world: &str) -> Result<PathBuf, String> {
+    if world.is_empty() || world.contains(['/', '\\']) || world.starts_with('.') {
+        return Err(format!("invalid world: {world:?}"));
+    }
+    Ok(app
+        .path()
+        .app_data_dir()
+        .map_err(|_| "app data dir".to_string())?
+        .join("worlds")
+        .join(world)
+        .join("prompts"))
+}
+
+/// Resolve and render prompt `id` for `feature`, recording the version used.
+pub fn render_prompt<R: Runtime>(
+    app: &AppHandle<R>,
+    id: &str,
+    feature: &str,
+    world: Option<&str>,
+    vars: &BTreeMap<&str, String>,
+) -> Result<String, String> {
+    let dir = prompt_dir(app)?;
+    let world_dir = match world {
+        Some(w) => Some(world_prompt_dir(app, w)?),
+        None => None,
+    };
+    let prompt = resolve_prompt(&dir, world_dir.as_deref(), id)?;
+    let text = prompt.template.render(vars)?;
+    if let Err(e) = record_prompt_use(&dir, &prompt, feature, world) {
+        log::warn!("could not record prompt use: {e}");
+    }
+    Ok(text)
+}
+
+#[tauri::command]
+pub async fn list_prompt_templates<R: Runtime>(
+    app: AppHandle<R>,
+    world: Option<String>,
+) -> Result<Vec<ResolvedPrompt>, String> {
+    let dir = prompt_dir(&app)?;
+    let world_dir = match &world {
+        Some(w) => Some(world_prompt_dir(&app, w)?),
+        None => None,
+    };
+    let mut ids: Vec<String> = BUILTIN_PROMPTS
+        .iter()
+        .map(|(id, _)| id.to_string())
+        .collect();
+    for entry in fs::read_dir(&dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|s| s.to_str()) == Some("yaml") {
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                if !ids.iter().any(|id| id == stem) {
+                    ids.push(stem.to_string());
+                }
+            }
+        }
+    }
+    ids.sort();
+    ids.iter()
+        .map(|id| resolve_prompt(&dir, world_dir.as_deref(), id))
+        .collect()
+}
+
+#[tauri::command]
+pub async fn get_prompt_template<R: Runtime>(
+    app: AppHandle<R>,
+    id: String,
+    world: Option<String>,
+) -> Result<ResolvedPrompt, String> {
+    let dir = prompt_dir(&app)?;
+    let world_dir = match &world {
+        Some(w) => Some(world_prompt_dir(&app, w)?),
+        None => None,
+    };
+    resolve_prompt(&dir, world_dir.as_deref(), &id)
+}
+
+/// Save a new version of a prompt, for all worlds or as an override for `world`.
+#[tauri::command]
+pub async fn save_prompt_template<R: Runtime>(
+    app: AppHandle<R>,
+    template: PromptTemplate,
+    world: Option<String>,
+) -> Result<PromptTemplate, String> {
+    let dir = match &world {
+        Some(w) => world_prompt_dir(&app, w)?,
+        None => prompt_dir(&app)?,
+    };
+    save_prompt(&dir, template)
+}
+
+/// Restore the built-in prompt, or drop the override for `world`.
+#[tauri::command]
+pub async fn reset_prompt_template<R: Runtime>(
+    app: AppHandle<R>,
+    id: String,
+    world: Option<String>,
+) -> Result<(), String> {
+    match world {
+        Some(w) => remove_prompt_override(&world_prompt_dir(&app, &w)?, &id),
+        None => reset_prompt(&prompt_dir(&app)?, &id).map(|_| ()),
+    }
+}
+
+#[tauri::command]
+pub async fn read_prompt_usage<R: Runtime>(
+    app: AppHandle<R>,
+    limit: Option<usize>,
+) -> Result<Vec<Value>, String> {
+    let path = prompt_dir(&app)?.join("usage.log");
+    let file = match fs::File::open(&path) {
+        Ok(f) => f,
+        Err(_) => return Ok(vec![]),
+    };
+    let mut entries: Vec<Value> = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|l| serde_json::from_str(&l).ok())
+        .collect();
+    if let Some(lim) = limit {
+        if entries.len() > lim {
+            entries = entries[entries.len() - lim..].to_vec();
+        }
+    }
+    Ok(entries)
+}
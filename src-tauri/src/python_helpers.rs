@@ -1,4 +1,4 @@
-use std::{fs, path::PathBuf};
+use std::{collections::HashMap, fs, path::PathBuf};
 
 use dirs;
 use serde::{Deserialize, Serialize};
@@ -11,6 +11,20 @@ pub struct AppConfig {
     pub comfy_path: Option<String>,
     pub sfz_convert_on_start: Option<bool>,
     pub sfz_out_dir: Option<String>,
+    pub default_model: Option<String>,
+    pub chat_model: Option<String>,
+    pub npc_event_model: Option<String>,
+    pub intent_model: Option<String>,
+    pub llm_backend: Option<String>,
+    pub openai_base_url: Option<String>,
+    pub openai_api_key: Option<String>,
+    /// Context window in tokens, keyed by model name.
+    pub context_budgets: Option<HashMap<String, usize>>,
+    pub intents: Option<crate::intent::IntentSettings>,
+    /// Ollama model for vault embeddings, or `hash` for the offline embedding.
+    pub embedding_model: Option<String>,
+    pub hybrid_search: Option<crate::search::HybridWeights>,
+    pub search_diversity: Option<crate::search::Diversity>,
 }
 
 fn config_path() -> PathBuf {
@@ -30,7 +44,7 @@ fn load_config() -> AppConfig {
     }
 }
 
-fn save_config(cfg: &AppConfig) -> Result<(), String> {
+pub fn save_config(cfg: &AppConfig) -> Result<(), String> {
     let path = config_path();
     let data = serde_json::to_string_pretty(cfg).map_err(|e| e.to_string())?;
     fs::write(path, data).map_err(|e| e.to_string())
@@ -0,0 +1,626 @@
+//! Typed schemas for saved NPC, spell, rule and lore records.
+//!
+//! Records are stored as JSON files and checked against the JSON schema of
+//! their type before saving, so errors name the fields at fault. Fields the
+//! types don't know are kept as they are. Files written before
+//! `schema_version` existed are upgraded when they are loaded.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::structured::schema_of;
+
+/// Version written into every saved record.
+///
+/// 0. Unversioned: tags may be a comma-separated string, lists may be
+///    `null`, spell levels and components are free text, and parsed spells
+///    and rules have no `id`.
+/// 1. The types below.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    Npc,
+    Spell,
+    Rule,
+    Lore,
+}
+
+impl RecordKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            RecordKind::Npc => "NPC",
+            RecordKind::Spell => "spell",
+            RecordKind::Rule => "rule",
+            RecordKind::Lore => "lore entry",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Alignment {
+    LawfulGood,
+    NeutralGood,
+    ChaoticGood,
+    LawfulNeutral,
+    Neutral,
+    ChaoticNeutral,
+    LawfulEvil,
+    NeutralEvil,
+    ChaoticEvil,
+    Unaligned,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SpellSchool {
+    Abjuration,
+    Conjuration,
+    Divination,
+    Enchantment,
+    Evocation,
+    Illusion,
+    Necromancy,
+    Transmutation,
+}
+
+/// Verbal, somatic and material spell components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Component {
+    V,
+    S,
+    M,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Npc {
+    #[serde(default)]
+    pub schema_version: u32,
+    #[schemars(length(min = 1))]
+    pub id: String,
+    #[schemars(length(min = 1))]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub species: Option<String>,
+    pub role: Option<String>,
+    pub alignment: Option<Alignment>,
+    pub location: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Image paths, filled in by `save_npc`.
+    pub portrait: Option<String>,
+    pub icon: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Spell {
+    #[serde(default)]
+    pub schema_version: u32,
+    #[schemars(length(min = 1))]
+    pub id: String,
+    #[schemars(length(min = 1))]
+    pub name: String,
+    pub description: String,
+    /// 0 for cantrips.
+    #[serde(default)]
+    #[schemars(range(max = 9))]
+    pub level: u8,
+    pub school: Option<SpellSchool>,
+    pub casting_time: Option<String>,
+    pub range: Option<String>,
+    pub duration: Option<String>,
+    #[serde(default)]
+    pub components: Vec<Component>,
+    /// What the `M` component consumes or needs.
+    pub material: Option<String>,
+    #[serde(default)]
+    pub concentration: bool,
+    #[serde(default)]
+    pub ritual: bool,
+    #[serde(default)]
+    pub classes: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Rule {
+    #[serde(default)]
+    pub schema_version: u32,
+    #[schemars(length(min = 1))]
+    pub id: String,
+    #[schemars(length(min = 1))]
+    pub name: String,
+    pub description: String,
+    /// E.g. `combat` or `conditions`.
+    pub category: Option<String>,
+    /// Book or document the rule comes from.
+    pub source: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Lore {
+    #[serde(default)]
+    pub schema_version: u32,
+    #[schemars(length(min = 1))]
+    pub id: String,
+    #[schemars(length(min = 1))]
+    pub name: String,
+    #[serde(default)]
+    pub summary: String,
+    pub location: Option<String>,
+    #[serde(default)]
+    pub hooks: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Further headed sections, by heading.
+    #[serde(default)]
+    pub sections: BTreeMap<String, String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A field that doesn't match its record's schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    /// Path to the field, e.g. `level` or `components[1]`.
+    pub field: String,
+    pub message: String,
+}
+
+/// Lowercase id from a name: `Wall of Force` becomes `wall-of-force`.
+pub fn slug(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            out.push(c);
+        } else if !out.is_empty() && !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+    out.trim_end_matches('-').to_string()
+}
+
+fn split_list(s: &str) -> Vec<Value> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(|t| Value::String(t.to_string()))
+        .collect()
+}
+
+fn snake(s: &str) -> String {
+    s.trim()
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Version 0 to 1.
+fn upgrade_v0(kind: RecordKind, map: &mut Map<String, Value>) {
+    let id_missing = map
+        .get("id")
+        .and_then(Value::as_str)
+        .is_none_or(|id| id.trim().is_empty());
+    if id_missing {
+        if let Some(name) = map.get("name").and_then(Value::as_str) {
+            let id = slug(name);
+            map.insert("id".into(), Value::String(id));
+        }
+    }
+    for key in ["tags", "hooks", "classes"] {
+        if let Some(Value::String(s)) = map.get(key) {
+            let list = split_list(s);
+            map.insert(key.into(), Value::Array(list));
+        }
+    }
+    match kind {
+        RecordKind::Npc => {
+            if let Some(Value::String(a)) = map.get("alignment") {
+                let alignment = match a.trim().to_uppercase().as_str() {
+                    "LG" => "lawful_good".into(),
+                    "NG" => "neutral_good".into(),
+                    "CG" => "chaotic_good".into(),
+                    "LN" => "lawful_neutral".into(),
+                    "N" | "TN" | "TRUE NEUTRAL" => "neutral".into(),
+                    "CN" => "chaotic_neutral".into(),
+                    "LE" => "lawful_evil".into(),
+                    "NE" => "neutral_evil".into(),
+                    "CE" => "chaotic_evil".into(),
+                    _ => snake(a),
+                };
+                map.insert("alignment".into(), Value::String(alignment));
+            }
+        }
+        RecordKind::Spell => {
+            if let Some(Value::String(level)) = map.get("level") {
+                let digits: String = level
+                    .trim()
+                    .chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect();
+                let level = if level.trim().eq_ignore_ascii_case("cantrip") {
+                    Some(0)
+                } else {
+                    digits.parse::<u64>().ok()
+                };
+                if let Some(level) = level {
+                    map.insert("level".into(), Value::from(level));
+                }
+            }
+            if let Some(Value::String(school)) = map.get("school") {
+                let school = snake(school);
+                map.insert("school".into(), Value::String(school));
+            }
+            if let Some(Value::String(text)) = map.get("components").cloned() {
+                // `V, S, M (a pinch of sulfur)`
+                let (letters, material) = match text.split_once('(') {
+                    Some((l, m)) => (l, Some(m.trim_end().trim_end_matches(')').trim())),
+                    None => (text.as_str(), None),
+                };
+                let components: Vec<Value> = letters
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .map(|c| c.trim().to_uppercase())
+                    .filter(|c| !c.is_empty())
+                    .map(Value::String)
+                    .collect();
+                map.insert("components".into(), Value::Array(components));
+                if let Some(m) = material.filter(|m| !m.is_empty()) {
+                    map.entry("material")
+                        .or_insert_with(|| Value::String(m.to_string()));
+                }
+            }
+        }
+        RecordKind::Rule | RecordKind::Lore => {}
+    }
+}
+
+/// Upgrade `record` to [`SCHEMA_VERSION`]. Records from a newer version and
+/// values that aren't objects are returned unchanged.
+pub fn migrate(kind: RecordKind, mut record: Value) -> Value {
+    let Some(map) = record.as_object_mut() else {
+        return record;
+    };
+    let version = map
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    if version >= SCHEMA_VERSION as u64 {
+        return record;
+    }
+    if version == 0 {
+        upgrade_v0(kind, map);
+    }
+    map.insert("schema_version".into(), Value::from(SCHEMA_VERSION));
+    record
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("\"{s}\""),
+        other => other.to_string(),
+    }
+}
+
+fn push(errors: &mut Vec<FieldError>, field: &str, message: String) {
+    errors.push(FieldError {
+        field: if field.is_empty() {
+            "record".into()
+        } else {
+            field.into()
+        },
+        message,
+    });
+}
+
+/// Check `value` against the subset of JSON schema `schemars` produces for
+/// the record types, collecting one error per field.
+fn check(schema: &Value, root: &Value, value: &Value, field: &str, errors: &mut Vec<FieldError>) {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let name = reference.rsplit('/').next().unwrap_or_default();
+        return check(&root["definitions"][name], root, value, field, errors);
+    }
+    if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+        for s in all {
+            check(s, root, value, field, errors);
+        }
+        return;
+    }
+    if let Some(any) = schema
+        .get("anyOf")
+        .or_else(|| schema.get("oneOf"))
+        .and_then(Value::as_array)
+    {
+        let mut first = None;
+        for s in any {
+            let mut errs = Vec::new();
+            check(s, root, value, field, &mut errs);
+            if errs.is_empty() {
+                return;
+            }
+            // Report against the branch that isn't just `null`.
+            if first.is_none() && s.get("type").and_then(Value::as_str) != Some("null") {
+                first = Some(errs);
+            }
+        }
+        errors.extend(first.unwrap_or_default());
+        return;
+    }
+    if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+        if !options.contains(value) {
+            let names: Vec<String> = options.iter().map(describe).collect();
+            let message = format!("{} is not one of {}", describe(value), names.join(", "));
+            push(errors, field, message);
+        }
+        return;
+    }
+    let allowed: Vec<&str> = match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(ts)) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let actual = json_type(value);
+    if !allowed.is_empty()
+        && !allowed
+            .iter()
+            .any(|t| *t == actual || (*t == "number" && actual == "integer"))
+    {
+        let message = format!("expected {}, got {actual}", allowed.join(" or "));
+        return push(errors, field, message);
+    }
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+            if n < min {
+                push(errors, field, format!("must be at least {min}"));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+            if n > max {
+                push(errors, field, format!("must be at most {max}"));
+            }
+        }
+    }
+    if let (Some(s), Some(min)) = (
+        value.as_str(),
+        schema.get("minLength").and_then(Value::as_u64),
+    ) {
+        if (s.trim().chars().count() as u64) < min {
+            push(errors, field, "must not be empty".into());
+        }
+    }
+    if let (Some(items), Some(list)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in list.iter().enumerate() {
+            check(items, root, item, &format!("{field}[{i}]"), errors);
+        }
+    }
+    let Some(map) = value.as_object() else {
+        return;
+    };
+    let join = |key: &str| {
+        if field.is_empty() {
+            key.to_string()
+        } else {
+            format!("{field}.{key}")
+        }
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    for key in &required {
+        if map.get(*key).is_none_or(Value::is_null) {
+            push(errors, &join(key), "is required".into());
+        }
+    }
+    let properties = schema.get("properties");
+    for (key, item) in map {
+        if item.is_null() && required.contains(&key.as_str()) {
+            continue;
+        }
+        match properties.and_then(|p| p.get(key)) {
+            Some(s) => check(s, root, item, &join(key), errors),
+            None => {
+                if let Some(extra) = schema.get("additionalProperties").filter(|a| a.is_object()) {
+                    check(extra, root, item, &join(key), errors);
+                }
+            }
+        }
+    }
+}
+
+/// Top-level `null`s mean "not set", so defaulted fields accept them too.
+fn drop_nulls(mut record: Value) -> Value {
+    if let Some(map) = record.as_object_mut() {
+        map.retain(|_, v| !v.is_null());
+    }
+    record
+}
+
+fn schema(kind: RecordKind) -> Value {
+    match kind {
+        RecordKind::Npc => schema_of::<Npc>(),
+        RecordKind::Spell => schema_of::<Spell>(),
+        RecordKind::Rule => schema_of::<Rule>(),
+        RecordKind::Lore => schema_of::<Lore>(),
+    }
+}
+
+/// Fields of `record` that don't match `kind`'s schema; empty when it's
+/// valid. The record is checked as given, without migrating it.
+pub fn validate(kind: RecordKind, record: &Value) -> Vec<FieldError> {
+    let schema = schema(kind);
+    let mut errors = Vec::new();
+    check(&schema, &schema, record, "", &mut errors);
+    errors
+}
+
+fn round_trip<T: Serialize + DeserializeOwned>(record: Value) -> Result<Value, String> {
+    let typed: T = serde_json::from_value(record).map_err(|e| e.to_string())?;
+    serde_json::to_value(typed).map_err(|e| e.to_string())
+}
+
+/// Why a record was rejected. `fields` lists the fields at fault for forms
+/// to show next to each one, and is empty for errors about the record as a
+/// whole, such as `exists` when saving over another record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordError {
+    pub message: String,
+    pub fields: Vec<FieldError>,
+}
+
+impl From<String> for RecordError {
+    fn from(message: String) -> Self {
+        RecordError {
+            message,
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// Migrate and validate `record`, filling in defaults. The error's message
+/// lists every field at fault, e.g. `invalid spell: level: must be at most 9`.
+pub fn parse_record(kind: RecordKind, record: Value) -> Result<Value, RecordError> {
+    let record = drop_nulls(migrate(kind, record));
+    let fields = validate(kind, &record);
+    if !fields.is_empty() {
+        let list: Vec<String> = fields
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect();
+        return Err(RecordError {
+            message: format!("invalid {}: {}", kind.label(), list.join("; ")),
+            fields,
+        });
+    }
+    match kind {
+        RecordKind::Npc => round_trip::<Npc>(record),
+        RecordKind::Spell => round_trip::<Spell>(record),
+        RecordKind::Rule => round_trip::<Rule>(record),
+        RecordKind::Lore => round_trip::<Lore>(record),
+    }
+    .map_err(|e| format!("invalid {}: {e}", kind.label()).into())
+}
+
+/// [`parse_record`] with just the error's message.
+pub fn parse(kind: RecordKind, record: Value) -> Result<Value, String> {
+    parse_record(kind, record).map_err(|e| e.message)
+}
+
+/// [`parse_record`] into the record's type.
+pub fn parse_as<T: DeserializeOwned>(kind: RecordKind, record: Value) -> Result<T, RecordError> {
+    serde_json::from_value(parse_record(kind, record)?).map_err(|e| e.to_string().into())
+}
+
+/// A record of a batch that [`parse_batch`] left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectedRecord {
+    /// Position in the batch.
+    pub index: usize,
+    pub name: Option<String>,
+    pub error: RecordError,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParsedBatch<T> {
+    pub records: Vec<T>,
+    pub rejected: Vec<RejectedRecord>,
+}
+
+/// [`parse_as`] each of `records`, keeping the valid ones so that one bad
+/// record doesn't lose the rest of an import.
+pub fn parse_batch<T: DeserializeOwned>(kind: RecordKind, records: Vec<Value>) -> ParsedBatch<T> {
+    let mut batch = ParsedBatch {
+        records: Vec::new(),
+        rejected: Vec::new(),
+    };
+    for (index, record) in records.into_iter().enumerate() {
+        let name = record["name"].as_str().map(str::to_string);
+        match parse_as(kind, record) {
+            Ok(record) => batch.records.push(record),
+            Err(error) => {
+                log::warn!("skipping {} {index}: {}", kind.label(), error.message);
+                batch.rejected.push(RejectedRecord { index, name, error });
+            }
+        }
+    }
+    batch
+}
+
+/// The id of a parsed record, safe to use as a file name.
+pub fn record_id(record: &Value) -> Result<String, String> {
+    let id = record["id"].as_str().unwrap_or_default();
+    if id.contains(['/', '\\']) || id.starts_with('.') {
+        return Err(format!("invalid id: {id}"));
+    }
+    Ok(id.to_string())
+}
+
+/// Read a saved record, migrated to [`SCHEMA_VERSION`] if it is older. The
+/// file itself is never changed; saving the record writes the new version.
+pub fn load(kind: RecordKind, path: &Path) -> Result<Value, String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let record: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+    let version = record["schema_version"].as_u64().unwrap_or(0);
+    if version >= SCHEMA_VERSION as u64 {
+        return Ok(record);
+    }
+    parse(kind, record.clone()).or_else(|e| {
+        log::warn!("{} does not validate after migrating: {e}", path.display());
+        Ok(migrate(kind, record))
+    })
+}
+
+/// Every record in `dir`, skipping `index.json`.
+pub fn load_dir(kind: RecordKind, dir: &Path) -> Result<Vec<Value>, String> {
+    let mut records = Vec::new();
+    if !dir.exists() {
+        return Ok(records);
+    }
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|s| s.to_str()) == Some("json")
+            && path.file_name().and_then(|s| s.to_str()) != Some("index.json")
+        {
+            records.push(load(kind, &path)?);
+        }
+    }
+    Ok(records)
+}
+
+/// Fields of `record` that don't match `kind`'s schema, after migrating it,
+/// for forms to show next to each field.
+#[tauri::command]
+pub fn validate_record(kind: RecordKind, record: Value) -> Vec<FieldError> {
+    validate(kind, &drop_nulls(migrate(kind, record)))
+}
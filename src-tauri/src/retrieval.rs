@@ -0,0 +1,386 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Runtime};
+
+use crate::commands::{self, hash_embed, ChatMessage};
+use crate::context;
+use crate::llm;
+use crate::ollama::{self, ModelFeature};
+use crate::search;
+
+/// Hits taken from each source before merging.
+const PER_SOURCE: u32 = 4;
+
+/// Longest passage taken from a saved record (rule, spell, lore, NPC).
+const RECORD_PASSAGE_TOKENS: usize = 200;
+
+/// Word overlap above which two passages count as the same text.
+const DUPLICATE_OVERLAP: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    Pdf,
+    Vault,
+    Rule,
+    Spell,
+    Lore,
+    Npc,
+}
+
+impl SourceKind {
+    fn label(self) -> &'static str {
+        match self {
+            SourceKind::Pdf => "pdf",
+            SourceKind::Vault => "vault",
+            SourceKind::Rule => "rule",
+            SourceKind::Spell => "spell",
+            SourceKind::Lore => "lore",
+            SourceKind::Npc => "npc",
+        }
+    }
+}
+
+/// A piece of retrieved text and where it came from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Passage {
+    /// Citation marker used in the prompt and the answer, e.g. `S1`.
+    pub id: String,
+    pub source: SourceKind,
+    /// Document, note or record id.
+    pub source_id: String,
+    pub title: Option<String>,
+    /// First and last page, for paged sources.
+    pub pages: Option<[u32; 2]>,
+    pub text: String,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CitedAnswer {
+    pub content: String,
+    /// Passages the answer refers to by marker.
+    pub citations: Vec<Passage>,
+}
+
+/// Sources searched for `intent`, most relevant first. Unknown intents
+/// search everything.
+pub fn sources_for(intent: &str) -> Vec<SourceKind> {
+    use SourceKind::*;
+    match intent {
+        "rules" => vec![Pdf, Rule, Spell, Vault],
+        "npc" => vec![Npc, Lore, Vault, Pdf],
+        "lore" => vec![Lore, Npc, Vault, Pdf],
+        "notes" => vec![Vault, Lore],
+        _ => vec![Pdf, Rule, Spell, Lore, Npc, Vault],
+    }
+}
+
+/// All string values in `v`, space separated.
+pub fn record_text(v: &Value) -> String {
+    fn walk(v: &Value, out: &mut Vec<String>) {
+        match v {
+            Value::String(s) if !s.trim().is_empty() => out.push(s.trim().to_string()),
+            Value::Array(items) => items.iter().for_each(|i| walk(i, out)),
+            Value::Object(map) => {
+                for (key, value) in map {
+                    // Paths to portraits and the like are noise for retrieval.
+                    if matches!(key.as_str(), "id" | "portrait" | "icon" | "path") {
+                        continue;
+                    }
+                    walk(value, out);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut out = Vec::new();
+    walk(v, &mut out);
+    out.join(" ")
+}
+
+/// Best `k` saved records for `query`, by similarity of their text.
+pub fn rank_records(kind: SourceKind, records: &[Value], query: &str, k: usize) -> Vec<Passage> {
+    let qvec = hash_embed(query);
+    let mut passages: Vec<Passage> = records
+        .iter()
+        .filter_map(|r| {
+            let text = record_text(r);
+            let score: f32 = qvec.iter().zip(hash_embed(&text)).map(|(a, b)| a * b).sum();
+            if score <= 0.0 {
+                return None;
+            }
+            let title = ["name", "title"]
+                .iter()
+                .find_map(|k| r[*k].as_str())
+                .map(|s| s.to_string());
+            Some(Passage {
+                id: String::new(),
+                source: kind,
+                source_id: r["id"]
+                    .as_str()
+                    .map(|s| s.to_string())
+                    .or_else(|| title.clone())
+                    .unwrap_or_default(),
+                title,
+                pages: None,
+                text: context::trim_to_tokens(&text, RECORD_PASSAGE_TOKENS),
+                score,
+            })
+        })
+        .collect();
+    passages.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    passages.truncate(k);
+    passages
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+fn is_duplicate(a: &Passage, b: &Passage) -> bool {
+    if a.source == b.source && a.source_id == b.source_id {
+        match (a.pages, b.pages) {
+            (Some([a0, a1]), Some([b0, b1])) if a0 <= b1 && b0 <= a1 => return true,
+            (None, None) => return true,
+            _ => {}
+        }
+    }
+    let (wa, wb) = (words(&a.text), words(&b.text));
+    let smaller = wa.len().min(wb.len());
+    if smaller == 0 {
+        return false;
+    }
+    wa.intersection(&wb).count() as f64 / smaller as f64 >= DUPLICATE_OVERLAP
+}
+
+/// Merge per-source hit lists into one list of passages within
+/// `budget_tokens`.
+///
+/// Sources take turns in the order given, each contributing its next best
+/// hit, so no single source crowds out the others. Passages overlapping one
+/// already taken (same document and pages, or mostly the same words) are
+/// skipped. Kept passages are numbered `S1`, `S2`, ...
+pub fn merge_passages(groups: Vec<Vec<Passage>>, budget_tokens: usize) -> Vec<Passage> {
+    let mut iters: Vec<_> = groups.into_iter().map(|g| g.into_iter()).collect();
+    let mut merged: Vec<Passage> = Vec::new();
+    let mut used = 0;
+    loop {
+        let mut progressed = false;
+        for it in iters.iter_mut() {
+            let Some(p) = it.next() else { continue };
+            progressed = true;
+            if merged.iter().any(|m| is_duplicate(m, &p)) {
+                continue;
+            }
+            let tokens = context::estimate_tokens(&p.text) + 8;
+            if used + tokens > budget_tokens {
+                continue;
+            }
+            used += tokens;
+            merged.push(p);
+        }
+        if !progressed {
+            break;
+        }
+    }
+    for (i, p) in merged.iter_mut().enumerate() {
+        p.id = format!("S{}", i + 1);
+    }
+    merged
+}
+
+fn describe(p: &Passage) -> String {
+    let mut label = format!(
+        "{}: {}",
+        p.source.label(),
+        p.title.as_deref().unwrap_or(&p.source_id)
+    );
+    if let Some([start, end]) = p.pages {
+        if start == end {
+            label.push_str(&format!(" p.{start}"));
+        } else {
+            label.push_str(&format!(" p.{start}-{end}"));
+        }
+    }
+    label
+}
+
+/// Passages as prompt text, one `[S1] (source) text` line each.
+pub fn format_context(passages: &[Passage]) -> String {
+    if passages.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("Sources:\n");
+    for p in passages {
+        out.push_str(&format!("[{}] ({}) {}\n", p.id, describe(p), p.text.trim()));
+    }
+    out
+}
+
+/// Passages whose marker appears in `answer`, in marker order.
+pub fn cited_passages(answer: &str, passages: &[Passage]) -> Vec<Passage> {
+    passages
+        .iter()
+        .filter(|p| answer.contains(&format!("[{}]", p.id)))
+        .cloned()
+        .collect()
+}
+
+/// Best hits of one source. PDF and vault chunks are re-ranked with the
+/// configured `search_diversity`, so neighbouring pages of one document
+/// don't fill the context.
+async fn search_source<R: Runtime>(
+    app: &AppHandle<R>,
+    kind: SourceKind,
+    query: &str,
+    world: Option<&str>,
+) -> Result<Vec<Passage>, String> {
+    let k = PER_SOURCE as usize;
+    let passages = match kind {
+        SourceKind::Pdf => commands::pdf_search(
+            app.clone(),
+            query.into(),
+            Some(PER_SOURCE),
+            None,
+            Some(search::diversity()),
+        )
+        .await?
+        .into_iter()
+        .map(|h| Passage {
+            id: String::new(),
+            source: kind,
+            title: None,
+            source_id: h.doc_id,
+            pages: Some(h.page_range),
+            text: h.text,
+            score: h.score,
+        })
+        .collect(),
+        SourceKind::Vault => {
+            let page = commands::vault_search(
+                app.clone(),
+                query.into(),
+                None,
+                None,
+                Some(PER_SOURCE),
+                None,
+                None,
+                Some(search::diversity()),
+            )
+            .await?;
+            page.hits
+                .into_iter()
+                .map(|h| Passage {
+                    id: String::new(),
+                    source: kind,
+                    title: None,
+                    source_id: h.doc_id,
+                    pages: Some(h.page_range),
+                    text: h.text,
+                    score: h.score,
+                })
+                .collect()
+        }
+        SourceKind::Rule => rank_records(kind, &commands::list_rules(app.clone()).await?, query, k),
+        SourceKind::Spell => {
+            rank_records(kind, &commands::list_spells(app.clone()).await?, query, k)
+        }
+        SourceKind::Lore | SourceKind::Npc => {
+            let Some(world) = world else {
+                return Ok(Vec::new());
+            };
+            let records = if kind == SourceKind::Lore {
+                commands::list_lore(app.clone(), world.into()).await?
+            } else {
+                commands::list_npcs(app.clone(), world.into()).await?
+            };
+            rank_records(kind, &records, query, k)
+        }
+    };
+    Ok(passages)
+}
+
+/// Retrieval budget: a quarter of the chat model's context window.
+pub fn default_budget() -> usize {
+    context::context_budget(&ollama::model_for(ModelFeature::Chat)) / 4
+}
+
+/// Search the sources relevant to `intent` and merge the hits.
+///
+/// A source that fails (no PDF tooling, no index yet) is skipped.
+pub async fn retrieve<R: Runtime>(
+    app: &AppHandle<R>,
+    query: &str,
+    intent: &str,
+    world: Option<&str>,
+    budget_tokens: usize,
+) -> Vec<Passage> {
+    let mut groups = Vec::new();
+    for kind in sources_for(intent) {
+        match search_source(app, kind, query, world).await {
+            Ok(hits) => groups.push(hits),
+            Err(e) => log::warn!("retrieval from {} failed: {e}", kind.label()),
+        }
+    }
+    merge_passages(groups, budget_tokens)
+}
+
+#[tauri::command]
+pub async fn retrieve_passages<R: Runtime>(
+    app: AppHandle<R>,
+    query: String,
+    intent: String,
+    world: Option<String>,
+    budget: Option<usize>,
+) -> Result<Vec<Passage>, String> {
+    let budget = budget.unwrap_or_else(default_budget);
+    Ok(retrieve(&app, &query, &intent, world.as_deref(), budget).await)
+}
+
+/// Answer the last user message using retrieved passages, citing them as
+/// `[S1]`, `[S2]`, ... The cited passages are returned with the answer.
+#[tauri::command]
+pub async fn chat_with_citations<R: Runtime>(
+    app: AppHandle<R>,
+    messages: Vec<ChatMessage>,
+    intent: String,
+    world: Option<String>,
+) -> Result<CitedAnswer, String> {
+    let query = messages
+        .iter()
+        .rev()
+        .find(|m| m.role == "user")
+        .map(|m| m.content.clone())
+        .ok_or("no user message")?;
+    let passages = retrieve(&app, &query, &intent, world.as_deref(), default_budget()).await;
+    let mut msgs = messages;
+    if !passages.is_empty() {
+        let at = msgs.iter().take_while(|m| m.role == "system").count();
+        msgs.insert(
+            at,
+            ChatMessage {
+                role: "system".into(),
+                content: format!(
+                    "{}\nUse these sources where relevant and cite them inline by marker, e.g. [S1]. Do not invent sources.",
+                    format_context(&passages)
+                ),
+            },
+        );
+    }
+    let backend = llm::backend();
+    let model = ollama::model_for(ModelFeature::Chat);
+    let req = context::fit_request(backend.as_ref(), &model, msgs).await?;
+    let content = backend.chat(&req).await?;
+    let citations = cited_passages(&content, &passages);
+    Ok(CitedAnswer { content, citations })
+}
@@ -0,0 +1,899 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::Path,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    query::Query,
+    sqlite::{Sqlite, SqliteArguments},
+    Connection, Row, SqliteConnection,
+};
+
+use crate::ann::{self, VectorSource};
+use crate::commands::hash_embed;
+use crate::embeddings;
+use crate::index_format;
+use crate::ollama;
+use crate::python_helpers::get_config;
+
+/// Candidates taken from each ranking before fusing them, per result wanted.
+const FUSION_POOL: usize = 4;
+
+/// Ranked chunks a paged search pages through and counts facets over.
+pub const MAX_CANDIDATES: usize = 200;
+
+/// Longest snippet returned with a hit, in characters.
+pub const SNIPPET_CHARS: usize = 240;
+
+/// Page size when none is given.
+const DEFAULT_PAGE_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// BM25 over the chunk text.
+    Keyword,
+    /// Embedding similarity.
+    Vector,
+    /// Both rankings merged with reciprocal rank fusion.
+    #[default]
+    Hybrid,
+}
+
+/// How much each ranking counts in hybrid search. A chunk ranked `r` (from
+/// 1) in a list contributes `weight / (rrf_k + r)`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HybridWeights {
+    pub keyword: f32,
+    pub vector: f32,
+    pub rrf_k: f32,
+}
+
+impl Default for HybridWeights {
+    fn default() -> Self {
+        HybridWeights {
+            keyword: 1.0,
+            vector: 1.0,
+            rrf_k: 60.0,
+        }
+    }
+}
+
+pub fn hybrid_weights() -> HybridWeights {
+    get_config().hybrid_search.unwrap_or_default()
+}
+
+/// Re-ranking for varied results with maximal marginal relevance. Each pick
+/// maximises `lambda * relevance - (1 - lambda) * similarity` to the chunks
+/// already picked, so `1.0` keeps the retrieval order and lower values trade
+/// relevance for distinct content.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Diversity {
+    pub lambda: f32,
+    /// Most chunks kept from one document; `None` for no limit.
+    pub per_doc_cap: Option<usize>,
+}
+
+impl Default for Diversity {
+    fn default() -> Self {
+        Diversity {
+            lambda: 0.7,
+            per_doc_cap: Some(2),
+        }
+    }
+}
+
+pub fn diversity() -> Diversity {
+    get_config().search_diversity.unwrap_or_default()
+}
+
+/// Which chunks of the index to search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    All,
+    /// Chunks of PDF documents, i.e. not of vault notes.
+    Pdfs,
+    /// Chunks of vault notes.
+    Notes,
+}
+
+/// Narrows a search to some chunks. Every filter that is set must match.
+/// Tag, world and date filters only match notes, since PDFs have no front
+/// matter.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SearchFilters {
+    /// Any of these documents.
+    pub doc_ids: Vec<String>,
+    /// Documents whose id starts with this, e.g. `npcs/`.
+    pub path_prefix: Option<String>,
+    /// Notes carrying all of these tags.
+    pub tags: Vec<String>,
+    /// Notes whose `world` is this, ignoring case.
+    pub world: Option<String>,
+    /// Inclusive `YYYY-MM-DD` bounds on the note's date.
+    pub date_from: Option<String>,
+    pub date_to: Option<String>,
+    /// Inclusive bounds; a chunk matches if its pages (lines, for notes)
+    /// overlap them.
+    pub page_from: Option<u32>,
+    pub page_to: Option<u32>,
+}
+
+enum Param {
+    Text(String),
+    Int(i64),
+}
+
+/// SQL condition on an `embeddings` row aliased as `e`, with its parameters.
+struct Condition {
+    sql: String,
+    params: Vec<Param>,
+}
+
+impl Condition {
+    fn new(scope: Scope, filters: &SearchFilters) -> Self {
+        let mut clauses = vec!["1 = 1".to_string()];
+        let mut params = Vec::new();
+        match scope {
+            Scope::All => {}
+            Scope::Pdfs => clauses.push("e.doc_id NOT IN (SELECT path FROM vault_files)".into()),
+            Scope::Notes => clauses.push("e.doc_id IN (SELECT path FROM vault_files)".into()),
+        }
+        if !filters.doc_ids.is_empty() {
+            clauses.push(format!("e.doc_id IN ({})", marks(filters.doc_ids.len())));
+            params.extend(filters.doc_ids.iter().cloned().map(Param::Text));
+        }
+        if let Some(prefix) = &filters.path_prefix {
+            clauses.push("substr(e.doc_id, 1, length(?)) = ?".into());
+            params.push(Param::Text(prefix.clone()));
+            params.push(Param::Text(prefix.clone()));
+        }
+        for tag in &filters.tags {
+            clauses.push(
+                "EXISTS (SELECT 1 FROM note_tags t WHERE t.path = e.doc_id AND t.tag = ?)".into(),
+            );
+            params.push(Param::Text(tag.trim_start_matches('#').to_lowercase()));
+        }
+        let meta = [
+            ("m.world = ? COLLATE NOCASE", &filters.world),
+            ("m.date >= ?", &filters.date_from),
+            ("m.date <= ?", &filters.date_to),
+        ];
+        for (test, value) in meta {
+            if let Some(value) = value {
+                clauses.push(format!(
+                    "EXISTS (SELECT 1 FROM note_meta m WHERE m.path = e.doc_id AND {test})"
+                ));
+                params.push(Param::Text(value.clone()));
+            }
+        }
+        if let Some(from) = filters.page_from {
+            clauses.push("e.page_end >= ?".into());
+            params.push(Param::Int(from.into()));
+        }
+        if let Some(to) = filters.page_to {
+            clauses.push("e.page_start <= ?".into());
+            params.push(Param::Int(to.into()));
+        }
+        Condition {
+            sql: clauses.join(" AND "),
+            params,
+        }
+    }
+
+    fn is_trivial(&self) -> bool {
+        self.sql == "1 = 1"
+    }
+
+    fn bind<'q>(
+        &'q self,
+        mut query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    ) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+        for param in &self.params {
+            query = match param {
+                Param::Text(s) => query.bind(s.as_str()),
+                Param::Int(i) => query.bind(*i),
+            };
+        }
+        query
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ChunkHit {
+    pub chunk_id: String,
+    pub doc_id: String,
+    pub page_range: [u32; 2],
+    pub text: String,
+    pub score: f32,
+}
+
+/// How many ranked chunks fall in each document, folder, tag and world.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SearchFacets {
+    pub docs: BTreeMap<String, usize>,
+    /// Parent folder of each note, e.g. `npcs` for `npcs/strahd.md`.
+    pub folders: BTreeMap<String, usize>,
+    pub tags: BTreeMap<String, usize>,
+    pub worlds: BTreeMap<String, usize>,
+}
+
+/// Which page of results to return. A `cursor` from a previous page wins
+/// over `offset`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PageRequest {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SearchPage {
+    pub hits: Vec<ChunkHit>,
+    pub facets: SearchFacets,
+    /// Ranked chunks matching the filters, capped at [`MAX_CANDIDATES`]
+    /// (200), so a count of what can be paged through rather than of every
+    /// match.
+    pub total: usize,
+    pub offset: usize,
+    /// Pass back as `cursor` for the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Ties a cursor to the search it came from.
+fn fingerprint(
+    query: &str,
+    mode: SearchMode,
+    scope: Scope,
+    filters: &SearchFilters,
+    diversity: Option<&Diversity>,
+) -> String {
+    let key = serde_json::to_string(&(query, mode, format!("{scope:?}"), filters, diversity))
+        .unwrap_or_default();
+    format!("{:x}", Sha256::digest(key.as_bytes()))[..16].to_string()
+}
+
+fn encode_cursor(offset: usize, fingerprint: &str) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{offset}:{fingerprint}"))
+}
+
+fn decode_cursor(cursor: &str, fingerprint: &str) -> Result<usize, String> {
+    let raw = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|b| String::from_utf8(b).ok())
+        .ok_or("invalid search cursor")?;
+    let (offset, from) = raw.split_once(':').ok_or("invalid search cursor")?;
+    if from != fingerprint {
+        return Err("search cursor belongs to a different query or filters".into());
+    }
+    offset.parse().map_err(|_| "invalid search cursor".into())
+}
+
+/// FTS5 query for free text: the whole query as a phrase, or any of its
+/// words. Every term is quoted so user input can't inject FTS syntax.
+pub fn fts_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let mut terms = Vec::new();
+    if words.len() > 1 {
+        terms.push(format!("\"{}\"", words.join(" ")));
+    }
+    terms.extend(words.iter().map(|w| format!("\"{w}\"")));
+    Some(terms.join(" OR "))
+}
+
+/// A matched term inside [`Snippet::text`], as byte and as character
+/// offsets (end exclusive).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Highlight {
+    pub start: usize,
+    pub end: usize,
+    pub char_start: usize,
+    pub char_end: usize,
+}
+
+/// A short window of a hit's text and where the query's terms are in it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    pub highlights: Vec<Highlight>,
+}
+
+/// Whether `word` matches a query term. Words of four letters or more also
+/// match their inflections ("wall", "walls"), so hits found by vector
+/// search usually get highlights too.
+fn term_matches(word: &str, term: &str) -> bool {
+    if word == term {
+        return true;
+    }
+    let (short, long) = if word.len() < term.len() {
+        (word, term)
+    } else {
+        (term, word)
+    };
+    short.chars().count() >= 4 && long.starts_with(short) && long.len() - short.len() <= 3
+}
+
+/// Up to `max_chars` of `text`, with whitespace collapsed, around the part
+/// that matches the most distinct terms of `query`. Cuts are marked with `…`.
+/// Text without any match gives its start and no highlights.
+pub fn snippet(text: &str, query: &str, max_chars: usize) -> Snippet {
+    let chars: Vec<char> = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .collect();
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect();
+
+    // (first char, end char, term index) of every matching word.
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].is_alphanumeric() {
+            i += 1;
+            continue;
+        }
+        let end = (i..chars.len())
+            .find(|&j| !chars[j].is_alphanumeric())
+            .unwrap_or(chars.len());
+        let word = chars[i..end].iter().collect::<String>().to_lowercase();
+        if let Some(t) = terms.iter().position(|t| term_matches(&word, t)) {
+            matches.push((i, end, t));
+        }
+        i = end;
+    }
+
+    let lead = max_chars / 4;
+    let mut start = 0;
+    let mut best = 0;
+    for &(at, _, _) in &matches {
+        let from = at.saturating_sub(lead);
+        let mut covered: Vec<usize> = matches
+            .iter()
+            .filter(|(s, e, _)| *s >= from && *e <= from + max_chars)
+            .map(|(_, _, t)| *t)
+            .collect();
+        covered.sort_unstable();
+        covered.dedup();
+        if covered.len() > best {
+            best = covered.len();
+            start = from;
+        }
+    }
+    if chars.len() <= max_chars {
+        start = 0;
+    } else {
+        start = start.min(chars.len() - max_chars);
+        // Start on a word where one is near.
+        if start > 0 && chars[start - 1] != ' ' {
+            if let Some(space) = (start..(start + 20).min(chars.len())).find(|&j| chars[j] == ' ') {
+                start = space + 1;
+            }
+        }
+    }
+    let end = (start + max_chars).min(chars.len());
+
+    let mut out = String::new();
+    let mut char_offset = 0;
+    if start > 0 {
+        out.push('…');
+        char_offset = 1;
+    }
+    let mut highlights = Vec::new();
+    let mut byte_at = Vec::with_capacity(end - start + 1);
+    for &c in &chars[start..end] {
+        byte_at.push(out.len());
+        out.push(c);
+    }
+    byte_at.push(out.len());
+    for &(s, e, _) in &matches {
+        if s >= start && e <= end {
+            highlights.push(Highlight {
+                start: byte_at[s - start],
+                end: byte_at[e - start],
+                char_start: s - start + char_offset,
+                char_end: e - start + char_offset,
+            });
+        }
+    }
+    if end < chars.len() {
+        out.push('…');
+    }
+    Snippet {
+        text: out,
+        highlights,
+    }
+}
+
+/// Best `k` chunks for `query` by BM25, scored so that higher is better.
+pub async fn keyword_search(
+    conn: &mut SqliteConnection,
+    query: &str,
+    k: usize,
+    scope: Scope,
+    filters: &SearchFilters,
+) -> Result<Vec<(String, f32)>, String> {
+    let Some(fts) = fts_query(query) else {
+        return Ok(Vec::new());
+    };
+    let condition = Condition::new(scope, filters);
+    let sql = format!(
+        "SELECT e.chunk_id, bm25(chunks_fts) AS rank
+         FROM chunks_fts JOIN embeddings e ON e.rowid = chunks_fts.rowid
+         WHERE chunks_fts MATCH ? AND {}
+         ORDER BY rank LIMIT ?",
+        condition.sql
+    );
+    Ok(condition
+        .bind(sqlx::query(&sql).bind(fts))
+        .bind(k as i64)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|r| (r.get("chunk_id"), -(r.get::<f64, _>("rank") as f32)))
+        .collect())
+}
+
+/// Best `k` chunks for `query` by embedding similarity.
+///
+/// Uses the configured embedding model when indexing has built its vectors
+/// and it can be reached, and the hashed vectors otherwise. Searching never
+/// embeds chunks; that is left to [`crate::vault_index::index_vault`]. Large
+/// indexes are searched through the HNSW graph in `index_dir`; when filters
+/// leave too few of the graph's neighbours, the filtered chunks are searched
+/// exactly instead.
+pub async fn vector_search(
+    conn: &mut SqliteConnection,
+    index_dir: &Path,
+    query: &str,
+    k: usize,
+    scope: Scope,
+    filters: &SearchFilters,
+) -> Result<Vec<(String, f32)>, String> {
+    let condition = Condition::new(scope, filters);
+    let model = embeddings::embedding_model();
+    let model_query = match ann::current_source(conn, &model).await? {
+        VectorSource::Model(_) => {
+            match embeddings::ollama_embed(&ollama::ollama_url(), &model, query).await {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    log::warn!("{model} embeddings unavailable, using hashed vectors: {e}");
+                    None
+                }
+            }
+        }
+        VectorSource::Hashed => None,
+    };
+    let use_model = model_query.is_some();
+    let qvec = model_query.unwrap_or_else(|| hash_embed(query));
+    let source = if use_model {
+        VectorSource::Model(&model)
+    } else {
+        VectorSource::Hashed
+    };
+
+    let graph = index_dir.join(ann::GRAPH_FILE);
+    // The graph spans every chunk, so take extra when only some will do.
+    let wanted = if condition.is_trivial() {
+        k
+    } else {
+        k * FUSION_POOL
+    };
+    match ann::nearest(&graph, source, &qvec, wanted) {
+        Ok(Some(nearest)) => {
+            if condition.is_trivial() {
+                return Ok(nearest);
+            }
+            let ids: Vec<&str> = nearest.iter().map(|(id, _)| id.as_str()).collect();
+            let sql = format!(
+                "SELECT e.chunk_id FROM embeddings e WHERE e.chunk_id IN ({}) AND {}",
+                marks(ids.len()),
+                condition.sql
+            );
+            let mut select = sqlx::query(&sql);
+            for id in &ids {
+                select = select.bind(*id);
+            }
+            let matching: HashSet<String> = condition
+                .bind(select)
+                .fetch_all(&mut *conn)
+                .await
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|r| r.get("chunk_id"))
+                .collect();
+            let exhausted = nearest.len() < wanted;
+            let kept: Vec<(String, f32)> = nearest
+                .into_iter()
+                .filter(|(id, _)| matching.contains(id))
+                .take(k)
+                .collect();
+            if kept.len() == k || exhausted {
+                return Ok(kept);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => log::warn!("vector index unavailable, searching exactly: {e}"),
+    }
+
+    let sql = if use_model {
+        format!(
+            "SELECT e.chunk_id, v.embedding
+             FROM embeddings e JOIN chunk_vectors v ON v.chunk_id = e.chunk_id
+             WHERE {}",
+            condition.sql
+        )
+    } else {
+        format!(
+            "SELECT e.chunk_id, e.embedding FROM embeddings e WHERE {}",
+            condition.sql
+        )
+    };
+    let rows = condition
+        .bind(sqlx::query(&sql))
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut hits = Vec::new();
+    for row in rows {
+        let blob: Vec<u8> = row.get("embedding");
+        let emb = embeddings::decode_vector(&blob);
+        let score: f32 = qvec.iter().zip(emb.iter()).map(|(a, b)| a * b).sum();
+        hits.push((row.get::<String, _>("chunk_id"), score));
+    }
+    hits.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    hits.truncate(k);
+    Ok(hits)
+}
+
+/// Merge rankings with reciprocal rank fusion. Each list is best first and
+/// paired with its weight; the result is best first.
+pub fn reciprocal_rank_fusion(lists: &[(&[(String, f32)], f32)], rrf_k: f32) -> Vec<(String, f32)> {
+    let mut scores: HashMap<&str, f32> = HashMap::new();
+    let mut order: Vec<&str> = Vec::new();
+    for (list, weight) in lists {
+        for (rank, (id, _)) in list.iter().enumerate() {
+            let entry = scores.entry(id).or_insert_with(|| {
+                order.push(id);
+                0.0
+            });
+            *entry += weight / (rrf_k + rank as f32 + 1.0);
+        }
+    }
+    let mut fused: Vec<(String, f32)> = order
+        .into_iter()
+        .map(|id| (id.to_string(), scores[id]))
+        .collect();
+    // Stable, so ties keep the order chunks were first seen in.
+    fused.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    fused
+}
+
+/// Reorder `hits` (best first) by maximal marginal relevance and keep at
+/// most `k`. Relevance is the retrieval score scaled to 0..1; similarity is
+/// the cosine of the chunks' hashed bag-of-words vectors. Hits over the
+/// per-document cap are dropped. Scores are left as retrieved.
+pub fn mmr_rerank(hits: Vec<ChunkHit>, k: usize, diversity: &Diversity) -> Vec<ChunkHit> {
+    let lambda = diversity.lambda.clamp(0.0, 1.0);
+    let cap = diversity.per_doc_cap.unwrap_or(usize::MAX).max(1);
+    let (lo, hi) = hits.iter().fold((f32::MAX, f32::MIN), |(lo, hi), h| {
+        (lo.min(h.score), hi.max(h.score))
+    });
+    let relevance: Vec<f32> = hits
+        .iter()
+        .map(|h| {
+            if hi - lo > f32::EPSILON {
+                (h.score - lo) / (hi - lo)
+            } else {
+                1.0
+            }
+        })
+        .collect();
+    let vectors: Vec<Vec<f32>> = hits.iter().map(|h| hash_embed(&h.text)).collect();
+    let mut max_sim = vec![0f32; hits.len()];
+    let mut taken = vec![false; hits.len()];
+    let mut per_doc: HashMap<&str, usize> = HashMap::new();
+    let mut order = Vec::new();
+    while order.len() < k {
+        let mut best: Option<(usize, f32)> = None;
+        for i in 0..hits.len() {
+            if taken[i] || per_doc.get(hits[i].doc_id.as_str()).copied().unwrap_or(0) >= cap {
+                continue;
+            }
+            let value = lambda * relevance[i] - (1.0 - lambda) * max_sim[i];
+            // Strictly greater, so ties keep the retrieval order.
+            if best.is_none_or(|(_, v)| value > v) {
+                best = Some((i, value));
+            }
+        }
+        let Some((pick, _)) = best else { break };
+        taken[pick] = true;
+        *per_doc.entry(hits[pick].doc_id.as_str()).or_default() += 1;
+        order.push(pick);
+        for i in 0..hits.len() {
+            if !taken[i] {
+                let sim: f32 = vectors[i]
+                    .iter()
+                    .zip(&vectors[pick])
+                    .map(|(a, b)| a * b)
+                    .sum();
+                max_sim[i] = max_sim[i].max(sim);
+            }
+        }
+    }
+    let mut slots: Vec<Option<ChunkHit>> = hits.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| slots[i].take()).collect()
+}
+
+/// `n` comma-separated `?` placeholders.
+fn marks(n: usize) -> String {
+    vec!["?"; n].join(", ")
+}
+
+/// The chunks of `ranked`, in order. Chunks no longer in the index are
+/// skipped.
+async fn load_hits(
+    conn: &mut SqliteConnection,
+    ranked: Vec<(String, f32)>,
+) -> Result<Vec<ChunkHit>, String> {
+    if ranked.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sql = format!(
+        "SELECT chunk_id, doc_id, page_start, page_end, text FROM embeddings
+         WHERE chunk_id IN ({})",
+        marks(ranked.len())
+    );
+    let mut select = sqlx::query(&sql);
+    for (chunk_id, _) in &ranked {
+        select = select.bind(chunk_id);
+    }
+    let mut rows: HashMap<String, _> = select
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|row| (row.get::<String, _>("chunk_id"), row))
+        .collect();
+    Ok(ranked
+        .into_iter()
+        .filter_map(|(chunk_id, score)| {
+            let row = rows.remove(&chunk_id)?;
+            Some(ChunkHit {
+                doc_id: row.get("doc_id"),
+                page_range: [
+                    row.get::<i64, _>("page_start") as u32,
+                    row.get::<i64, _>("page_end") as u32,
+                ],
+                text: row.get("text"),
+                chunk_id,
+                score,
+            })
+        })
+        .collect())
+}
+
+async fn open_index(index_dir: &Path) -> Result<Option<SqliteConnection>, String> {
+    let db_path = index_dir.join("index.sqlite");
+    if !db_path.exists() {
+        return Ok(None);
+    }
+    let mut conn = SqliteConnection::connect(&format!("sqlite:{}", db_path.to_string_lossy()))
+        .await
+        .map_err(|e| e.to_string())?;
+    index_format::check_current(&mut conn).await?;
+    Ok(Some(conn))
+}
+
+async fn rank(
+    conn: &mut SqliteConnection,
+    index_dir: &Path,
+    query: &str,
+    k: usize,
+    mode: SearchMode,
+    scope: Scope,
+    filters: &SearchFilters,
+) -> Result<Vec<(String, f32)>, String> {
+    Ok(match mode {
+        SearchMode::Keyword => keyword_search(conn, query, k, scope, filters).await?,
+        SearchMode::Vector => vector_search(conn, index_dir, query, k, scope, filters).await?,
+        SearchMode::Hybrid => {
+            let pool = k * FUSION_POOL;
+            let keyword = keyword_search(conn, query, pool, scope, filters).await?;
+            let vector = vector_search(conn, index_dir, query, pool, scope, filters).await?;
+            let weights = hybrid_weights();
+            let mut fused = reciprocal_rank_fusion(
+                &[(&keyword, weights.keyword), (&vector, weights.vector)],
+                weights.rrf_k,
+            );
+            fused.truncate(k);
+            fused
+        }
+    })
+}
+
+/// Search the chunk index at `index_dir/index.sqlite`. A missing index has
+/// no hits.
+pub async fn search_index(
+    index_dir: &Path,
+    query: &str,
+    k: usize,
+    mode: SearchMode,
+    scope: Scope,
+    filters: &SearchFilters,
+) -> Result<Vec<ChunkHit>, String> {
+    let Some(mut conn) = open_index(index_dir).await? else {
+        return Ok(Vec::new());
+    };
+    let ranked = rank(&mut conn, index_dir, query, k, mode, scope, filters).await?;
+    load_hits(&mut conn, ranked).await
+}
+
+/// [`search_index`] re-ranked for diversity: a wider pool of candidates is
+/// retrieved and the best `k` picked from it with [`mmr_rerank`].
+pub async fn search_diverse(
+    index_dir: &Path,
+    query: &str,
+    k: usize,
+    mode: SearchMode,
+    scope: Scope,
+    filters: &SearchFilters,
+    diversity: &Diversity,
+) -> Result<Vec<ChunkHit>, String> {
+    let pool = (k * FUSION_POOL).min(MAX_CANDIDATES).max(k);
+    let hits = search_index(index_dir, query, pool, mode, scope, filters).await?;
+    Ok(mmr_rerank(hits, k, diversity))
+}
+
+/// Count facets over ranked hits, at most [`MAX_CANDIDATES`] of them. Tags
+/// and worlds come from the notes' front matter, each counted in one grouped
+/// query.
+async fn facets(conn: &mut SqliteConnection, hits: &[ChunkHit]) -> Result<SearchFacets, String> {
+    let mut facets = SearchFacets::default();
+    for hit in hits {
+        let doc = hit.doc_id.as_str();
+        *facets.docs.entry(doc.to_string()).or_default() += 1;
+        if let Some((folder, _)) = doc.rsplit_once('/') {
+            *facets.folders.entry(folder.to_string()).or_default() += 1;
+        }
+    }
+    if hits.is_empty() {
+        return Ok(facets);
+    }
+    let values = vec!["(?)"; hits.len()].join(", ");
+    for (column, table, counts) in [
+        ("tag", "note_tags", &mut facets.tags),
+        ("world", "note_meta", &mut facets.worlds),
+    ] {
+        // One row per hit, so a note counts once for each of its chunks.
+        let sql = format!(
+            "WITH hits (path) AS (VALUES {values})
+             SELECT f.{column} AS value, COUNT(*) AS n
+             FROM hits JOIN {table} f ON f.path = hits.path
+             WHERE f.{column} IS NOT NULL
+             GROUP BY f.{column}"
+        );
+        let mut query = sqlx::query(&sql);
+        for hit in hits {
+            query = query.bind(&hit.doc_id);
+        }
+        for row in query
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?
+        {
+            counts.insert(row.get("value"), row.get::<i64, _>("n") as usize);
+        }
+    }
+    Ok(facets)
+}
+
+/// Order `hits` so that no page of `page_size` holds more than `cap` chunks
+/// of one document. Chunks over the cap move on to the first page with room
+/// for them; once nothing else is left they fill the page in order.
+fn cap_per_page(hits: Vec<ChunkHit>, page_size: usize, cap: usize) -> Vec<ChunkHit> {
+    let mut rest: VecDeque<ChunkHit> = hits.into();
+    let mut out = Vec::with_capacity(rest.len());
+    while !rest.is_empty() {
+        let mut per_doc: HashMap<String, usize> = HashMap::new();
+        let mut deferred = Vec::new();
+        let mut filled = 0;
+        while filled < page_size {
+            let Some(hit) = rest.pop_front() else { break };
+            let count = per_doc.entry(hit.doc_id.clone()).or_default();
+            if *count < cap {
+                *count += 1;
+                filled += 1;
+                out.push(hit);
+            } else {
+                deferred.push(hit);
+            }
+        }
+        let fill = (page_size - filled).min(deferred.len());
+        out.extend(deferred.drain(..fill));
+        for hit in deferred.into_iter().rev() {
+            rest.push_front(hit);
+        }
+    }
+    out
+}
+
+/// Search with filters, one page at a time. The best [`MAX_CANDIDATES`]
+/// matching chunks are ranked once; facets count all of them and the page is
+/// a slice of them. With `diversity` the candidates are re-ranked by
+/// [`mmr_rerank`], and its per-document cap applies to each page rather than
+/// dropping chunks, so `total` and the facets are the same with or without it.
+pub async fn search_page(
+    index_dir: &Path,
+    query: &str,
+    mode: SearchMode,
+    scope: Scope,
+    filters: &SearchFilters,
+    page: &PageRequest,
+    diversity: Option<&Diversity>,
+) -> Result<SearchPage, String> {
+    let fingerprint = fingerprint(query, mode, scope, filters, diversity);
+    let offset = match &page.cursor {
+        Some(cursor) => decode_cursor(cursor, &fingerprint)?,
+        None => page.offset.unwrap_or(0),
+    };
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+    let Some(mut conn) = open_index(index_dir).await? else {
+        return Ok(SearchPage {
+            offset,
+            ..Default::default()
+        });
+    };
+    let ranked = rank(
+        &mut conn,
+        index_dir,
+        query,
+        MAX_CANDIDATES,
+        mode,
+        scope,
+        filters,
+    )
+    .await?;
+    let mut all = load_hits(&mut conn, ranked).await?;
+    if let Some(diversity) = diversity {
+        let uncapped = Diversity {
+            per_doc_cap: None,
+            ..diversity.clone()
+        };
+        all = mmr_rerank(all, MAX_CANDIDATES, &uncapped);
+        if let Some(cap) = diversity.per_doc_cap {
+            all = cap_per_page(all, limit, cap.max(1));
+        }
+    }
+    let facets = facets(&mut conn, &all).await?;
+    let total = all.len();
+    let hits: Vec<ChunkHit> = all.into_iter().skip(offset).take(limit).collect();
+    let next = offset + hits.len();
+    Ok(SearchPage {
+        hits,
+        facets,
+        total,
+        offset,
+        next_cursor: (next < total).then(|| encode_cursor(next, &fingerprint)),
+    })
+}
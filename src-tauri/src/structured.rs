@@ -0,0 +1,79 @@
+use schemars::{schema_for, JsonSchema};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::commands::ChatMessage;
+use crate::llm::{ChatRequest, LlmBackend};
+
+/// How many times a malformed reply is sent back to the model for repair
+/// before giving up.
+pub const MAX_REPAIR_ATTEMPTS: usize = 2;
+
+/// JSON schema for `T`, in the shape Ollama's `format` field accepts.
+pub fn schema_of<T: JsonSchema>() -> Value {
+    let mut schema = serde_json::to_value(schema_for!(T)).unwrap_or_default();
+    if let Some(obj) = schema.as_object_mut() {
+        obj.remove("$schema");
+    }
+    schema
+}
+
+/// Remove a surrounding Markdown code fence (```` ```json ... ``` ````), if any.
+pub fn strip_code_fences(s: &str) -> &str {
+    let trimmed = s.trim();
+    let inner = match trimmed.strip_prefix("```") {
+        Some(rest) => rest,
+        None => return trimmed,
+    };
+    // Drop the info string (`json`, `JSON`, ...) on the opening line.
+    let inner = match inner.find('\n') {
+        Some(pos) => &inner[pos + 1..],
+        None => inner,
+    };
+    inner.trim_end().trim_end_matches("```").trim()
+}
+
+/// Parse a model reply into `T`, tolerating code fences around the JSON.
+pub fn parse_reply<T: DeserializeOwned>(s: &str) -> Result<T, String> {
+    serde_json::from_str::<T>(strip_code_fences(s)).map_err(|e| e.to_string())
+}
+
+/// Ask `backend` for a reply to `req` matching `T`'s schema.
+///
+/// The schema is sent as the request format. When the reply still fails to
+/// parse, the error is fed back to the model and the request retried, up to
+/// `max_repairs` times.
+pub async fn chat_structured<T: JsonSchema + DeserializeOwned>(
+    backend: &dyn LlmBackend,
+    req: ChatRequest,
+    max_repairs: usize,
+) -> Result<T, String> {
+    let schema = schema_of::<T>();
+    let mut req = req.with_format(schema.clone());
+    let mut attempt = 0;
+    loop {
+        let reply = backend.chat(&req).await?;
+        let err = match parse_reply::<T>(&reply) {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        };
+        if attempt >= max_repairs {
+            return Err(format!(
+                "invalid structured reply after {} attempts: {err}; last reply: {reply}",
+                attempt + 1
+            ));
+        }
+        attempt += 1;
+        log::warn!("structured reply did not validate ({err}), asking for repair");
+        req.messages.push(ChatMessage {
+            role: "assistant".into(),
+            content: reply,
+        });
+        req.messages.push(ChatMessage {
+            role: "user".into(),
+            content: format!(
+                "Your previous reply was not valid: {err}. Reply again with ONLY a JSON value matching this schema, without code fences or commentary:\n{schema}"
+            ),
+        });
+    }
+}
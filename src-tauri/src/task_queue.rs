@@ -1,5 +1,6 @@
 use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
 use std::process::{Command as PCommand, Output, Stdio};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex as StdMutex};
@@ -7,13 +8,15 @@ use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use sysinfo::System;
 use tauri::async_runtime::{self, JoinHandle};
 use tauri::{AppHandle, Emitter, Wry};
 use tokio::sync::{mpsc, Mutex, Semaphore};
 use tokio::time::sleep;
 
+use crate::comfy::{self, WorkflowProgress};
+use crate::vault_index;
 use crate::video_tools::ShortSpec;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +54,17 @@ pub enum TaskCommand {
     GenerateShort {
         spec: ShortSpec,
     },
+    /// Run a ComfyUI workflow (API format) and collect its output images.
+    ComfyWorkflow {
+        workflow: Value,
+        #[serde(default)]
+        output_dir: Option<String>,
+    },
+    /// Index the notes in the vault folder; `force` re-chunks unchanged notes.
+    IndexVault {
+        #[serde(default)]
+        force: bool,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -372,6 +386,75 @@ impl TaskQueue {
                                     println!("Generating short: {:?}", spec);
                                     Ok(Value::String("ok".into()))
                                 }
+                                TaskCommand::IndexVault { force } => {
+                                    let index = crate::commands::vault_index_dir();
+                                    vault_index::index_vault(
+                                        &crate::commands::output_folder(),
+                                        &index.join("index.sqlite"),
+                                        &crate::ollama::ollama_url(),
+                                        &crate::embeddings::embedding_model(),
+                                        force,
+                                    )
+                                    .await
+                                    .map(|report| json!(report))
+                                    .map_err(TaskError::from)
+                                }
+                                TaskCommand::ComfyWorkflow {
+                                    workflow,
+                                    output_dir,
+                                } => {
+                                    let out = output_dir.map(PathBuf::from).unwrap_or_else(|| {
+                                        crate::commands::output_folder().join("ComfyUI")
+                                    });
+                                    let (ptx, mut prx) =
+                                        mpsc::unbounded_channel::<WorkflowProgress>();
+                                    let tasks_progress = tasks_clone.clone();
+                                    let app_progress = app_handle.clone();
+                                    let forward = async_runtime::spawn(async move {
+                                        while let Some(p) = prx.recv().await {
+                                            let update = {
+                                                let mut map = tasks_progress.lock().await;
+                                                map.get_mut(&id).map(|t| {
+                                                    if p.max > 0 {
+                                                        t.progress = p.value as f32 / p.max as f32;
+                                                    }
+                                                    TaskUpdatePayload {
+                                                        task: t.clone(),
+                                                        progress: serde_json::to_value(&p).ok(),
+                                                    }
+                                                })
+                                            };
+                                            if let Some(update) = update {
+                                                if let Some(app) =
+                                                    app_progress.lock().unwrap().clone()
+                                                {
+                                                    let _ = app.emit("task_updated", update);
+                                                }
+                                            }
+                                        }
+                                    });
+                                    let res = comfy::run_workflow(
+                                        &comfy::comfy_url(),
+                                        &workflow,
+                                        &out,
+                                        Some(ptx),
+                                        comfy::WORKFLOW_TIMEOUT,
+                                    )
+                                    .await;
+                                    let _ = forward.await;
+                                    res.map(|paths| {
+                                        json!(paths
+                                            .iter()
+                                            .map(|p| p.to_string_lossy().to_string())
+                                            .collect::<Vec<_>>())
+                                    })
+                                    .map_err(|message| {
+                                        TaskError {
+                                            code: PdfErrorCode::ExecutionFailed,
+                                            message,
+                                        }
+                                    })
+                                }
                             };
                             let snapshot = {
                                 let mut map = tasks_clone.lock().await;
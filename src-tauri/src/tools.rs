@@ -0,0 +1,338 @@
+use async_trait::async_trait;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Runtime};
+
+use crate::commands::{self, ChatMessage};
+use crate::context;
+use crate::llm::{self, ChatRequest, LlmBackend, ToolRound, ToolSpec, ToolTurn};
+use crate::ollama::{self, ModelFeature};
+
+/// Tool-call rounds allowed before the model is made to answer without tools.
+pub const MAX_TOOL_ROUNDS: usize = 5;
+
+/// Upper bound on entries returned by the `list_*` tools.
+const MAX_LIST_RESULTS: usize = 20;
+
+/// Most of the context left after the request that one tool result may take,
+/// as a fraction: a quarter, so several rounds of results still fit.
+const RESULT_SHARE: usize = 4;
+
+/// A tool call made while answering, as reported to the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ToolInvocation {
+    pub name: String,
+    pub arguments: Value,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolChatReply {
+    pub content: String,
+    pub tool_calls: Vec<ToolInvocation>,
+}
+
+/// Executes the tools offered to the model.
+#[async_trait]
+pub trait ToolRunner: Send + Sync {
+    async fn run(&self, name: &str, args: &Value) -> Result<Value, String>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DiceRoll {
+    pub expression: String,
+    pub rolls: Vec<u32>,
+    pub modifier: i64,
+    pub total: i64,
+}
+
+/// Roll dice written like `d20`, `2d6+3` or `1d8 + 1d6 - 1`.
+pub fn roll_dice(expression: &str) -> Result<DiceRoll, String> {
+    let expr: String = expression
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    if expr.is_empty() {
+        return Err("empty dice expression".into());
+    }
+    let mut rng = thread_rng();
+    let mut rolls = Vec::new();
+    let mut modifier = 0i64;
+    let mut total = 0i64;
+    let mut dice_count = 0u32;
+    // Split into signed terms: "2d6+3-1d4" -> +2d6, +3, -1d4.
+    let mut terms = Vec::new();
+    let mut start = 0;
+    for (i, c) in expr.char_indices() {
+        if (c == '+' || c == '-') && i > start {
+            terms.push(&expr[start..i]);
+            start = i;
+        }
+    }
+    terms.push(&expr[start..]);
+    for term in terms {
+        let (sign, body) = match term.strip_prefix('-') {
+            Some(rest) => (-1i64, rest),
+            None => (1, term.strip_prefix('+').unwrap_or(term)),
+        };
+        let invalid = || format!("invalid dice expression: {expression}");
+        match body.split_once('d') {
+            Some((count, sides)) => {
+                let count: u32 = if count.is_empty() {
+                    1
+                } else {
+                    count.parse().map_err(|_| invalid())?
+                };
+                let sides: u32 = sides.parse().map_err(|_| invalid())?;
+                if count == 0 || sides == 0 {
+                    return Err(invalid());
+                }
+                dice_count = match dice_count.checked_add(count) {
+                    Some(n) if n <= 100 => n,
+                    _ => return Err("too many dice (max 100)".into()),
+                };
+                for _ in 0..count {
+                    let r = rng.gen_range(1..=sides);
+                    rolls.push(r);
+                    total = total.checked_add(sign * r as i64).ok_or_else(invalid)?;
+                }
+            }
+            None => {
+                let n: i64 = body.parse().map_err(|_| invalid())?;
+                modifier = modifier.checked_add(sign * n).ok_or_else(invalid)?;
+                total = total.checked_add(sign * n).ok_or_else(invalid)?;
+            }
+        }
+    }
+    Ok(DiceRoll {
+        expression: expression.trim().to_string(),
+        rolls,
+        modifier,
+        total,
+    })
+}
+
+fn query_param() -> Value {
+    json!({ "type": "string", "description": "Optional text to filter by (case-insensitive)." })
+}
+
+fn world_param() -> Value {
+    json!({ "type": "string", "description": "World name; defaults to the current world." })
+}
+
+/// Tools offered to the model in `chat_with_tools`.
+pub fn builtin_tools() -> Vec<ToolSpec> {
+    let search = |description: &str| {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string", "description": description },
+                "k": { "type": "integer", "description": "Number of results, default 3." },
+            },
+            "required": ["query"],
+        })
+    };
+    vec![
+        ToolSpec {
+            name: "vault_search".into(),
+            description: "Search the user's indexed notes vault.".into(),
+            parameters: search("What to look for in the notes."),
+        },
+        ToolSpec {
+            name: "pdf_search".into(),
+            description: "Search imported PDF rulebooks and sourcebooks.".into(),
+            parameters: search("What to look for in the PDFs."),
+        },
+        ToolSpec {
+            name: "list_npcs".into(),
+            description: "List non-player characters of a world.".into(),
+            parameters: json!({
+                "type": "object",
+                "properties": { "world": world_param(), "query": query_param() },
+            }),
+        },
+        ToolSpec {
+            name: "list_lore".into(),
+            description: "List lore entries of a world.".into(),
+            parameters: json!({
+                "type": "object",
+                "properties": { "world": world_param(), "query": query_param() },
+            }),
+        },
+        ToolSpec {
+            name: "list_spells".into(),
+            description: "List saved spells.".into(),
+            parameters: json!({ "type": "object", "properties": { "query": query_param() } }),
+        },
+        ToolSpec {
+            name: "list_rules".into(),
+            description: "List saved rules.".into(),
+            parameters: json!({ "type": "object", "properties": { "query": query_param() } }),
+        },
+        ToolSpec {
+            name: "roll_dice".into(),
+            description: "Roll dice, e.g. `d20`, `2d6+3` or `1d8+1d6`.".into(),
+            parameters: json!({
+                "type": "object",
+                "properties": { "expression": { "type": "string" } },
+                "required": ["expression"],
+            }),
+        },
+    ]
+}
+
+/// Keep entries whose JSON mentions `query`, capped at [`MAX_LIST_RESULTS`].
+fn filter_entries(entries: Vec<Value>, query: Option<&str>) -> Value {
+    let query = query
+        .map(|q| q.trim().to_lowercase())
+        .filter(|q| !q.is_empty());
+    let filtered: Vec<Value> = entries
+        .into_iter()
+        .filter(|e| match &query {
+            Some(q) => e.to_string().to_lowercase().contains(q),
+            None => true,
+        })
+        .take(MAX_LIST_RESULTS)
+        .collect();
+    Value::Array(filtered)
+}
+
+/// Runs the built-in tools against the app's data.
+pub struct AppTools<R: Runtime> {
+    pub app: AppHandle<R>,
+    /// World used when the model doesn't name one.
+    pub world: Option<String>,
+}
+
+impl<R: Runtime> AppTools<R> {
+    fn world(&self, args: &Value) -> Result<String, String> {
+        args["world"]
+            .as_str()
+            .filter(|w| !w.trim().is_empty())
+            .map(|w| w.to_string())
+            .or_else(|| self.world.clone())
+            .ok_or_else(|| "world is required".to_string())
+    }
+}
+
+#[async_trait]
+impl<R: Runtime> ToolRunner for AppTools<R> {
+    async fn run(&self, name: &str, args: &Value) -> Result<Value, String> {
+        let query = args["query"].as_str();
+        let k = args["k"].as_u64().map(|k| k.min(10) as u32);
+        let app = self.app.clone();
+        let value = match name {
+            "vault_search" => {
+                let query = query.unwrap_or_default().to_string();
+                let page =
+                    commands::vault_search(app, query, None, None, k.or(Some(3)), None, None, None)
+                        .await?;
+                json!(page.hits)
+            }
+            "pdf_search" => {
+                json!(
+                    commands::pdf_search(app, query.unwrap_or_default().to_string(), k, None, None)
+                        .await?
+                )
+            }
+            "list_npcs" => {
+                filter_entries(commands::list_npcs(app, self.world(args)?).await?, query)
+            }
+            "list_lore" => {
+                filter_entries(commands::list_lore(app, self.world(args)?).await?, query)
+            }
+            "list_spells" => filter_entries(commands::list_spells(app).await?, query),
+            "list_rules" => filter_entries(commands::list_rules(app).await?, query),
+            "roll_dice" => json!(roll_dice(args["expression"].as_str().unwrap_or_default())?),
+            other => return Err(format!("unknown tool: {other}")),
+        };
+        Ok(value)
+    }
+}
+
+/// Chat with `tools` available, running the model's tool calls through
+/// `runner` until it produces an answer.
+///
+/// After `max_rounds` rounds of calls the tools are withdrawn, so the model
+/// has to answer with what it has. Results are cut to fit the room `req`
+/// leaves in the model's context; the reply lists them in full.
+pub async fn run_tool_loop(
+    backend: &dyn LlmBackend,
+    req: &ChatRequest,
+    tools: &[ToolSpec],
+    runner: &dyn ToolRunner,
+    max_rounds: usize,
+) -> Result<ToolChatReply, String> {
+    let mut rounds: Vec<ToolRound> = Vec::new();
+    let mut invocations = Vec::new();
+    let mut room = context::room_left(&req.model, &req.messages);
+    let share = room / RESULT_SHARE;
+    loop {
+        let offered = if rounds.len() < max_rounds {
+            tools
+        } else {
+            &[]
+        };
+        let (calls, content) = match backend.chat_tools(req, offered, &rounds).await? {
+            ToolTurn::Answer(content) => {
+                return Ok(ToolChatReply {
+                    content,
+                    tool_calls: invocations,
+                })
+            }
+            ToolTurn::Calls { calls, content } => (calls, content),
+        };
+        room = room.saturating_sub(context::estimate_tokens(&content));
+        let mut results = Vec::new();
+        for call in &calls {
+            let (result, error) = match runner.run(&call.name, &call.arguments).await {
+                Ok(v) => (Some(v), None),
+                Err(e) => (None, Some(e)),
+            };
+            let text = match (&result, &error) {
+                (Some(v), _) => v.to_string(),
+                (None, Some(e)) => json!({ "error": e }).to_string(),
+                (None, None) => String::new(),
+            };
+            let text = context::trim_to_tokens(&text, share.min(room));
+            room = room.saturating_sub(context::estimate_tokens(&text));
+            results.push(text);
+            invocations.push(ToolInvocation {
+                name: call.name.clone(),
+                arguments: call.arguments.clone(),
+                result,
+                error,
+            });
+        }
+        rounds.push(ToolRound {
+            content,
+            calls,
+            results,
+        });
+    }
+}
+
+/// Chat where the model can look up notes, PDFs, NPCs, lore, spells and
+/// rules and roll dice. Every tool call is listed in the reply.
+#[tauri::command]
+pub async fn chat_with_tools<R: Runtime>(
+    app: AppHandle<R>,
+    messages: Vec<ChatMessage>,
+    world: Option<String>,
+) -> Result<ToolChatReply, String> {
+    let backend = llm::backend();
+    let model = ollama::model_for(ModelFeature::Chat);
+    let req = context::fit_request(backend.as_ref(), &model, messages).await?;
+    let runner = AppTools { app, world };
+    run_tool_loop(
+        backend.as_ref(),
+        &req,
+        &builtin_tools(),
+        &runner,
+        MAX_TOOL_ROUNDS,
+    )
+    .await
+}
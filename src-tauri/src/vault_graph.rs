@@ -0,0 +1,415 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, Row, SqliteConnection};
+
+use crate::commands;
+use crate::index_format;
+use crate::vault_index;
+
+/// Deepest neighbourhood `vault_neighborhood` will walk.
+const MAX_DEPTH: usize = 3;
+
+/// A `[[wiki link]]` as written in a note.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WikiLink {
+    /// Note name or path, without `#heading` or `|alias`.
+    pub target: String,
+    pub heading: Option<String>,
+    pub alias: Option<String>,
+    /// `![[...]]`, which shows the target inline.
+    pub embed: bool,
+    /// 1-based line in the note.
+    pub line: u32,
+}
+
+/// A link from one indexed note, with the note it points at if that exists.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NoteLink {
+    pub source: String,
+    #[serde(flatten)]
+    pub link: WikiLink,
+    /// Vault path the target resolves to; `None` for a broken link.
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GraphNode {
+    /// Vault path, or the link target for a note that doesn't exist.
+    pub id: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub exists: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    /// Links from `source` to `target`.
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Subgraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+/// Lines of `text` outside fenced code blocks, numbered from 1, with inline
+/// code blanked out.
+fn prose_lines(text: &str) -> impl Iterator<Item = (u32, String)> + '_ {
+    let mut in_fence = false;
+    text.lines().enumerate().filter_map(move |(i, line)| {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            return None;
+        }
+        if in_fence {
+            return None;
+        }
+        let mut out = String::with_capacity(line.len());
+        let mut in_code = false;
+        for c in line.chars() {
+            if c == '`' {
+                in_code = !in_code;
+            }
+            out.push(if in_code || c == '`' { ' ' } else { c });
+        }
+        Some((i as u32 + 1, out))
+    })
+}
+
+/// The wiki links in a note body, skipping code. `[[#Heading]]` points into
+/// the same note and isn't a link between notes.
+pub fn parse_links(text: &str) -> Vec<WikiLink> {
+    let mut links = Vec::new();
+    for (line_no, line) in prose_lines(text) {
+        let mut rest = line.as_str();
+        while let Some(start) = rest.find("[[") {
+            let embed = rest[..start].ends_with('!');
+            let after = &rest[start + 2..];
+            let Some(end) = after.find("]]") else { break };
+            let inner = &after[..end];
+            rest = &after[end + 2..];
+            let (target, alias) = match inner.split_once('|') {
+                Some((t, a)) => (t, Some(a.trim().to_string()).filter(|a| !a.is_empty())),
+                None => (inner, None),
+            };
+            let (target, heading) = match target.split_once('#') {
+                Some((t, h)) => (t, Some(h.trim().to_string()).filter(|h| !h.is_empty())),
+                None => (target, None),
+            };
+            let target = target.trim();
+            if target.is_empty() || target.contains('[') {
+                continue;
+            }
+            links.push(WikiLink {
+                target: target.to_string(),
+                heading,
+                alias,
+                embed,
+                line: line_no,
+            });
+        }
+    }
+    links
+}
+
+/// `#tags` in a note body, lowercased and without the `#`. A tag follows
+/// whitespace or starts the line, and isn't all digits, so headings, issue
+/// numbers and `[[note#heading]]` don't count.
+pub fn parse_inline_tags(text: &str) -> Vec<String> {
+    let mut tags = BTreeSet::new();
+    for (_, line) in prose_lines(text) {
+        let mut prev = ' ';
+        for (i, c) in line.char_indices() {
+            if c == '#' && prev.is_whitespace() {
+                let tag: String = line[i + 1..]
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '/'))
+                    .collect();
+                let tag = tag.trim_end_matches('/');
+                if !tag.is_empty() && !tag.chars().all(|c| c.is_ascii_digit()) {
+                    tags.insert(tag.to_lowercase());
+                }
+            }
+            prev = c;
+        }
+    }
+    tags.into_iter().collect()
+}
+
+fn strip_extension(path: &str) -> &str {
+    match path.rsplit_once('.') {
+        Some((stem, ext))
+            if vault_index::NOTE_EXTENSIONS
+                .iter()
+                .any(|e| ext.eq_ignore_ascii_case(e)) =>
+        {
+            stem
+        }
+        _ => path,
+    }
+}
+
+fn folder(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(f, _)| f)
+}
+
+fn title(path: &str) -> String {
+    strip_extension(path.rsplit('/').next().unwrap_or(path)).to_string()
+}
+
+/// The note `target` names, matched the way Obsidian does: ignoring case and
+/// extension, by full path or by the trailing part of one. When several
+/// notes share a name, one in `from`'s folder wins, then the shortest path.
+pub fn resolve_link<'a>(target: &str, from: &str, notes: &'a [String]) -> Option<&'a str> {
+    let wanted = strip_extension(target.trim().trim_start_matches('/')).to_lowercase();
+    let mut matches: Vec<&str> = notes
+        .iter()
+        .map(String::as_str)
+        .filter(|n| {
+            let stem = strip_extension(n).to_lowercase();
+            stem == wanted || stem.ends_with(&format!("/{wanted}"))
+        })
+        .collect();
+    matches.sort_by_key(|n| {
+        (
+            folder(n) != folder(from),
+            strip_extension(n).to_lowercase() != wanted,
+            n.len(),
+            n.to_string(),
+        )
+    });
+    matches.first().copied()
+}
+
+/// Every indexed note and every link between them.
+struct Graph {
+    notes: Vec<String>,
+    links: Vec<NoteLink>,
+}
+
+async fn open(index_dir: &Path) -> Result<Option<SqliteConnection>, String> {
+    let db_path = index_dir.join("index.sqlite");
+    if !db_path.exists() {
+        return Ok(None);
+    }
+    let mut conn = SqliteConnection::connect(&format!("sqlite:{}", db_path.to_string_lossy()))
+        .await
+        .map_err(|e| e.to_string())?;
+    index_format::check_current(&mut conn).await?;
+    Ok(Some(conn))
+}
+
+async fn load(conn: &mut SqliteConnection) -> Result<Graph, String> {
+    let notes: Vec<String> = sqlx::query("SELECT path FROM vault_files ORDER BY path")
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|r| r.get("path"))
+        .collect();
+    let rows = sqlx::query(
+        "SELECT path, target, heading, alias, embed, line FROM note_links ORDER BY path, line",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    let links = rows
+        .into_iter()
+        .map(|r| {
+            let source: String = r.get("path");
+            let link = WikiLink {
+                target: r.get("target"),
+                heading: r.get("heading"),
+                alias: r.get("alias"),
+                embed: r.get::<i64, _>("embed") != 0,
+                line: r.get::<i64, _>("line") as u32,
+            };
+            let path = resolve_link(&link.target, &source, &notes).map(str::to_string);
+            NoteLink { source, link, path }
+        })
+        .collect();
+    Ok(Graph { notes, links })
+}
+
+async fn graph(index_dir: &Path) -> Result<Graph, String> {
+    match open(index_dir).await? {
+        Some(mut conn) => load(&mut conn).await,
+        None => Ok(Graph {
+            notes: Vec::new(),
+            links: Vec::new(),
+        }),
+    }
+}
+
+/// Links from other notes (and the note itself) to `path`.
+pub async fn backlinks(index_dir: &Path, path: &str) -> Result<Vec<NoteLink>, String> {
+    Ok(graph(index_dir)
+        .await?
+        .links
+        .into_iter()
+        .filter(|l| l.path.as_deref() == Some(path))
+        .collect())
+}
+
+/// Links written in `path`, resolved or not.
+pub async fn outgoing_links(index_dir: &Path, path: &str) -> Result<Vec<NoteLink>, String> {
+    Ok(graph(index_dir)
+        .await?
+        .links
+        .into_iter()
+        .filter(|l| l.source == path)
+        .collect())
+}
+
+/// Notes with no links to or from another note.
+pub async fn orphans(index_dir: &Path) -> Result<Vec<String>, String> {
+    let graph = graph(index_dir).await?;
+    let mut linked = BTreeSet::new();
+    for link in &graph.links {
+        if let Some(path) = link.path.as_deref().filter(|p| *p != link.source) {
+            linked.insert(path);
+            linked.insert(link.source.as_str());
+        }
+    }
+    Ok(graph
+        .notes
+        .iter()
+        .filter(|n| !linked.contains(n.as_str()))
+        .cloned()
+        .collect())
+}
+
+/// Links whose target matches no indexed note.
+pub async fn broken_links(index_dir: &Path) -> Result<Vec<NoteLink>, String> {
+    Ok(graph(index_dir)
+        .await?
+        .links
+        .into_iter()
+        .filter(|l| l.path.is_none())
+        .collect())
+}
+
+/// Notes within `depth` links of `path`, following links both ways, with
+/// the links between them. Broken links show up as nodes that don't exist.
+pub async fn neighborhood(index_dir: &Path, path: &str, depth: usize) -> Result<Subgraph, String> {
+    let Some(mut conn) = open(index_dir).await? else {
+        return Ok(Subgraph::default());
+    };
+    let graph = load(&mut conn).await?;
+    if !graph.notes.iter().any(|n| n == path) {
+        return Err(format!("{path} is not in the vault index"));
+    }
+    // Broken links are keyed by their lowercased target.
+    let node_of = |l: &NoteLink| match &l.path {
+        Some(p) => p.clone(),
+        None => strip_extension(&l.link.target).to_lowercase(),
+    };
+    let mut adjacent: HashMap<String, Vec<String>> = HashMap::new();
+    for link in &graph.links {
+        let target = node_of(link);
+        adjacent
+            .entry(link.source.clone())
+            .or_default()
+            .push(target.clone());
+        adjacent
+            .entry(target)
+            .or_default()
+            .push(link.source.clone());
+    }
+
+    let mut seen: BTreeMap<String, usize> = BTreeMap::from([(path.to_string(), 0)]);
+    let mut queue = VecDeque::from([path.to_string()]);
+    while let Some(node) = queue.pop_front() {
+        let dist = seen[&node];
+        if dist == depth.min(MAX_DEPTH) {
+            continue;
+        }
+        for next in adjacent.get(&node).into_iter().flatten() {
+            if !seen.contains_key(next) {
+                seen.insert(next.clone(), dist + 1);
+                queue.push_back(next.clone());
+            }
+        }
+    }
+
+    let mut counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+    for link in &graph.links {
+        let target = node_of(link);
+        if seen.contains_key(&link.source) && seen.contains_key(&target) {
+            *counts.entry((link.source.clone(), target)).or_default() += 1;
+        }
+    }
+    let mut nodes = Vec::with_capacity(seen.len());
+    for id in seen.into_keys() {
+        let exists = graph.notes.contains(&id);
+        let tags = if exists {
+            sqlx::query("SELECT tag FROM note_tags WHERE path = ? ORDER BY tag")
+                .bind(&id)
+                .fetch_all(&mut conn)
+                .await
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|r| r.get("tag"))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        nodes.push(GraphNode {
+            title: title(&id),
+            id,
+            tags,
+            exists,
+        });
+    }
+    Ok(Subgraph {
+        nodes,
+        edges: counts
+            .into_iter()
+            .map(|((source, target), count)| GraphEdge {
+                source,
+                target,
+                count,
+            })
+            .collect(),
+    })
+}
+
+/// Notes linking to `path`, a note's path in the vault such as `npcs/strahd.md`.
+#[tauri::command]
+pub async fn vault_backlinks(path: String) -> Result<Vec<NoteLink>, String> {
+    backlinks(&commands::vault_index_dir(), &path).await
+}
+
+/// Links written in `path`; broken ones have no `path`.
+#[tauri::command]
+pub async fn vault_outgoing_links(path: String) -> Result<Vec<NoteLink>, String> {
+    outgoing_links(&commands::vault_index_dir(), &path).await
+}
+
+#[tauri::command]
+pub async fn vault_orphans() -> Result<Vec<String>, String> {
+    orphans(&commands::vault_index_dir()).await
+}
+
+#[tauri::command]
+pub async fn vault_broken_links() -> Result<Vec<NoteLink>, String> {
+    broken_links(&commands::vault_index_dir()).await
+}
+
+/// Notes within `depth` (default 1, at most 3) links of `path`.
+#[tauri::command]
+pub async fn vault_neighborhood(path: String, depth: Option<u32>) -> Result<Subgraph, String> {
+    neighborhood(
+        &commands::vault_index_dir(),
+        &path,
+        depth.unwrap_or(1) as usize,
+    )
+    .await
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, Executor, Row, SqliteConnection};
+
+use crate::ann;
+use crate::commands::hash_embed;
+use crate::context::estimate_tokens;
+use crate::embeddings::{self, encode_vector, HASH_MODEL};
+use crate::index_format;
+use crate::vault_graph;
+
+/// Largest chunk, in estimated tokens, before a section is split further.
+const MAX_CHUNK_TOKENS: usize = 300;
+
+/// File extensions indexed as notes.
+pub const NOTE_EXTENSIONS: [&str; 3] = ["md", "markdown", "txt"];
+
+/// `embeddings` matches the table `pdf_tools.py` creates, so PDF chunks and
+/// notes share one index. `vault_files` records what each note looked like
+/// when it was last indexed; `note_meta` and `note_tags` hold what its front
+/// matter says, for filtering searches.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS embeddings (
+    chunk_id TEXT PRIMARY KEY,
+    embedding BLOB,
+    doc_id TEXT,
+    page_start INTEGER,
+    page_end INTEGER,
+    text TEXT
+);
+CREATE TABLE IF NOT EXISTS vault_files (
+    path TEXT PRIMARY KEY,
+    mtime INTEGER NOT NULL,
+    hash TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS note_meta (
+    path TEXT PRIMARY KEY,
+    world TEXT,
+    date TEXT
+);
+CREATE TABLE IF NOT EXISTS note_tags (
+    path TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (path, tag)
+);
+CREATE TABLE IF NOT EXISTS note_links (
+    path TEXT NOT NULL,
+    target TEXT NOT NULL,
+    heading TEXT,
+    alias TEXT,
+    embed INTEGER NOT NULL,
+    line INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS note_links_path ON note_links (path);
+";
+
+/// A piece of a note. Notes have no pages, so `line_start` and `line_end`
+/// (1-based, inclusive) fill the index's page columns.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NoteChunk {
+    /// Headings the chunk sits under, outermost first.
+    pub headings: Vec<String>,
+    pub text: String,
+    pub line_start: u32,
+    pub line_end: u32,
+}
+
+/// What a note's YAML front matter says about it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct NoteMeta {
+    /// Lowercased, without a leading `#`.
+    pub tags: Vec<String>,
+    pub world: Option<String>,
+    /// `YYYY-MM-DD`.
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct IndexReport {
+    pub scanned: usize,
+    /// Notes that were new or changed and have been chunked again.
+    pub indexed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub chunks: usize,
+    /// Chunks embedded with the configured model.
+    pub embedded: usize,
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&level) && line[level..].starts_with(' ') {
+        Some((level, line[level..].trim()))
+    } else {
+        None
+    }
+}
+
+/// Split `lines` (numbered from `first_line`) at blank lines, then at single
+/// lines, so no piece is over [`MAX_CHUNK_TOKENS`] where that is possible.
+fn split_section(lines: &[&str], first_line: u32) -> Vec<(u32, u32, String)> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut text = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let candidate = if text.is_empty() {
+            line.to_string()
+        } else {
+            format!("{text}\n{line}")
+        };
+        let at_break = line.trim().is_empty();
+        if estimate_tokens(&candidate) > MAX_CHUNK_TOKENS && !text.trim().is_empty() {
+            pieces.push((start, i - 1, std::mem::take(&mut text)));
+            start = i;
+            text = line.to_string();
+        } else if at_break && estimate_tokens(&text) > MAX_CHUNK_TOKENS / 2 {
+            // Prefer ending a chunk between paragraphs once it is reasonably full.
+            pieces.push((start, i - 1, std::mem::take(&mut text)));
+            start = i + 1;
+        } else {
+            text = candidate;
+        }
+    }
+    if !text.trim().is_empty() {
+        pieces.push((start, lines.len() - 1, text));
+    }
+    pieces
+        .into_iter()
+        .filter(|(_, _, t)| !t.trim().is_empty())
+        .map(|(s, e, t)| (first_line + s as u32, first_line + e as u32, t))
+        .collect()
+}
+
+/// Split a note into chunks at its Markdown headings. Each chunk starts with
+/// the headings it belongs to, so a chunk from "Strahd > Lair" still mentions
+/// Strahd. Headings inside fenced code blocks are ignored.
+pub fn chunk_note(text: &str) -> Vec<NoteChunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut path: Vec<(usize, String)> = Vec::new();
+    let mut section_start = 0;
+    let mut in_fence = false;
+
+    let flush = |path: &[(usize, String)], from: usize, to: usize, chunks: &mut Vec<_>| {
+        if from >= to {
+            return;
+        }
+        let headings: Vec<String> = path.iter().map(|(_, h)| h.clone()).collect();
+        for (start, end, body) in split_section(&lines[from..to], from as u32 + 1) {
+            let text = if headings.is_empty() {
+                body.trim().to_string()
+            } else {
+                format!("{}\n{}", headings.join(" > "), body.trim())
+            };
+            chunks.push(NoteChunk {
+                headings: headings.clone(),
+                text,
+                line_start: start,
+                line_end: end,
+            });
+        }
+    };
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            continue;
+        }
+        if let Some((level, title)) = heading(line) {
+            flush(&path, section_start, i, &mut chunks);
+            path.retain(|(l, _)| *l < level);
+            path.push((level, title.to_string()));
+            section_start = i + 1;
+        }
+    }
+    flush(&path, section_start, lines.len(), &mut chunks);
+    chunks
+}
+
+fn yaml_strings(value: &serde_yaml::Value) -> Vec<String> {
+    match value {
+        serde_yaml::Value::Sequence(items) => items.iter().flat_map(yaml_strings).collect(),
+        serde_yaml::Value::String(s) => s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .map(str::to_string)
+            .collect(),
+        serde_yaml::Value::Number(n) => vec![n.to_string()],
+        _ => Vec::new(),
+    }
+}
+
+/// Read the front matter at the top of a note. Returns its metadata and the
+/// number of lines it takes up, so the body can be chunked with the right line
+/// numbers. Notes without (valid) front matter get empty metadata.
+pub fn parse_front_matter(text: &str) -> (NoteMeta, usize) {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim_end) != Some("---") {
+        return (NoteMeta::default(), 0);
+    }
+    let Some(end) = lines.position(|l| matches!(l.trim_end(), "---" | "...")) else {
+        return (NoteMeta::default(), 0);
+    };
+    let yaml: Vec<&str> = text.lines().skip(1).take(end).collect();
+    let Ok(serde_yaml::Value::Mapping(map)) =
+        serde_yaml::from_str::<serde_yaml::Value>(&yaml.join("\n"))
+    else {
+        return (NoteMeta::default(), end + 2);
+    };
+    let field = |key: &str| map.get(serde_yaml::Value::String(key.into()));
+
+    let mut tags: Vec<String> = ["tags", "tag"]
+        .iter()
+        .filter_map(|k| field(k))
+        .flat_map(yaml_strings)
+        .map(|t| t.trim_start_matches('#').to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    let world = field("world")
+        .and_then(|v| v.as_str())
+        .map(|w| w.trim().to_string())
+        .filter(|w| !w.is_empty());
+    let date = field("date")
+        .and_then(|v| v.as_str())
+        .and_then(|d| d.get(..10))
+        .filter(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").is_ok())
+        .map(str::to_string);
+    (NoteMeta { tags, world, date }, end + 2)
+}
+
+/// Notes under `dir`, skipping hidden folders and the index folder.
+fn note_files(dir: &Path, skip: &Path, out: &mut Vec<PathBuf>) -> Result<(), String> {
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with('.'));
+        if hidden || path == skip {
+            continue;
+        }
+        if path.is_dir() {
+            note_files(&path, skip, out)?;
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| NOTE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn modified_millis(path: &Path) -> Result<i64, String> {
+    let modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| e.to_string())?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0))
+}
+
+async fn remove_note(conn: &mut SqliteConnection, doc_id: &str) -> Result<(), String> {
+    sqlx::query("DELETE FROM embeddings WHERE doc_id = ?")
+        .bind(doc_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    for table in ["note_meta", "note_tags", "note_links"] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE path = ?"))
+            .bind(doc_id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Create the chunk and note tables if they are missing and bring the index
+/// up to the current format.
+pub async fn ensure_schema(conn: &mut SqliteConnection) -> Result<(), String> {
+    conn.execute(SCHEMA).await.map_err(|e| e.to_string())?;
+    index_format::migrate(conn).await?;
+    Ok(())
+}
+
+/// Index the notes in `vault` into the SQLite index at `db_path`.
+///
+/// A note is chunked again only when its modification time changed and its
+/// SHA-256 differs from the last run; notes that disappeared are removed once
+/// the whole vault has been read. A missing `vault` is an error, so an
+/// unmounted folder doesn't empty the index.
+/// Every chunk gets a hashed vector, and then `model` vectors unless it is
+/// `hash`. If the model can't be reached the hashed vectors are kept and the
+/// report shows nothing embedded. `force` re-chunks every note. Finally the
+/// vector graph is brought up to date with whichever vectors searches use.
+///
+/// Front matter is left out of the chunks and stored as [`NoteMeta`]; a note
+/// without a `date` is dated by its modification time.
+pub async fn index_vault(
+    vault: &Path,
+    db_path: &Path,
+    base_url: &str,
+    model: &str,
+    force: bool,
+) -> Result<IndexReport, String> {
+    if !vault.is_dir() {
+        return Err(format!("Vault not found at {}", vault.display()));
+    }
+    let mut files = Vec::new();
+    note_files(vault, db_path.parent().unwrap_or(vault), &mut files)?;
+
+    if let Some(parent) = db_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut conn =
+        SqliteConnection::connect(&format!("sqlite:{}?mode=rwc", db_path.to_string_lossy()))
+            .await
+            .map_err(|e| e.to_string())?;
+    ensure_schema(&mut conn).await?;
+
+    let mut report = IndexReport {
+        scanned: files.len(),
+        ..Default::default()
+    };
+
+    let mut seen = HashSet::new();
+    for path in files {
+        let doc_id = path
+            .strip_prefix(vault)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        seen.insert(doc_id.clone());
+        let mtime = modified_millis(&path)?;
+        // Notes indexed before metadata was recorded count as changed.
+        let known = sqlx::query(
+            "SELECT f.mtime, f.hash FROM vault_files f
+             JOIN note_meta m ON m.path = f.path WHERE f.path = ?",
+        )
+        .bind(&doc_id)
+        .fetch_optional(&mut conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .map(|r| (r.get::<i64, _>("mtime"), r.get::<String, _>("hash")));
+        if !force && known.as_ref().is_some_and(|(m, _)| *m == mtime) {
+            report.unchanged += 1;
+            continue;
+        }
+        let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+        let hash = format!("{:x}", Sha256::digest(&bytes));
+        let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+        if force || known.as_ref().map(|(_, h)| h) != Some(&hash) {
+            remove_note(&mut tx, &doc_id).await?;
+            let text = String::from_utf8_lossy(&bytes);
+            let (mut meta, skip) = parse_front_matter(&text);
+            let body: Vec<&str> = text.lines().skip(skip).collect();
+            let body = body.join("\n");
+            meta.tags.extend(vault_graph::parse_inline_tags(&body));
+            meta.tags.sort();
+            meta.tags.dedup();
+            if meta.date.is_none() {
+                meta.date = chrono::DateTime::from_timestamp_millis(mtime)
+                    .map(|d| d.format("%Y-%m-%d").to_string());
+            }
+            sqlx::query("INSERT INTO note_meta (path, world, date) VALUES (?, ?, ?)")
+                .bind(&doc_id)
+                .bind(&meta.world)
+                .bind(&meta.date)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+            for tag in &meta.tags {
+                sqlx::query("INSERT INTO note_tags (path, tag) VALUES (?, ?)")
+                    .bind(&doc_id)
+                    .bind(tag)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            for link in vault_graph::parse_links(&body) {
+                sqlx::query(
+                    "INSERT INTO note_links (path, target, heading, alias, embed, line)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&doc_id)
+                .bind(&link.target)
+                .bind(&link.heading)
+                .bind(&link.alias)
+                .bind(link.embed)
+                .bind(link.line as i64 + skip as i64)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+            for (i, mut chunk) in chunk_note(&body).into_iter().enumerate() {
+                chunk.line_start += skip as u32;
+                chunk.line_end += skip as u32;
+                let vector = encode_vector(&hash_embed(&chunk.text));
+                sqlx::query("INSERT OR REPLACE INTO embeddings VALUES (?, ?, ?, ?, ?, ?)")
+                    .bind(format!("{doc_id}#{i}"))
+                    .bind(vector)
+                    .bind(&doc_id)
+                    .bind(chunk.line_start as i64)
+                    .bind(chunk.line_end as i64)
+                    .bind(&chunk.text)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                report.chunks += 1;
+            }
+            report.indexed += 1;
+        } else {
+            // Touched but not edited.
+            report.unchanged += 1;
+        }
+        sqlx::query("INSERT OR REPLACE INTO vault_files (path, mtime, hash) VALUES (?, ?, ?)")
+            .bind(&doc_id)
+            .bind(mtime)
+            .bind(&hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        tx.commit().await.map_err(|e| e.to_string())?;
+    }
+
+    let known: Vec<String> = sqlx::query("SELECT path FROM vault_files")
+        .fetch_all(&mut conn)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|r| r.get("path"))
+        .collect();
+    for doc_id in known.into_iter().filter(|p| !seen.contains(p)) {
+        remove_note(&mut conn, &doc_id).await?;
+        sqlx::query("DELETE FROM vault_files WHERE path = ?")
+            .bind(&doc_id)
+            .execute(&mut conn)
+            .await
+            .map_err(|e| e.to_string())?;
+        report.removed += 1;
+    }
+
+    if model != HASH_MODEL {
+        match embeddings::sync_vectors(&mut conn, base_url, model).await {
+            Ok(n) => report.embedded = n,
+            Err(e) => log::warn!("could not embed vault with {model}, keeping hashed vectors: {e}"),
+        }
+    }
+    let source = ann::current_source(&mut conn, model).await?;
+    let graph = db_path.with_file_name(ann::GRAPH_FILE);
+    if let Err(e) = ann::update_graph(&mut conn, &graph, source, ann::EXACT_SEARCH_LIMIT).await {
+        log::warn!("could not update the vector index, searches stay exact: {e}");
+    }
+    Ok(report)
+}
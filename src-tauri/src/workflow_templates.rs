@@ -0,0 +1,360 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::prompts;
+
+/// Templates shipped with the app, written to the template folder on first use.
+const BUILTIN_TEMPLATES: [(&str, &str); 3] = [
+    ("portrait", include_str!("../workflows/portrait.json")),
+    ("token", include_str!("../workflows/token.json")),
+    ("landscape", include_str!("../workflows/landscape.json")),
+];
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamKind {
+    Text,
+    Integer,
+    Number,
+    /// Integer seed; missing or `-1` picks a random one.
+    Seed,
+    /// `WIDTHxHEIGHT`, exposed to the workflow as `size.width` / `size.height`.
+    Size,
+    /// Checkpoint file name as known to ComfyUI.
+    Checkpoint,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateParam {
+    pub name: String,
+    pub kind: ParamKind,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    #[serde(default)]
+    pub choices: Option<Vec<String>>,
+}
+
+/// A ComfyUI graph (API format) with `{{name}}` placeholders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowTemplate {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub parameters: Vec<TemplateParam>,
+    pub workflow: Value,
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateSummary {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub version: u32,
+    pub parameters: Vec<TemplateParam>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderedWorkflow {
+    pub template_id: String,
+    pub version: u32,
+    /// Final parameter values, including defaults and generated seeds.
+    pub params: BTreeMap<String, Value>,
+    pub workflow: Value,
+}
+
+impl From<&WorkflowTemplate> for TemplateSummary {
+    fn from(t: &WorkflowTemplate) -> Self {
+        TemplateSummary {
+            id: t.id.clone(),
+            name: t.name.clone(),
+            description: t.description.clone(),
+            version: t.version,
+            parameters: t.parameters.clone(),
+        }
+    }
+}
+
+fn check_range(p: &TemplateParam, v: f64, errors: &mut Vec<String>) {
+    if let Some(min) = p.min {
+        if v < min {
+            errors.push(format!("{}: must be at least {min}", p.name));
+        }
+    }
+    if let Some(max) = p.max {
+        if v > max {
+            errors.push(format!("{}: must be at most {max}", p.name));
+        }
+    }
+}
+
+fn parse_size(v: &Value) -> Option<(u64, u64)> {
+    match v {
+        Value::String(s) => {
+            let (w, h) = s
+                .to_lowercase()
+                .split_once('x')
+                .map(|(w, h)| (w.trim().parse::<u64>().ok(), h.trim().parse::<u64>().ok()))?;
+            Some((w?, h?))
+        }
+        Value::Object(o) => Some((o.get("width")?.as_u64()?, o.get("height")?.as_u64()?)),
+        _ => None,
+    }
+}
+
+/// Validate one parameter and add its substitution values to `out`.
+fn resolve_param(
+    p: &TemplateParam,
+    value: Option<&Value>,
+    out: &mut BTreeMap<String, Value>,
+    errors: &mut Vec<String>,
+) {
+    let value = value.filter(|v| !v.is_null()).or(p.default.as_ref());
+    let random_seed = Value::from(-1);
+    let value = match value {
+        Some(v) => v,
+        None if p.kind == ParamKind::Seed => &random_seed,
+        None if p.required => {
+            errors.push(format!("{}: required", p.name));
+            return;
+        }
+        None => {
+            errors.push(format!("{}: no value and no default", p.name));
+            return;
+        }
+    };
+    match p.kind {
+        ParamKind::Text | ParamKind::Checkpoint => {
+            let s = match value.as_str() {
+                Some(s) => s,
+                None => {
+                    errors.push(format!("{}: expected a string", p.name));
+                    return;
+                }
+            };
+            if p.required && s.trim().is_empty() {
+                errors.push(format!("{}: required", p.name));
+            }
+            if p.kind == ParamKind::Checkpoint && s.trim().is_empty() {
+                errors.push(format!("{}: checkpoint name is empty", p.name));
+            }
+            if let Some(choices) = &p.choices {
+                if !choices.iter().any(|c| c == s) {
+                    errors.push(format!("{}: must be one of {}", p.name, choices.join(", ")));
+                }
+            }
+            out.insert(p.name.clone(), Value::String(s.to_string()));
+        }
+        ParamKind::Integer => match value.as_i64() {
+            Some(n) => {
+                check_range(p, n as f64, errors);
+                out.insert(p.name.clone(), Value::from(n));
+            }
+            None => errors.push(format!("{}: expected an integer", p.name)),
+        },
+        ParamKind::Number => match value.as_f64() {
+            Some(n) => {
+                check_range(p, n, errors);
+                out.insert(p.name.clone(), Value::from(n));
+            }
+            None => errors.push(format!("{}: expected a number", p.name)),
+        },
+        ParamKind::Size => match parse_size(value) {
+            Some((w, h)) => {
+                if w == 0 || h == 0 || w % 8 != 0 || h % 8 != 0 {
+                    errors.push(format!(
+                        "{}: width and height must be positive multiples of 8",
+                        p.name
+                    ));
+                }
+                check_range(p, w.max(h) as f64, errors);
+                out.insert(p.name.clone(), Value::String(format!("{w}x{h}")));
+                out.insert(format!("{}.width", p.name), Value::from(w));
+                out.insert(format!("{}.height", p.name), Value::from(h));
+            }
+            None => errors.push(format!("{}: expected WIDTHxHEIGHT", p.name)),
+        },
+        ParamKind::Seed => {
+            let seed = if value.as_i64() == Some(-1) {
+                thread_rng().gen_range(0..u32::MAX as u64)
+            } else if let Some(seed) = value.as_u64() {
+                seed
+            } else {
+                errors.push(format!("{}: expected a non-negative integer", p.name));
+                return;
+            };
+            out.insert(p.name.clone(), Value::from(seed));
+        }
+    }
+}
+
+fn placeholder_text(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Replace the `{{name}}` placeholders in `text`. Only the template is
+/// scanned, so parameter values are inserted as they are.
+fn fill(text: &str, values: &BTreeMap<String, Value>, errors: &mut Vec<String>) -> String {
+    prompts::fill_placeholders(text, |key| values.get(key).map(placeholder_text), errors)
+}
+
+fn substitute(node: &mut Value, values: &BTreeMap<String, Value>, errors: &mut Vec<String>) {
+    match node {
+        Value::String(s) => {
+            let trimmed = s.trim();
+            if let Some(key) = trimmed
+                .strip_prefix("{{")
+                .and_then(|r| r.strip_suffix("}}"))
+                .map(str::trim)
+            {
+                if let Some(v) = values.get(key) {
+                    *node = v.clone();
+                    return;
+                }
+            }
+            *s = fill(s, values, errors);
+        }
+        Value::Array(items) => {
+            for item in items {
+                substitute(item, values, errors);
+            }
+        }
+        Value::Object(map) => {
+            for v in map.values_mut() {
+                substitute(v, values, errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl WorkflowTemplate {
+    /// Validate `params` against the declared parameters and produce a
+    /// workflow ready for `comfy_submit_workflow`.
+    ///
+    /// All validation problems are reported together, one per `; `.
+    pub fn render(&self, params: &Map<String, Value>) -> Result<RenderedWorkflow, String> {
+        let mut errors = Vec::new();
+        for key in params.keys() {
+            if !self.parameters.iter().any(|p| &p.name == key) {
+                errors.push(format!("{key}: unknown parameter"));
+            }
+        }
+        let mut values = BTreeMap::new();
+        for p in &self.parameters {
+            resolve_param(p, params.get(&p.name), &mut values, &mut errors);
+        }
+        let mut workflow = self.workflow.clone();
+        if errors.is_empty() {
+            substitute(&mut workflow, &values, &mut errors);
+        }
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+        let params = values
+            .into_iter()
+            .filter(|(k, _)| self.parameters.iter().any(|p| &p.name == k))
+            .collect();
+        Ok(RenderedWorkflow {
+            template_id: self.id.clone(),
+            version: self.version,
+            params,
+            workflow,
+        })
+    }
+}
+
+/// Write the built-in templates into `dir` unless a file with the same id exists.
+pub fn install_builtin_templates(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    for (id, json) in BUILTIN_TEMPLATES {
+        let path = dir.join(format!("{id}.json"));
+        if !path.exists() {
+            fs::write(&path, json).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Load every `*.json` template in `dir`, sorted by id.
+pub fn load_templates(dir: &Path) -> Result<Vec<WorkflowTemplate>, String> {
+    let mut templates = Vec::new();
+    if !dir.exists() {
+        return Ok(templates);
+    }
+    let entries = fs::read_dir(dir).map_err(|e| e.to_string())?;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|s| s.to_str()) != Some("json") {
+            continue;
+        }
+        let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        match serde_json::from_str::<WorkflowTemplate>(&contents) {
+            Ok(t) => templates.push(t),
+            Err(e) => log::warn!("skipping workflow template {}: {e}", path.display()),
+        }
+    }
+    templates.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(templates)
+}
+
+fn template_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|_| "app data dir".to_string())?
+        .join("comfy-templates");
+    install_builtin_templates(&dir)?;
+    Ok(dir)
+}
+
+#[tauri::command]
+pub async fn list_workflow_templates<R: Runtime>(
+    app: AppHandle<R>,
+) -> Result<Vec<TemplateSummary>, String> {
+    let dir = template_dir(&app)?;
+    Ok(load_templates(&dir)?
+        .iter()
+        .map(TemplateSummary::from)
+        .collect())
+}
+
+#[tauri::command]
+pub async fn render_workflow_template<R: Runtime>(
+    app: AppHandle<R>,
+    id: String,
+    params: Map<String, Value>,
+) -> Result<RenderedWorkflow, String> {
+    let dir = template_dir(&app)?;
+    let template = load_templates(&dir)?
+        .into_iter()
+        .find(|t| t.id == id)
+        .ok_or_else(|| format!("workflow template not found: {id}"))?;
+    template.render(&params)
+}
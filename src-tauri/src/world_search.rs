@@ -0,0 +1,438 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Connection, Executor, Row, SqliteConnection};
+use tauri::{AppHandle, Runtime};
+
+use crate::commands::{self, hash_embed};
+use crate::retrieval::{record_text, SourceKind};
+use crate::search::{self, Highlight, Scope, SearchFilters, SearchMode, SNIPPET_CHARS};
+use crate::vault_index;
+
+/// Saved records (NPCs, lore, spells, rules), one row per JSON file, with an
+/// FTS5 copy kept in step by triggers. Rules and spells are shared by every
+/// world and have an empty `world`.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS entities (
+    path TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    world TEXT NOT NULL,
+    id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    text TEXT NOT NULL,
+    mtime INTEGER NOT NULL
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS entities_fts USING fts5(title, text);
+CREATE TRIGGER IF NOT EXISTS entities_fts_insert AFTER INSERT ON entities
+BEGIN INSERT INTO entities_fts (rowid, title, text) VALUES (new.rowid, new.title, new.text); END;
+CREATE TRIGGER IF NOT EXISTS entities_fts_delete AFTER DELETE ON entities
+BEGIN DELETE FROM entities_fts WHERE rowid = old.rowid; END;
+";
+
+/// Folders holding each kind of saved record for one world; `None` for
+/// kinds that are not searched.
+#[derive(Debug, Clone, Default)]
+pub struct RecordDirs {
+    pub npcs: Option<PathBuf>,
+    pub lore: Option<PathBuf>,
+    pub spells: Option<PathBuf>,
+    pub rules: Option<PathBuf>,
+}
+
+impl RecordDirs {
+    fn dir(&self, kind: SourceKind) -> Option<&Path> {
+        match kind {
+            SourceKind::Npc => self.npcs.as_deref(),
+            SourceKind::Lore => self.lore.as_deref(),
+            SourceKind::Spell => self.spells.as_deref(),
+            SourceKind::Rule => self.rules.as_deref(),
+            SourceKind::Pdf | SourceKind::Vault => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WorldHit {
+    pub kind: SourceKind,
+    /// Record id, note path or PDF `doc_id`.
+    pub id: String,
+    pub title: String,
+    pub snippet: String,
+    /// Where the query's terms are in `snippet`.
+    pub highlights: Vec<Highlight>,
+    pub score: f32,
+    /// Pages (lines, for notes) of the best matching chunk.
+    pub pages: Option<[u32; 2]>,
+}
+
+fn kind_key(kind: SourceKind) -> String {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn modified_millis(path: &Path) -> i64 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Bring the `kind` records of `world` in the index up to date with `dir`:
+/// files that are new or have a new mtime are read again, and rows whose
+/// file is gone are dropped.
+async fn sync_records(
+    conn: &mut SqliteConnection,
+    kind: SourceKind,
+    world: &str,
+    dir: &Path,
+) -> Result<(), String> {
+    let key = kind_key(kind);
+    let known: HashMap<String, i64> =
+        sqlx::query("SELECT path, mtime FROM entities WHERE kind = ? AND world = ?")
+            .bind(&key)
+            .bind(world)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|r| (r.get("path"), r.get("mtime")))
+            .collect();
+
+    let mut seen = HashSet::new();
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json")
+            || path.file_name().and_then(|n| n.to_str()) == Some("index.json")
+        {
+            continue;
+        }
+        let path_key = path.to_string_lossy().to_string();
+        seen.insert(path_key.clone());
+        let mtime = modified_millis(&path);
+        if known.get(&path_key) == Some(&mtime) {
+            continue;
+        }
+        let record: Value = match fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|raw| serde_json::from_str(&raw).map_err(|e| e.to_string()))
+        {
+            Ok(record) => record,
+            Err(e) => {
+                log::warn!("skipping {}: {e}", path.display());
+                continue;
+            }
+        };
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let id = record["id"].as_str().map(str::to_string).unwrap_or(stem);
+        let title = ["name", "title"]
+            .iter()
+            .find_map(|k| record[*k].as_str())
+            .unwrap_or(&id)
+            .to_string();
+        sqlx::query("DELETE FROM entities WHERE path = ?")
+            .bind(&path_key)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        sqlx::query(
+            "INSERT INTO entities (path, kind, world, id, title, text, mtime)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&path_key)
+        .bind(&key)
+        .bind(world)
+        .bind(&id)
+        .bind(&title)
+        .bind(record_text(&record))
+        .bind(mtime)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+    for gone in known.keys().filter(|p| !seen.contains(*p)) {
+        sqlx::query("DELETE FROM entities WHERE path = ?")
+            .bind(gone)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+fn record_hit(
+    kind: SourceKind,
+    id: String,
+    title: String,
+    text: &str,
+    query: &str,
+    score: f32,
+) -> WorldHit {
+    let snippet = search::snippet(text, query, SNIPPET_CHARS);
+    WorldHit {
+        kind,
+        id,
+        title,
+        snippet: snippet.text,
+        highlights: snippet.highlights,
+        score,
+        pages: None,
+    }
+}
+
+/// Best `limit` records of one kind by BM25, names counting four times as
+/// much as the rest of the text.
+async fn search_records(
+    conn: &mut SqliteConnection,
+    kind: SourceKind,
+    world: &str,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<WorldHit>, String> {
+    let Some(fts) = search::fts_query(query) else {
+        return Ok(Vec::new());
+    };
+    Ok(sqlx::query(
+        "SELECT e.id, e.title, e.text, bm25(entities_fts, 4.0, 1.0) AS rank
+         FROM entities_fts JOIN entities e ON e.rowid = entities_fts.rowid
+         WHERE entities_fts MATCH ? AND e.kind = ? AND e.world = ?
+         ORDER BY rank LIMIT ?",
+    )
+    .bind(fts)
+    .bind(kind_key(kind))
+    .bind(world)
+    .bind(limit as i64)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?
+    .into_iter()
+    .map(|r| {
+        let score = -(r.get::<f64, _>("rank") as f32);
+        record_hit(
+            kind,
+            r.get("id"),
+            r.get("title"),
+            r.get("text"),
+            query,
+            score,
+        )
+    })
+    .collect())
+}
+
+/// Best `limit` records of one kind by the cosine of their hashed vectors
+/// with the query's; records without a word of the query are left out.
+async fn similar_records(
+    conn: &mut SqliteConnection,
+    kind: SourceKind,
+    world: &str,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<WorldHit>, String> {
+    let qvec = hash_embed(query);
+    let mut scored: Vec<(f32, String, String, String)> =
+        sqlx::query("SELECT id, title, text FROM entities WHERE kind = ? AND world = ?")
+            .bind(kind_key(kind))
+            .bind(world)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter_map(|r| {
+                let (id, title, text): (String, String, String) =
+                    (r.get("id"), r.get("title"), r.get("text"));
+                let vec = hash_embed(&format!("{title} {text}"));
+                let score: f32 = vec.iter().zip(&qvec).map(|(a, b)| a * b).sum();
+                (score > 0.0).then_some((score, id, title, text))
+            })
+            .collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    Ok(scored
+        .into_iter()
+        .take(limit)
+        .map(|(score, id, title, text)| record_hit(kind, id, title, &text, query, score))
+        .collect())
+}
+
+/// Best chunk of each of the top `limit` notes or PDFs, ranked by `mode`.
+async fn search_chunks(
+    conn: &mut SqliteConnection,
+    index_dir: &Path,
+    kind: SourceKind,
+    query: &str,
+    mode: SearchMode,
+    limit: usize,
+) -> Result<Vec<WorldHit>, String> {
+    let scope = match kind {
+        SourceKind::Vault => Scope::Notes,
+        _ => Scope::Pdfs,
+    };
+    let chunks = search::search_index(
+        index_dir,
+        query,
+        limit * 4,
+        mode,
+        scope,
+        &SearchFilters::default(),
+    )
+    .await?;
+    let mut seen = HashSet::new();
+    let mut hits = Vec::new();
+    for chunk in chunks {
+        if hits.len() == limit || !seen.insert(chunk.doc_id.clone()) {
+            continue;
+        }
+        let title = match kind {
+            SourceKind::Vault => Path::new(&chunk.doc_id)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string()),
+            // The PDF table is missing until the first import.
+            _ => sqlx::query("SELECT title FROM pdf_documents WHERE doc_id = ?")
+                .bind(&chunk.doc_id)
+                .fetch_optional(&mut *conn)
+                .await
+                .ok()
+                .flatten()
+                .map(|r| r.get("title")),
+        };
+        let snippet = search::snippet(&chunk.text, query, SNIPPET_CHARS);
+        hits.push(WorldHit {
+            kind,
+            title: title.unwrap_or_else(|| chunk.doc_id.clone()),
+            snippet: snippet.text,
+            highlights: snippet.highlights,
+            id: chunk.doc_id,
+            score: chunk.score,
+            pages: Some(chunk.page_range),
+        });
+    }
+    Ok(hits)
+}
+
+/// Search the records of `world` and the vault and PDF chunks in one go.
+///
+/// Hits of every kind are ranked together twice, by BM25 and by vector
+/// similarity (records by their hashed vectors, chunks as in
+/// [`search::vector_search`]), and the two rankings are merged by reciprocal
+/// rank fusion with the hybrid search weights; the fused score becomes the
+/// hit's score. Notes and PDFs give one hit per document. Kinds without a
+/// folder in `dirs` are skipped.
+pub async fn search_world_index(
+    index_dir: &Path,
+    dirs: &RecordDirs,
+    world: &str,
+    query: &str,
+    kinds: &[SourceKind],
+    limit: usize,
+) -> Result<Vec<WorldHit>, String> {
+    fs::create_dir_all(index_dir).map_err(|e| e.to_string())?;
+    let db_path = index_dir.join("index.sqlite");
+    let mut conn =
+        SqliteConnection::connect(&format!("sqlite:{}?mode=rwc", db_path.to_string_lossy()))
+            .await
+            .map_err(|e| e.to_string())?;
+    vault_index::ensure_schema(&mut conn).await?;
+    conn.execute(SCHEMA).await.map_err(|e| e.to_string())?;
+
+    let mut keyword = Vec::new();
+    let mut vector = Vec::new();
+    for &kind in kinds {
+        if matches!(kind, SourceKind::Pdf | SourceKind::Vault) {
+            let conn = &mut conn;
+            keyword.extend(
+                search_chunks(conn, index_dir, kind, query, SearchMode::Keyword, limit).await?,
+            );
+            vector.extend(
+                search_chunks(conn, index_dir, kind, query, SearchMode::Vector, limit).await?,
+            );
+        } else if let Some(dir) = dirs.dir(kind) {
+            let shared = matches!(kind, SourceKind::Spell | SourceKind::Rule);
+            let world = if shared { "" } else { world };
+            sync_records(&mut conn, kind, world, dir).await?;
+            keyword.extend(search_records(&mut conn, kind, world, query, limit).await?);
+            vector.extend(similar_records(&mut conn, kind, world, query, limit).await?);
+        }
+    }
+
+    // The keyword hits go in first, their snippets being picked around the
+    // matched terms.
+    let mut by_key: HashMap<String, WorldHit> = HashMap::new();
+    let mut rank = |mut hits: Vec<WorldHit>| -> Vec<(String, f32)> {
+        hits.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+        hits.into_iter()
+            .map(|hit| {
+                let key = format!("{}:{}", kind_key(hit.kind), hit.id);
+                let score = hit.score;
+                by_key.entry(key.clone()).or_insert(hit);
+                (key, score)
+            })
+            .collect()
+    };
+    let keyword = rank(keyword);
+    let vector = rank(vector);
+    let weights = search::hybrid_weights();
+    let lists = [
+        (keyword.as_slice(), weights.keyword),
+        (vector.as_slice(), weights.vector),
+    ];
+    Ok(search::reciprocal_rank_fusion(&lists, weights.rrf_k)
+        .into_iter()
+        .take(limit)
+        .filter_map(|(key, score)| {
+            by_key.remove(&key).map(|mut hit| {
+                hit.score = score;
+                hit
+            })
+        })
+        .collect())
+}
+
+/// Search NPCs, lore, spells, rules, notes and PDFs of `world` at once.
+/// `kinds` defaults to all of them and `limit` to 20.
+#[tauri::command]
+pub async fn search_world<R: Runtime>(
+    app: AppHandle<R>,
+    world: String,
+    query: String,
+    kinds: Option<Vec<SourceKind>>,
+    limit: Option<u32>,
+) -> Result<Vec<WorldHit>, String> {
+    let kinds = kinds.unwrap_or_else(|| {
+        use SourceKind::*;
+        vec![Npc, Lore, Spell, Rule, Vault, Pdf]
+    });
+    // Only the folders searched, as looking them up creates them.
+    let mut dirs = RecordDirs::default();
+    for kind in &kinds {
+        match kind {
+            SourceKind::Npc => dirs.npcs = Some(commands::npc_storage_dir(&app, &world)?),
+            SourceKind::Lore => dirs.lore = Some(commands::lore_storage_dir(&app, &world)?),
+            SourceKind::Spell => dirs.spells = Some(commands::spell_storage_dir(&app)?),
+            SourceKind::Rule => dirs.rules = Some(commands::rule_storage_dir(&app)?),
+            SourceKind::Pdf | SourceKind::Vault => {}
+        }
+    }
+    search_world_index(
+        &commands::vault_index_dir(),
+        &dirs,
+        &world,
+        &query,
+        &kinds,
+        limit.unwrap_or(20) as usize,
+    )
+    .await
+}
@@ -0,0 +1,204 @@
+use std::time::Instant;
+
+use blossom_lib::ann::{nearest, update_graph, HnswIndex, VectorSource};
+use blossom_lib::commands::hash_embed;
+use blossom_lib::embeddings::{encode_vector, set_meta};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sqlx::{Connection, Executor, Row, SqliteConnection};
+
+fn normalized(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    v.iter_mut().for_each(|x| *x /= norm);
+    v
+}
+
+/// `n` unit vectors scattered around `clusters` random centres, like chunks
+/// of notes about a handful of topics.
+fn corpus(n: usize, dim: usize, clusters: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let centres: Vec<Vec<f32>> = (0..clusters)
+        .map(|_| (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect())
+        .collect();
+    (0..n)
+        .map(|_| {
+            let c = &centres[rng.gen_range(0..clusters)];
+            normalized(c.iter().map(|x| x + rng.gen_range(-0.4..0.4)).collect())
+        })
+        .collect()
+}
+
+fn exact(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<String> {
+    let mut scored: Vec<(usize, f32)> = vectors
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i, v.iter().zip(query).map(|(a, b)| a * b).sum()))
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored
+        .iter()
+        .take(k)
+        .map(|(i, _)| format!("c{i}"))
+        .collect()
+}
+
+fn build(vectors: &[Vec<f32>]) -> HnswIndex {
+    let mut index = HnswIndex::new("test", vectors[0].len());
+    for (i, v) in vectors.iter().enumerate() {
+        index.insert(&format!("c{i}"), v).unwrap();
+    }
+    index
+}
+
+fn recall(index: &HnswIndex, vectors: &[Vec<f32>], queries: &[Vec<f32>], k: usize) -> f64 {
+    let mut found = 0;
+    for q in queries {
+        let truth = exact(vectors, q, k);
+        found += index
+            .search(q, k, 64)
+            .iter()
+            .filter(|(id, _)| truth.contains(id))
+            .count();
+    }
+    found as f64 / (queries.len() * k) as f64
+}
+
+#[test]
+fn finds_neighbours_across_inserts_removes_and_reloads() {
+    let mut vectors = corpus(2_050, 16, 20, 1);
+    let queries = vectors.split_off(2_000);
+    let mut index = build(&vectors);
+    assert_eq!(index.len(), 2_000);
+    assert!(recall(&index, &vectors, &queries, 10) >= 0.9);
+
+    // A removed chunk is never returned; a replaced one moves with its vector.
+    let top = index.search(&queries[0], 1, 64)[0].0.clone();
+    assert!(index.remove(&top));
+    assert!(index
+        .search(&queries[0], 10, 64)
+        .iter()
+        .all(|(id, _)| *id != top));
+    index.insert("c1", &queries[0]).unwrap();
+    assert_eq!(index.search(&queries[0], 1, 64)[0].0, "c1");
+    assert_eq!(index.len(), 1_999);
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("vectors.hnsw");
+    index.save(&path).unwrap();
+    let loaded = HnswIndex::load(&path).unwrap();
+    assert_eq!(loaded.len(), index.len());
+    for q in &queries {
+        assert_eq!(loaded.search(q, 5, 64), index.search(q, 5, 64));
+    }
+
+    std::fs::write(&path, b"BLSMHNSW\x01").unwrap();
+    assert!(HnswIndex::load(&path).is_err());
+    assert!(index.insert("bad", &[1.0]).is_err());
+}
+
+#[tokio::test]
+async fn graph_follows_index_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("index.sqlite");
+    let graph = dir.path().join("vectors.hnsw");
+    let mut conn = SqliteConnection::connect(&format!("sqlite:{}?mode=rwc", db.display()))
+        .await
+        .unwrap();
+    conn.execute(
+        "CREATE TABLE embeddings (chunk_id TEXT PRIMARY KEY, embedding BLOB, doc_id TEXT,
+         page_start INTEGER, page_end INTEGER, text TEXT)",
+    )
+    .await
+    .unwrap();
+    blossom_lib::embeddings::ensure_schema(&mut conn)
+        .await
+        .unwrap();
+
+    async fn add(conn: &mut SqliteConnection, id: &str, v: &[f32]) {
+        sqlx::query("INSERT OR REPLACE INTO chunk_vectors VALUES (?, 'm', ?)")
+            .bind(id)
+            .bind(encode_vector(v))
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+    }
+    for (i, v) in corpus(50, 8, 4, 3).iter().enumerate() {
+        add(&mut conn, &format!("c{i}"), v).await;
+    }
+    set_meta(&mut conn, "embedding_model", "m").await.unwrap();
+    set_meta(&mut conn, "embedding_dim", "8").await.unwrap();
+    let source = VectorSource::Model("m");
+    let query = normalized(vec![1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0]);
+
+    // Searching alone never builds a graph.
+    assert!(nearest(&graph, source, &query, 3).unwrap().is_none());
+    update_graph(&mut conn, &graph, source, 10).await.unwrap();
+    let hits = nearest(&graph, source, &query, 3).unwrap().unwrap();
+    assert_eq!(hits.len(), 3);
+    assert!(graph.exists());
+    assert!(nearest(&graph, VectorSource::Hashed, &hash_embed("x"), 3)
+        .unwrap()
+        .is_none());
+
+    // Chunks written after the graph was saved are picked up by the next update.
+    add(&mut conn, "new", &query).await;
+    update_graph(&mut conn, &graph, source, 10).await.unwrap();
+    let hits = nearest(&graph, source, &query, 3).unwrap().unwrap();
+    assert_eq!(hits[0].0, "new");
+
+    sqlx::query("DELETE FROM chunk_vectors WHERE chunk_id = 'new'")
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    update_graph(&mut conn, &graph, source, 10).await.unwrap();
+    let hits = nearest(&graph, source, &query, 3).unwrap().unwrap();
+    assert!(hits.iter().all(|(id, _)| id != "new"));
+    let logged: i64 = sqlx::query("SELECT COUNT(*) AS n FROM vector_log")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get("n");
+    assert_eq!(logged, 0);
+
+    // Small indexes are searched exactly and keep no graph.
+    update_graph(&mut conn, &graph, source, 100).await.unwrap();
+    assert!(nearest(&graph, source, &query, 3).unwrap().is_none());
+    assert!(!graph.exists());
+}
+
+/// Run with `cargo test --release --test ann_index -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn benchmark_100k_chunks() {
+    let dim = 64;
+    let mut vectors = corpus(100_200, dim, 500, 4);
+    let queries = vectors.split_off(100_000);
+    let k = 10;
+
+    let started = Instant::now();
+    let index = build(&vectors);
+    println!("built 100k-chunk graph in {:?}", started.elapsed());
+
+    let started = Instant::now();
+    for q in &queries {
+        std::hint::black_box(exact(&vectors, q, k));
+    }
+    let exact_time = started.elapsed();
+
+    let started = Instant::now();
+    for q in &queries {
+        std::hint::black_box(index.search(q, k, 64));
+    }
+    let ann_time = started.elapsed();
+
+    let recall = recall(&index, &vectors, &queries, k);
+    println!(
+        "{} queries: exact {:?}, hnsw {:?} ({:.1}x faster), recall@{k} {:.3}",
+        queries.len(),
+        exact_time,
+        ann_time,
+        exact_time.as_secs_f64() / ann_time.as_secs_f64(),
+        recall
+    );
+    assert!(recall >= 0.9);
+    assert!(ann_time * 5 < exact_time);
+}
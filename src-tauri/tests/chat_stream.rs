@@ -0,0 +1,95 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use blossom_lib::{
+    commands::ChatMessage,
+    llm::{CancelToken, ChatRequest, LlmBackend, OllamaBackend, OpenAiBackend},
+};
+use httpmock::prelude::*;
+
+const BODY: &str = concat!(
+    "{\"message\":{\"role\":\"assistant\",\"content\":\"Hel\"},\"done\":false}\n",
+    "{\"message\":{\"role\":\"assistant\",\"content\":\"lo \"},\"done\":false}\n",
+    "{\"message\":{\"role\":\"assistant\",\"content\":\"there\"},\"done\":false}\n",
+    "{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true}\n",
+);
+
+fn request() -> ChatRequest {
+    ChatRequest::new(
+        "m",
+        vec![ChatMessage {
+            role: "user".into(),
+            content: "hi".into(),
+        }],
+    )
+}
+
+#[tokio::test]
+async fn streams_and_cancels() {
+    let server = MockServer::start();
+    let chat = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .json_body_partial(r#"{ "model": "m", "stream": true }"#);
+        then.status(200).body(BODY);
+    });
+
+    let backend = OllamaBackend::new(server.base_url());
+
+    let cancel = CancelToken::new();
+    let mut chunks = Vec::new();
+    let reply = backend
+        .chat_stream(&request(), &cancel, &mut |d| chunks.push(d.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(chunks, vec!["Hel", "lo ", "there"]);
+    assert_eq!(reply.content, "Hello there");
+    assert!(!reply.cancelled);
+
+    // Cancelling after the first chunk keeps what was received so far.
+    let cancel = CancelToken::new();
+    let reply = backend
+        .chat_stream(&request(), &cancel, &mut |_| cancel.cancel())
+        .await
+        .unwrap();
+    assert_eq!(reply.content, "Hel");
+    assert!(reply.cancelled);
+
+    chat.assert_hits(2);
+}
+
+#[tokio::test]
+async fn cancels_while_waiting_for_the_first_token() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/api/chat");
+        then.status(200).delay(Duration::from_secs(10)).body(BODY);
+    });
+    server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200)
+            .delay(Duration::from_secs(10))
+            .body("data: [DONE]\n\n");
+    });
+    let backends: [Box<dyn LlmBackend>; 2] = [
+        Box::new(OllamaBackend::new(server.base_url())),
+        Box::new(OpenAiBackend::new(server.base_url(), None)),
+    ];
+    for backend in backends {
+        let cancel = Arc::new(CancelToken::new());
+        let stop = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            stop.cancel();
+        });
+        let started = Instant::now();
+        let reply = backend
+            .chat_stream(&request(), &cancel, &mut |_| {})
+            .await
+            .unwrap();
+        assert!(reply.cancelled && reply.content.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,248 @@
+use std::{fs, time::Duration};
+
+use blossom_lib::comfy::{run_workflow, WORKFLOW_TIMEOUT};
+use futures::{SinkExt, StreamExt};
+use httpmock::prelude::*;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+/// Answer one plain HTTP request the way ComfyUI would for prompt `p1`.
+async fn answer_http(mut stream: TcpStream) {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            return;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let head = String::from_utf8_lossy(&request).to_string();
+    let body = if head.starts_with("POST /prompt") {
+        json!({ "prompt_id": "p1" }).to_string()
+    } else if head.starts_with("GET /history/p1") {
+        json!({ "p1": {
+            "status": { "status_str": "success", "completed": true },
+            "outputs": { "9": { "images": [
+                { "filename": "ws.png", "subfolder": "", "type": "output" }
+            ] } }
+        } })
+        .to_string()
+    } else if head.starts_with("GET /view") {
+        "PNGDATA".to_string()
+    } else {
+        json!({ "queue_running": [], "queue_pending": [] }).to_string()
+    };
+    let reply = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(reply.as_bytes()).await.unwrap();
+}
+
+/// A ComfyUI stand-in serving HTTP and the `/ws` socket on one port; the
+/// socket sends `events` as soon as a client connects.
+async fn fake_comfy(events: Vec<Value>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let events = events.clone();
+            tokio::spawn(async move {
+                let mut head = [0u8; 8];
+                let n = stream.peek(&mut head).await.unwrap();
+                if !head[..n].starts_with(b"GET /ws") {
+                    return answer_http(stream).await;
+                }
+                let mut ws = accept_async(stream).await.unwrap();
+                for event in events {
+                    ws.send(Message::Text(event.to_string())).await.unwrap();
+                }
+                while let Some(Ok(_)) = ws.next().await {}
+            });
+        }
+    });
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn runs_workflow_and_downloads_outputs() {
+    let server = MockServer::start();
+    let workflow = json!({
+        "3": { "class_type": "KSampler", "inputs": { "seed": 1 } },
+        "9": { "class_type": "SaveImage", "inputs": { "filename_prefix": "ComfyUI" } }
+    });
+
+    let prompt = server.mock(|when, then| {
+        when.method(POST)
+            .path("/prompt")
+            .json_body_partial(r#"{ "prompt": { "3": { "class_type": "KSampler" } } }"#);
+        then.status(200)
+            .json_body(json!({ "prompt_id": "p1", "number": 0 }));
+    });
+    let history = server.mock(|when, then| {
+        when.method(GET).path("/history/p1");
+        then.status(200).json_body(json!({
+            "p1": {
+                "status": { "status_str": "success", "completed": true },
+                "outputs": {
+                    "9": { "images": [
+                        { "filename": "ComfyUI_00001_.png", "subfolder": "", "type": "output" },
+                        { "filename": "preview.png", "subfolder": "", "type": "temp" }
+                    ] }
+                }
+            }
+        }));
+    });
+    let view = server.mock(|when, then| {
+        when.method(GET)
+            .path("/view")
+            .query_param("filename", "ComfyUI_00001_.png")
+            .query_param("type", "output");
+        then.status(200).body("PNGDATA");
+    });
+
+    let out = tempfile::tempdir().unwrap();
+    let paths = run_workflow(
+        &server.base_url(),
+        &workflow,
+        out.path(),
+        None,
+        WORKFLOW_TIMEOUT,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(paths, vec![out.path().join("ComfyUI_00001_.png")]);
+    assert_eq!(fs::read_to_string(&paths[0]).unwrap(), "PNGDATA");
+    prompt.assert();
+    history.assert();
+    view.assert();
+}
+
+#[tokio::test]
+async fn reports_execution_errors() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/prompt");
+        then.status(200).json_body(json!({ "prompt_id": "p2" }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/history/p2");
+        then.status(200).json_body(json!({
+            "p2": { "status": { "status_str": "error", "messages": [] }, "outputs": {} }
+        }));
+    });
+
+    let out = tempfile::tempdir().unwrap();
+    let err = run_workflow(
+        &server.base_url(),
+        &json!({}),
+        out.path(),
+        None,
+        WORKFLOW_TIMEOUT,
+    )
+    .await
+    .unwrap_err();
+    assert!(err.contains("execution failed"), "{err}");
+}
+
+#[tokio::test]
+async fn follows_progress_over_the_websocket() {
+    let base = fake_comfy(vec![
+        json!({ "type": "status", "data": { "status": {} } }),
+        json!({ "type": "executing", "data": { "node": "3", "prompt_id": "p1" } }),
+        json!({ "type": "progress", "data": { "value": 1, "max": 2, "prompt_id": "p1" } }),
+        json!({ "type": "progress", "data": { "value": 7, "max": 9, "prompt_id": "other" } }),
+        json!({ "type": "progress", "data": { "value": 2, "max": 2, "prompt_id": "p1" } }),
+        json!({ "type": "executing", "data": { "node": null, "prompt_id": "p1" } }),
+    ])
+    .await;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let out = tempfile::tempdir().unwrap();
+    let paths = run_workflow(&base, &json!({}), out.path(), Some(tx), WORKFLOW_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(paths, vec![out.path().join("ws.png")]);
+
+    let mut seen = Vec::new();
+    while let Some(p) = rx.recv().await {
+        seen.push((p.prompt_id, p.node, p.value, p.max));
+    }
+    assert_eq!(
+        seen,
+        [
+            ("p1".to_string(), Some("3".to_string()), 1, 2),
+            ("p1".to_string(), Some("3".to_string()), 2, 2),
+        ]
+    );
+
+    let base = fake_comfy(vec![json!({
+        "type": "execution_error",
+        "data": { "prompt_id": "p1", "exception_message": "CUDA out of memory\n" }
+    })])
+    .await;
+    let err = run_workflow(&base, &json!({}), out.path(), None, WORKFLOW_TIMEOUT)
+        .await
+        .unwrap_err();
+    assert_eq!(err, "ComfyUI execution failed: CUDA out of memory");
+}
+
+#[tokio::test]
+async fn gives_up_on_stuck_and_dropped_prompts() {
+    let server = MockServer::start();
+    let (base, workflow) = (server.base_url(), json!({}));
+    server.mock(|when, then| {
+        when.method(POST).path("/prompt");
+        then.status(200).json_body(json!({ "prompt_id": "p3" }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/history/p3");
+        then.status(200).json_body(json!({}));
+    });
+    let mut queue = server.mock(|when, then| {
+        when.method(GET).path("/queue");
+        then.status(200)
+            .json_body(json!({ "queue_running": [[0, "p3", {}]], "queue_pending": [] }));
+    });
+    let interrupt = server.mock(|when, then| {
+        when.method(POST)
+            .path("/interrupt")
+            .json_body(json!({ "prompt_id": "p3" }));
+        then.status(200);
+    });
+
+    let out = tempfile::tempdir().unwrap();
+    let timeout = Duration::from_millis(600);
+    let err = run_workflow(&base, &workflow, out.path(), None, timeout)
+        .await
+        .unwrap_err();
+    assert!(err.contains("timed out"), "{err}");
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    interrupt.assert();
+
+    // Cancelling the task drops the future, which interrupts the prompt too.
+    let run = run_workflow(&base, &workflow, out.path(), None, WORKFLOW_TIMEOUT);
+    assert!(tokio::time::timeout(Duration::from_millis(300), run)
+        .await
+        .is_err());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    interrupt.assert_hits(2);
+
+    // Restarted ComfyUI: neither finished nor queued.
+    queue.delete();
+    server.mock(|when, then| {
+        when.method(GET).path("/queue");
+        then.status(200)
+            .json_body(json!({ "queue_running": [], "queue_pending": [] }));
+    });
+    let err = run_workflow(&base, &workflow, out.path(), None, WORKFLOW_TIMEOUT)
+        .await
+        .unwrap_err();
+    assert_eq!(err, "ComfyUI dropped prompt p3");
+}
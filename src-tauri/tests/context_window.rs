@@ -0,0 +1,112 @@
+use blossom_lib::{
+    commands::ChatMessage,
+    context::{estimate_messages_tokens, estimate_tokens, fit_messages, trim_to_tokens},
+    llm::OllamaBackend,
+};
+use httpmock::prelude::*;
+use serde_json::json;
+
+fn msg(role: &str, content: &str) -> ChatMessage {
+    ChatMessage {
+        role: role.into(),
+        content: content.into(),
+    }
+}
+
+fn long_chat() -> Vec<ChatMessage> {
+    let mut messages = vec![msg("system", "You are the game master's assistant.")];
+    for i in 0..20 {
+        messages.push(msg(
+            "user",
+            &format!("Question {i}: {}", "tell me more ".repeat(20)),
+        ));
+        messages.push(msg(
+            "assistant",
+            &format!("Answer {i}: {}", "the tale goes on ".repeat(20)),
+        ));
+    }
+    messages
+}
+
+#[test]
+fn estimates_and_trims() {
+    assert_eq!(estimate_tokens(""), 0);
+    assert_eq!(estimate_tokens("abcd"), 1);
+    assert_eq!(estimate_tokens("abcde"), 2);
+
+    let text = "line one\nline two\nline three";
+    assert_eq!(trim_to_tokens(text, 100), text);
+    assert_eq!(trim_to_tokens(text, 5), "line one\nline two…");
+    assert_eq!(
+        trim_to_tokens(&"x".repeat(40), 2),
+        format!("{}…", "x".repeat(8))
+    );
+}
+
+#[tokio::test]
+async fn short_chats_are_left_alone() {
+    let backend = OllamaBackend::new("http://127.0.0.1:9");
+    let messages = vec![msg("user", "Hello")];
+    let fitted = fit_messages(&backend, "m", messages.clone(), 4096)
+        .await
+        .unwrap();
+    assert_eq!(fitted.len(), 1);
+    assert_eq!(fitted[0].content, "Hello");
+}
+
+#[tokio::test]
+async fn summarizes_older_turns() {
+    let server = MockServer::start();
+    let summary = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .body_contains("Summarize the conversation")
+            .body_contains("Question 0");
+        then.status(200).json_body(json!({
+            "message": { "role": "assistant", "content": "The party asked about the tale." }
+        }));
+    });
+
+    let backend = OllamaBackend::new(server.base_url());
+    let messages = long_chat();
+    let budget = 1024;
+    let fitted = fit_messages(&backend, "m", messages.clone(), budget)
+        .await
+        .unwrap();
+
+    assert!(estimate_messages_tokens(&fitted) <= budget);
+    assert_eq!(fitted[0].content, messages[0].content);
+    assert_eq!(fitted[1].role, "system");
+    assert!(fitted[1]
+        .content
+        .contains("The party asked about the tale."));
+    // The newest turns survive verbatim.
+    assert_eq!(
+        fitted.last().unwrap().content,
+        messages.last().unwrap().content
+    );
+    assert!(fitted.len() < messages.len());
+    summary.assert();
+}
+
+#[tokio::test]
+async fn drops_older_turns_when_summary_fails() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(POST).path("/api/chat");
+        then.status(500).body("model not loaded");
+    });
+
+    let backend = OllamaBackend::new(server.base_url());
+    let messages = long_chat();
+    let fitted = fit_messages(&backend, "m", messages.clone(), 1024)
+        .await
+        .unwrap();
+    assert_eq!(fitted[0].role, "system");
+    assert_eq!(fitted[1].role, "user");
+    assert_eq!(
+        fitted.last().unwrap().content,
+        messages.last().unwrap().content
+    );
+    assert!(estimate_messages_tokens(&fitted) <= 1024);
+}
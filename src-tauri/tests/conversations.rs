@@ -0,0 +1,102 @@
+use blossom_lib::commands::ChatMessage;
+use blossom_lib::conversations::{self, open_store};
+
+fn msg(role: &str, content: &str) -> ChatMessage {
+    ChatMessage {
+        role: role.into(),
+        content: content.into(),
+    }
+}
+
+#[tokio::test]
+async fn stores_and_reloads_conversations() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("conversations.sqlite");
+    let mut conn = open_store(&path).await.unwrap();
+
+    let conv = conversations::create(&mut conn, None, None, Some("Eldoria".into()))
+        .await
+        .unwrap();
+    let summary = conversations::append(
+        &mut conn,
+        &conv.id,
+        &[
+            msg("user", "Who rules the free city of Marrowgate these days?"),
+            msg("assistant", "The Lantern Council, since the duke vanished."),
+        ],
+    )
+    .await
+    .unwrap();
+    assert_eq!(summary.title, "Who rules the free city of Marrowgate th");
+    drop(conn);
+
+    // Reopening the file keeps everything.
+    let mut conn = open_store(&path).await.unwrap();
+    let history = conversations::history(&mut conn, &conv.id).await.unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[1].role, "assistant");
+
+    let other = conversations::create(&mut conn, Some("Loot".into()), None, None)
+        .await
+        .unwrap();
+    let eldoria = conversations::list(&mut conn, Some("Eldoria"))
+        .await
+        .unwrap();
+    assert_eq!(eldoria.len(), 1);
+    assert_eq!(eldoria[0].id, conv.id);
+    assert_eq!(conversations::list(&mut conn, None).await.unwrap().len(), 2);
+
+    conversations::rename(&mut conn, &other.id, "Treasure tables")
+        .await
+        .unwrap();
+    let hits = conversations::search(&mut conn, "treasure", None, 10)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].conversation.id, other.id);
+    assert!(hits[0].message_id.is_none());
+
+    // Wildcards in the query match literally.
+    let sale = conversations::create(&mut conn, Some("Half_price: 50% off".into()), None, None)
+        .await
+        .unwrap();
+    let hits = conversations::search(&mut conn, "50%", None, 10)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].conversation.id, sale.id);
+    assert_eq!(
+        conversations::search(&mut conn, "f_price", None, 10)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(conversations::search(&mut conn, "e_t", None, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let hits = conversations::search(&mut conn, "lantern", Some("Eldoria"), 10)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert!(hits[0].snippet.contains("[Lantern]"), "{}", hits[0].snippet);
+    assert!(
+        conversations::search(&mut conn, "lantern", Some("Elsewhere"), 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    conversations::delete(&mut conn, &conv.id).await.unwrap();
+    assert!(conversations::get(&mut conn, &conv.id).await.is_err());
+    let err = conversations::delete(&mut conn, &conv.id)
+        .await
+        .unwrap_err();
+    assert!(err.contains("not found"), "{err}");
+    assert!(conversations::search(&mut conn, "lantern", None, 10)
+        .await
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,169 @@
+use std::env;
+
+use blossom_lib::embeddings::{
+    decode_vector, index_model, sync_vectors, IndexModel, DEFAULT_EMBEDDING_MODEL, HASH_MODEL,
+};
+use blossom_lib::search::{search_index, Scope, SearchFilters, SearchMode};
+use blossom_lib::vault_index::ensure_schema;
+use httpmock::prelude::*;
+use serde_json::json;
+use sqlx::{Connection, Executor, Row, SqliteConnection};
+
+async fn index_with(chunks: &[(&str, &str)]) -> (tempfile::TempDir, SqliteConnection) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("index.sqlite");
+    let mut conn = SqliteConnection::connect(&format!("sqlite:{}?mode=rwc", path.display()))
+        .await
+        .unwrap();
+    conn.execute(
+        "CREATE TABLE embeddings (chunk_id TEXT PRIMARY KEY, embedding BLOB, doc_id TEXT,
+         page_start INTEGER, page_end INTEGER, text TEXT)",
+    )
+    .await
+    .unwrap();
+    ensure_schema(&mut conn).await.unwrap();
+    for (id, text) in chunks {
+        add_chunk(&mut conn, id, text).await;
+    }
+    (dir, conn)
+}
+
+async fn add_chunk(conn: &mut SqliteConnection, id: &str, text: &str) {
+    sqlx::query("INSERT INTO embeddings VALUES (?, x'', 'doc', 1, 1, ?)")
+        .bind(id)
+        .bind(text)
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn embeds_new_chunks_and_reindexes_on_model_change() {
+    let server = MockServer::start();
+    let small = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/embeddings")
+            .json_body_partial(r#"{ "model": "small" }"#);
+        then.status(200)
+            .json_body(json!({ "embedding": [3.0, 4.0] }));
+    });
+    let large = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/embeddings")
+            .json_body_partial(r#"{ "model": "large" }"#);
+        then.status(200)
+            .json_body(json!({ "embedding": [1.0, 0.0, 0.0] }));
+    });
+
+    let (_dir, mut conn) = index_with(&[("a", "Fireball"), ("b", "Flame strike")]).await;
+    assert_eq!(index_model(&mut conn).await.unwrap(), None);
+
+    assert_eq!(
+        sync_vectors(&mut conn, &server.base_url(), "small")
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        index_model(&mut conn).await.unwrap(),
+        Some(IndexModel {
+            model: "small".into(),
+            dimension: 2
+        })
+    );
+    let row = sqlx::query("SELECT embedding FROM chunk_vectors WHERE chunk_id = 'a'")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(
+        decode_vector(&row.get::<Vec<u8>, _>("embedding")),
+        vec![0.6, 0.8]
+    );
+
+    // Only chunks added since the last sync are embedded.
+    add_chunk(&mut conn, "c", "Wall of fire").await;
+    assert_eq!(
+        sync_vectors(&mut conn, &server.base_url(), "small")
+            .await
+            .unwrap(),
+        1
+    );
+    small.assert_hits(3);
+
+    // A different model replaces every vector.
+    assert_eq!(
+        sync_vectors(&mut conn, &server.base_url(), "large")
+            .await
+            .unwrap(),
+        3
+    );
+    large.assert_hits(3);
+    assert_eq!(index_model(&mut conn).await.unwrap().unwrap().dimension, 3);
+    let models: Vec<String> = sqlx::query("SELECT DISTINCT model FROM chunk_vectors")
+        .fetch_all(&mut conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.get("model"))
+        .collect();
+    assert_eq!(models, vec!["large".to_string()]);
+}
+
+#[tokio::test]
+async fn hash_model_works_offline_and_unreachable_server_fails() {
+    let (_dir, mut conn) = index_with(&[("a", "Fireball")]).await;
+    assert!(sync_vectors(&mut conn, "http://127.0.0.1:9", "small")
+        .await
+        .is_err());
+    assert_eq!(index_model(&mut conn).await.unwrap(), None);
+
+    assert_eq!(
+        sync_vectors(&mut conn, "http://127.0.0.1:9", HASH_MODEL)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        index_model(&mut conn).await.unwrap().unwrap().dimension,
+        512
+    );
+}
+
+#[tokio::test]
+async fn searching_embeds_only_the_query() {
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    let server = MockServer::start();
+    env::set_var("BLOSSOM_OLLAMA_URL", server.base_url());
+    let embed = server.mock(|when, then| {
+        when.method(POST).path("/api/embeddings");
+        then.status(200)
+            .json_body(json!({ "embedding": [3.0, 4.0] }));
+    });
+
+    let (dir, mut conn) = index_with(&[("a", "Fireball"), ("b", "Flame strike")]).await;
+    sync_vectors(&mut conn, &server.base_url(), DEFAULT_EMBEDDING_MODEL)
+        .await
+        .unwrap();
+    add_chunk(&mut conn, "c", "Wall of fire").await;
+
+    let hits = search_index(
+        dir.path(),
+        "fire",
+        3,
+        SearchMode::Vector,
+        Scope::All,
+        &SearchFilters::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(hits.len(), 2);
+    // Two chunks, then the query; "c" waits for the next indexing run.
+    embed.assert_hits(3);
+    let vectors: i64 = sqlx::query("SELECT COUNT(*) AS n FROM chunk_vectors")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get("n");
+    assert_eq!(vectors, 2);
+}
@@ -0,0 +1,158 @@
+use std::env;
+
+use blossom_lib::search::{
+    fts_query, reciprocal_rank_fusion, search_index, Scope, SearchFilters, SearchMode,
+};
+use blossom_lib::vault_index::ensure_schema;
+use sqlx::{Connection, Executor, SqliteConnection};
+
+#[test]
+fn builds_safe_fts_queries() {
+    assert_eq!(
+        fts_query("Wall of Force?").unwrap(),
+        r#""wall of force" OR "wall" OR "of" OR "force""#
+    );
+    assert_eq!(
+        fts_query(r#"Strahd" OR x:*"#).unwrap(),
+        r#""strahd or x" OR "strahd" OR "or" OR "x""#
+    );
+    assert!(fts_query(" ?! ").is_none());
+}
+
+#[test]
+fn fuses_rankings_by_weighted_reciprocal_rank() {
+    let keyword = vec![("a".to_string(), 9.0), ("b".to_string(), 5.0)];
+    let vector = vec![("b".to_string(), 0.9), ("c".to_string(), 0.8)];
+    let fused = reciprocal_rank_fusion(&[(&keyword, 1.0), (&vector, 1.0)], 60.0);
+    let ids: Vec<&str> = fused.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, vec!["b", "a", "c"]);
+    assert!((fused[0].1 - (1.0 / 62.0 + 1.0 / 61.0)).abs() < 1e-6);
+
+    // A zero weight leaves the other ranking's order.
+    let fused = reciprocal_rank_fusion(&[(&keyword, 1.0), (&vector, 0.0)], 60.0);
+    assert_eq!(fused[0].0, "a");
+}
+
+#[tokio::test]
+async fn exact_names_rank_first_in_keyword_and_hybrid_modes() {
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    env::set_var("BLOSSOM_OLLAMA_URL", "http://127.0.0.1:9");
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut conn = SqliteConnection::connect(&format!(
+        "sqlite:{}?mode=rwc",
+        dir.path().join("index.sqlite").display()
+    ))
+    .await
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE embeddings (chunk_id TEXT PRIMARY KEY, embedding BLOB, doc_id TEXT,
+         page_start INTEGER, page_end INTEGER, text TEXT);
+         CREATE TABLE vault_files (path TEXT PRIMARY KEY, mtime INTEGER NOT NULL, hash TEXT NOT NULL);
+         INSERT INTO vault_files VALUES ('notes/session.md', 0, '');",
+    )
+    .await
+    .unwrap();
+    ensure_schema(&mut conn).await.unwrap();
+    let chunks = [
+        (
+            "phb_1",
+            "phb",
+            "A wall blocks movement. Walls of stone and a wall of ice are common.",
+        ),
+        (
+            "phb_2",
+            "phb",
+            "Force damage is dealt by magic missile. Force is pure magical energy.",
+        ),
+        (
+            "phb_3",
+            "phb",
+            "Wall of Force creates an invisible wall of force.",
+        ),
+        (
+            "phb_4",
+            "phb",
+            "The cleric casts a healing spell on the wall guard.",
+        ),
+        (
+            "notes/session.md#0",
+            "notes/session.md",
+            "Strahd cast Wall of Force on the party.",
+        ),
+    ];
+    for (id, doc, text) in chunks {
+        sqlx::query("INSERT INTO embeddings VALUES (?, x'', ?, 1, 1, ?)")
+            .bind(id)
+            .bind(doc)
+            .bind(text)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+    }
+
+    let none = SearchFilters::default();
+    let hits = search_index(
+        dir.path(),
+        "Wall of Force",
+        2,
+        SearchMode::Keyword,
+        Scope::Pdfs,
+        &none,
+    )
+    .await
+    .unwrap();
+    assert_eq!(hits[0].chunk_id, "phb_3");
+    assert!(hits.iter().all(|h| h.doc_id == "phb"));
+
+    let hits = search_index(
+        dir.path(),
+        "Strahd",
+        3,
+        SearchMode::Hybrid,
+        Scope::All,
+        &none,
+    )
+    .await
+    .unwrap();
+    assert_eq!(hits[0].doc_id, "notes/session.md");
+
+    // Chunks added after the keyword index was built are found too.
+    sqlx::query(
+        "INSERT INTO embeddings VALUES ('dmg_1', x'', 'dmg', 9, 9, 'Ravenloft lair actions')",
+    )
+    .execute(&mut conn)
+    .await
+    .unwrap();
+    let hits = search_index(
+        dir.path(),
+        "ravenloft",
+        1,
+        SearchMode::Keyword,
+        Scope::All,
+        &none,
+    )
+    .await
+    .unwrap();
+    assert_eq!(hits[0].chunk_id, "dmg_1");
+    assert_eq!(hits[0].page_range, [9, 9]);
+
+    let hits = search_index(dir.path(), "wall", 3, SearchMode::Vector, Scope::All, &none)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 3);
+
+    let missing = tempfile::tempdir().unwrap();
+    assert!(search_index(
+        missing.path(),
+        "wall",
+        3,
+        SearchMode::Hybrid,
+        Scope::All,
+        &none
+    )
+    .await
+    .unwrap()
+    .is_empty());
+}
@@ -0,0 +1,148 @@
+use std::{env, path::Path};
+
+use blossom_lib::commands::hash_embed;
+use blossom_lib::embeddings::decode_vector;
+use blossom_lib::index_format::{index_info, FORMAT_VERSION};
+use blossom_lib::search::{search_index, Scope, SearchFilters, SearchMode};
+use blossom_lib::vault_index::ensure_schema;
+use sqlx::{Connection, Executor, Row, SqliteConnection};
+use tokio::sync::Mutex;
+
+/// Each test points `HOME` at a folder of its own, so they take turns.
+static ENV: Mutex<()> = Mutex::const_new(());
+
+/// An index as `pdf_tools.py` left it before the format was versioned.
+async fn legacy_index(dir: &Path, vector: Vec<u8>) -> SqliteConnection {
+    let path = dir.join("index.sqlite");
+    let mut conn = SqliteConnection::connect(&format!("sqlite:{}?mode=rwc", path.display()))
+        .await
+        .unwrap();
+    conn.execute(
+        "CREATE TABLE embeddings (chunk_id TEXT PRIMARY KEY, embedding BLOB, doc_id TEXT,
+         page_start INTEGER, page_end INTEGER, text TEXT)",
+    )
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO embeddings VALUES ('doc_0', ?, 'doc', 1, 1, 'wall of force')")
+        .bind(vector)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    conn
+}
+
+async fn search(dir: &Path) -> Result<Vec<String>, String> {
+    Ok(search_index(
+        dir,
+        "wall of force",
+        3,
+        SearchMode::Vector,
+        Scope::All,
+        &SearchFilters::default(),
+    )
+    .await?
+    .into_iter()
+    .map(|h| h.chunk_id)
+    .collect())
+}
+
+#[tokio::test]
+async fn legacy_indexes_are_upgraded_in_place() {
+    let _env = ENV.lock().await;
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    let dir = tempfile::tempdir().unwrap();
+    let vector = hash_embed("wall of force");
+    let big_endian: Vec<u8> = vector.iter().flat_map(|v| v.to_be_bytes()).collect();
+    let mut conn = legacy_index(dir.path(), big_endian).await;
+    // Written on a big-endian machine and copied here.
+    conn.execute(
+        "CREATE TABLE index_meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+         INSERT INTO index_meta VALUES ('byte_order', 'big');",
+    )
+    .await
+    .unwrap();
+
+    ensure_schema(&mut conn).await.unwrap();
+    let info = index_info(&mut conn).await.unwrap();
+    assert_eq!(
+        (info.format_version, info.byte_order.as_str()),
+        (FORMAT_VERSION, "little")
+    );
+    let blob: Vec<u8> = sqlx::query("SELECT embedding FROM embeddings")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get("embedding");
+    assert_eq!(decode_vector(&blob), vector);
+    // Opening it again changes nothing.
+    ensure_schema(&mut conn).await.unwrap();
+    assert_eq!(index_info(&mut conn).await.unwrap(), info);
+    assert_eq!(search(dir.path()).await.unwrap(), ["doc_0"]);
+
+    // Chunks written before the upgrade and after it are searchable by keyword.
+    sqlx::query("INSERT INTO embeddings VALUES ('doc_1', x'', 'doc', 2, 2, 'wall of stone')")
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let matched: i64 =
+        sqlx::query("SELECT COUNT(*) AS n FROM chunks_fts WHERE chunks_fts MATCH 'wall'")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap()
+            .get("n");
+    assert_eq!(matched, 2);
+}
+
+#[tokio::test]
+async fn searches_leave_old_indexes_for_the_indexer() {
+    let _env = ENV.lock().await;
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    let dir = tempfile::tempdir().unwrap();
+    let vector: Vec<u8> = hash_embed("wall of force")
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let mut conn = legacy_index(dir.path(), vector).await;
+
+    let err = search(dir.path()).await.unwrap_err();
+    assert!(err.contains("index needs reindexing"), "{err}");
+    assert!(err.contains("format 1"), "{err}");
+    // Nothing was migrated or created.
+    let tables: i64 = sqlx::query("SELECT COUNT(*) AS n FROM sqlite_master WHERE type = 'table'")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get("n");
+    assert_eq!(tables, 1);
+
+    ensure_schema(&mut conn).await.unwrap();
+    assert_eq!(search(dir.path()).await.unwrap(), ["doc_0"]);
+}
+
+#[tokio::test]
+async fn unreadable_indexes_are_refused() {
+    let _env = ENV.lock().await;
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    let newer = tempfile::tempdir().unwrap();
+    let vector: Vec<u8> = hash_embed("wall")
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let mut conn = legacy_index(newer.path(), vector).await;
+    conn.execute(
+        "CREATE TABLE index_meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+         INSERT INTO index_meta VALUES ('format_version', '99');",
+    )
+    .await
+    .unwrap();
+    let err = search(newer.path()).await.unwrap_err();
+    assert!(err.contains("format 99"), "{err}");
+
+    let short = tempfile::tempdir().unwrap();
+    let mut conn = legacy_index(short.path(), vec![0; 64]).await;
+    let err = ensure_schema(&mut conn).await.unwrap_err();
+    assert!(err.contains("16-dimensional"), "{err}");
+}
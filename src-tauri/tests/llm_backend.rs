@@ -0,0 +1,72 @@
+use blossom_lib::{
+    commands::ChatMessage,
+    llm::{CancelToken, ChatRequest, LlmBackend, OllamaBackend, OpenAiBackend},
+};
+use httpmock::prelude::*;
+use serde_json::json;
+
+fn request() -> ChatRequest {
+    ChatRequest::new(
+        "local-model",
+        vec![ChatMessage {
+            role: "user".into(),
+            content: "Who rules Barovia?".into(),
+        }],
+    )
+}
+
+#[tokio::test]
+async fn openai_backend_chat_and_stream() {
+    let server = MockServer::start();
+    let plain = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .header("authorization", "Bearer secret")
+            .json_body_partial(r#"{ "model": "local-model", "stream": false }"#);
+        then.status(200).json_body(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "Strahd." } }]
+        }));
+    });
+    let streamed = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .json_body_partial(r#"{ "stream": true }"#);
+        then.status(200).body(concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Str\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"ahd.\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        ));
+    });
+
+    // A trailing `/v1` in the configured URL is tolerated.
+    let backend = OpenAiBackend::new(format!("{}/v1/", server.base_url()), Some("secret".into()));
+    assert_eq!(backend.chat(&request()).await.unwrap(), "Strahd.");
+
+    let cancel = CancelToken::new();
+    let mut chunks = Vec::new();
+    let reply = backend
+        .chat_stream(&request(), &cancel, &mut |d| chunks.push(d.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(chunks, vec!["Str", "ahd."]);
+    assert_eq!(reply.content, "Strahd.");
+
+    plain.assert();
+    streamed.assert();
+}
+
+#[tokio::test]
+async fn ollama_backend_chat() {
+    let server = MockServer::start();
+    let chat = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .json_body_partial(r#"{ "model": "local-model", "stream": false }"#);
+        then.status(200)
+            .json_body(json!({ "message": { "role": "assistant", "content": "Strahd." } }));
+    });
+    let backend = OllamaBackend::new(server.base_url());
+    assert_eq!(backend.chat(&request()).await.unwrap(), "Strahd.");
+    chat.assert();
+}
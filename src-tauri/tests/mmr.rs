@@ -0,0 +1,120 @@
+use std::{env, fs};
+
+use blossom_lib::embeddings::HASH_MODEL;
+use blossom_lib::search::{
+    mmr_rerank, search_page, ChunkHit, Diversity, PageRequest, Scope, SearchFilters, SearchMode,
+};
+use blossom_lib::vault_index::index_vault;
+
+fn hit(chunk_id: &str, doc_id: &str, text: &str, score: f32) -> ChunkHit {
+    ChunkHit {
+        chunk_id: chunk_id.into(),
+        doc_id: doc_id.into(),
+        page_range: [1, 1],
+        text: text.into(),
+        score,
+    }
+}
+
+fn ids(hits: &[ChunkHit]) -> Vec<&str> {
+    hits.iter().map(|h| h.chunk_id.as_str()).collect()
+}
+
+#[test]
+fn near_duplicates_give_way_to_other_sources() {
+    let repeated = "the wall of force is an invisible barrier";
+    let hits = vec![
+        hit("a1", "phb", repeated, 0.9),
+        hit("a2", "phb", repeated, 0.89),
+        hit("a3", "dmg", repeated, 0.88),
+        hit("b", "notes", "forcecage traps creatures in a cube", 0.5),
+    ];
+
+    let relevance_only = Diversity {
+        lambda: 1.0,
+        per_doc_cap: None,
+    };
+    assert_eq!(
+        ids(&mmr_rerank(hits.clone(), 4, &relevance_only)),
+        ["a1", "a2", "a3", "b"]
+    );
+
+    let balanced = Diversity {
+        lambda: 0.5,
+        per_doc_cap: None,
+    };
+    assert_eq!(ids(&mmr_rerank(hits.clone(), 2, &balanced)), ["a1", "b"]);
+
+    // The cap drops hits past it, even when nothing else is left.
+    let capped = Diversity {
+        lambda: 1.0,
+        per_doc_cap: Some(1),
+    };
+    let reranked = mmr_rerank(hits, 10, &capped);
+    assert_eq!(ids(&reranked), ["a1", "a3", "b"]);
+    assert_eq!(reranked[0].score, 0.9);
+}
+
+#[tokio::test]
+async fn vault_pages_are_reranked_before_paging() {
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    env::set_var("BLOSSOM_OLLAMA_URL", "http://127.0.0.1:9");
+
+    let vault = tempfile::tempdir().unwrap();
+    let mut long = String::new();
+    for i in 1..=5 {
+        long.push_str(&format!(
+            "## Part {i}\nThe castle gate is barred. The castle gate is guarded.\n\n"
+        ));
+    }
+    fs::write(vault.path().join("castle.md"), long).unwrap();
+    fs::write(
+        vault.path().join("village.md"),
+        "# Village\nA road leads from the village to the castle.",
+    )
+    .unwrap();
+    let index_dir = vault.path().join("Index");
+    index_vault(
+        vault.path(),
+        &index_dir.join("index.sqlite"),
+        "http://127.0.0.1:9",
+        HASH_MODEL,
+        false,
+    )
+    .await
+    .unwrap();
+
+    let search = |diversity: Option<Diversity>| {
+        let index_dir = index_dir.clone();
+        async move {
+            search_page(
+                &index_dir,
+                "castle gate",
+                SearchMode::Keyword,
+                Scope::All,
+                &SearchFilters::default(),
+                &PageRequest {
+                    limit: Some(3),
+                    ..Default::default()
+                },
+                diversity.as_ref(),
+            )
+            .await
+            .unwrap()
+        }
+    };
+
+    let plain = search(None).await;
+    assert!(plain.hits.iter().all(|h| h.doc_id == "castle.md"));
+
+    let diverse = search(Some(Diversity::default())).await;
+    let docs: Vec<&str> = diverse.hits.iter().map(|h| h.doc_id.as_str()).collect();
+    // Two chunks per document on a page, so the village note makes the
+    // first one; the other castle chunks are still counted and paged.
+    assert_eq!(docs, ["castle.md", "castle.md", "village.md"]);
+    assert_eq!(diverse.total, plain.total);
+    assert_eq!(diverse.facets, plain.facets);
+    assert!(diverse.facets.docs["castle.md"] > 2);
+    assert!(diverse.next_cursor.is_some());
+}
@@ -1,4 +1,9 @@
-use blossom_lib::commands::parse_npc_event;
+use std::env;
+
+use blossom_lib::commands::{npc_event_reply, parse_npc_event, ChatMessage};
+use blossom_lib::llm::OllamaBackend;
+use httpmock::prelude::*;
+use serde_json::json;
 
 #[test]
 fn parse_npc_event_valid() {
@@ -15,3 +20,42 @@ fn parse_npc_event_invalid() {
     assert!(parse_npc_event(json).is_err());
 }
 
+#[tokio::test]
+async fn long_scenes_are_fitted_to_the_context() {
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    let server = MockServer::start();
+    let summary = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .body_contains("Summarize the conversation");
+        then.status(200).json_body(json!({
+            "message": { "role": "assistant", "content": "The orcs were routed." }
+        }));
+    });
+    let event = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .json_body_partial(r#"{ "format": { "type": "object" } }"#)
+            .body_contains("The orcs were routed.");
+        then.status(200).json_body(json!({
+            "message": {
+                "role": "assistant",
+                "content": "{\"who\":\"Alice\",\"action\":\"cheer\",\"targets\":[],\"effects\":[],\"narration\":\"Alice cheers.\"}"
+            }
+        }));
+    });
+
+    let mut messages = Vec::new();
+    for i in 0..200 {
+        messages.push(ChatMessage {
+            role: if i % 2 == 0 { "user" } else { "assistant" }.into(),
+            content: format!("Round {i}: {}", "the battle rages on ".repeat(10)),
+        });
+    }
+    let backend = OllamaBackend::new(server.base_url());
+    let reply = npc_event_reply(&backend, "m", messages).await.unwrap();
+    assert_eq!(reply.action, "cheer");
+    summary.assert();
+    event.assert();
+}
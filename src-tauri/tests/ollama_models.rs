@@ -0,0 +1,85 @@
+use std::env;
+
+use blossom_lib::context::{context_budget, DEFAULT_CONTEXT_TOKENS};
+use blossom_lib::ollama::{
+    model_for, ollama_delete_model, ollama_list_models, ollama_show_model, save_model_settings,
+    ModelFeature, ModelSettings, DEFAULT_MODEL,
+};
+use httpmock::prelude::*;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+/// Held by tests that change process-wide environment variables.
+static ENV: Mutex<()> = Mutex::const_new(());
+
+#[tokio::test]
+async fn manages_models_through_api() {
+    let _env = ENV.lock().await;
+    let server = MockServer::start();
+    env::set_var("BLOSSOM_OLLAMA_URL", server.base_url());
+
+    let tags = server.mock(|when, then| {
+        when.method(GET).path("/api/tags");
+        then.status(200).json_body(json!({
+            "models": [
+                { "name": "gpt-oss:20b", "size": 42, "digest": "abc" },
+                { "name": "llama3:8b", "size": 7 }
+            ]
+        }));
+    });
+    let show = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/show")
+            .json_body(json!({ "model": "llama3:8b" }));
+        then.status(200)
+            .json_body(json!({ "details": { "family": "llama" } }));
+    });
+    let delete = server.mock(|when, then| {
+        when.method(DELETE)
+            .path("/api/delete")
+            .json_body(json!({ "model": "llama3:8b" }));
+        then.status(200);
+    });
+
+    let models = ollama_list_models().await.unwrap();
+    assert_eq!(models.len(), 2);
+    assert_eq!(models[0].name, "gpt-oss:20b");
+    assert_eq!(models[0].size, 42);
+
+    let info = ollama_show_model("llama3:8b".into()).await.unwrap();
+    assert_eq!(info["details"]["family"], "llama");
+
+    ollama_delete_model("llama3:8b".into()).await.unwrap();
+
+    tags.assert();
+    show.assert();
+    delete.assert();
+}
+
+#[tokio::test]
+async fn feature_overrides_fall_back_to_default() {
+    let _env = ENV.lock().await;
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+
+    assert_eq!(model_for(ModelFeature::Chat), DEFAULT_MODEL);
+
+    save_model_settings(ModelSettings {
+        default_model: Some("llama3:8b".into()),
+        chat_model: None,
+        npc_event_model: Some("mistral:7b".into()),
+        intent_model: Some("".into()),
+        embedding_model: None,
+        context_budgets: [("llama3".to_string(), 8192)].into_iter().collect(),
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(model_for(ModelFeature::Chat), "llama3:8b");
+    assert_eq!(model_for(ModelFeature::NpcEvent), "mistral:7b");
+    assert_eq!(model_for(ModelFeature::Intent), "llama3:8b");
+
+    // Budgets apply to every tag of a model unless a tag has its own.
+    assert_eq!(context_budget("llama3:8b"), 8192);
+    assert_eq!(context_budget("mistral:7b"), DEFAULT_CONTEXT_TOKENS);
+}
@@ -0,0 +1,256 @@
+use std::{fs, path::Path};
+
+use blossom_lib::pdf_library::{
+    add_pdf, chunk_pages, extract_pdf, list_pdfs, remove_pdf, AddAction, DuplicatePolicy,
+};
+use pdf_extract::content::{Content, Operation};
+use pdf_extract::{dictionary, Document, Object, Stream, StringFormat};
+use sqlx::{Connection, Row, SqliteConnection};
+
+/// A PDF with one page per entry of `pages`, written with Helvetica.
+fn write_pdf(path: &Path, title: &str, pages: &[&str]) {
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+    let kids: Vec<Object> = pages
+        .iter()
+        .map(|text| {
+            let content = Content {
+                operations: vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                    Operation::new("Td", vec![72.into(), 720.into()]),
+                    Operation::new("Tj", vec![Object::string_literal(*text)]),
+                    Operation::new("ET", vec![]),
+                ],
+            };
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+            })
+            .into()
+        })
+        .collect();
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    let info_id = doc.add_object(dictionary! {
+        "Title" => Object::String(title.as_bytes().to_vec(), StringFormat::Literal),
+        "CreationDate" => Object::string_literal("D:20240501120000Z"),
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc.trailer.set("Info", info_id);
+    doc.save(path).unwrap();
+}
+
+#[test]
+fn chunks_carry_their_page_span() {
+    let pages = vec!["word ".repeat(300), String::new(), "other ".repeat(300)];
+    let chunks = chunk_pages(&pages);
+    assert_eq!(chunks.len(), 2);
+    assert_eq!((chunks[0].page_start, chunks[0].page_end), (1, 3));
+    assert_eq!((chunks[1].page_start, chunks[1].page_end), (3, 3));
+    assert_eq!(chunks[1].text.split(' ').count(), 100);
+    assert!(chunk_pages(&[String::new()]).is_empty());
+}
+
+#[tokio::test]
+async fn adds_lists_and_removes_documents_without_python() {
+    let dir = tempfile::tempdir().unwrap();
+    let pdf = dir.path().join("phb.pdf");
+    write_pdf(
+        &pdf,
+        "Player's Handbook",
+        &["Wall of Force creates a wall.", "Grappling rules."],
+    );
+
+    let text = extract_pdf(&fs::read(&pdf).unwrap()).unwrap();
+    assert_eq!(text.pages.len(), 2);
+    assert!(text.pages[0].contains("Wall of Force"));
+    assert_eq!(text.title.as_deref(), Some("Player's Handbook"));
+    assert_eq!(text.created.as_deref(), Some("2024-05-01T12:00:00"));
+    assert!(extract_pdf(b"not a pdf").is_err());
+
+    let index = dir.path().join("Index");
+    let report = add_pdf(&index, &pdf, DuplicatePolicy::Skip).await.unwrap();
+    assert_eq!(report.action, AddAction::Added);
+    assert!(report.pages_without_text.is_empty());
+    let doc = report.doc;
+    assert_eq!(doc.pages, 2);
+    assert_eq!(doc.title, "Player's Handbook");
+    assert_eq!(doc.doc_id, &doc.hash[..16]);
+    // Files `pdf_tools.py ingest` reads for LLM tagging.
+    assert!(index.join(&doc.doc_id).join("doc.json").exists());
+    assert!(fs::read_to_string(index.join("chunks.jsonl"))
+        .unwrap()
+        .contains(&format!("{}_0", doc.doc_id)));
+
+    let mut conn =
+        SqliteConnection::connect(&format!("sqlite:{}", index.join("index.sqlite").display()))
+            .await
+            .unwrap();
+    let row = sqlx::query("SELECT page_start, page_end, text FROM embeddings WHERE doc_id = ?")
+        .bind(&doc.doc_id)
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(row.get::<i64, _>("page_start"), 1);
+    assert_eq!(row.get::<i64, _>("page_end"), 2);
+    assert!(row.get::<String, _>("text").contains("Grappling"));
+
+    // The same file again is the same document.
+    let again = add_pdf(&index, &pdf, DuplicatePolicy::KeepBoth)
+        .await
+        .unwrap();
+    assert_eq!((again.action, again.doc), (AddAction::Skipped, doc.clone()));
+
+    // Documents imported by the Python tool are listed too.
+    fs::create_dir_all(index.join("0123456789abcdef")).unwrap();
+    fs::write(
+        index.join("0123456789abcdef/doc.json"),
+        r#"{"title": "Old Import", "pages": 3, "hash": "abc", "created": null}"#,
+    )
+    .unwrap();
+    let docs = list_pdfs(&index).await.unwrap();
+    assert_eq!(docs.len(), 2);
+    assert!(docs.iter().any(|d| d.title == "Old Import" && d.pages == 3));
+
+    remove_pdf(&index, &doc.doc_id).await.unwrap();
+    let docs = list_pdfs(&index).await.unwrap();
+    assert_eq!(docs.len(), 1);
+    assert!(!index.join(&doc.doc_id).exists());
+    assert!(!fs::read_to_string(index.join("chunks.jsonl"))
+        .unwrap()
+        .contains(&doc.doc_id));
+    let left: i64 = sqlx::query("SELECT COUNT(*) AS n FROM embeddings")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get("n");
+    assert_eq!(left, 0);
+
+    // Scanned pages are imported but reported.
+    let scan = dir.path().join("map.pdf");
+    write_pdf(&scan, "Map of Barovia", &["Village of Barovia", " ", ""]);
+    let report = add_pdf(&index, &scan, DuplicatePolicy::Skip).await.unwrap();
+    assert_eq!(
+        (report.doc.pages, report.pages_without_text),
+        (3, vec![2, 3])
+    );
+}
+
+#[tokio::test]
+async fn duplicates_follow_the_policy_and_editions_are_added() {
+    let dir = tempfile::tempdir().unwrap();
+    let index = dir.path().join("Index");
+    let wall = "Wall of Force creates a wall. ".repeat(10);
+    let grapple = "Grappling rules. ".repeat(10);
+    let pages = [wall.as_str(), grapple.as_str()];
+    let original = dir.path().join("phb.pdf");
+    write_pdf(&original, "Player's Handbook", &pages);
+    // Re-downloaded under another name: different bytes, same text.
+    let copy = dir.path().join("phb (1).pdf");
+    write_pdf(&copy, "PHB", &pages);
+    let first = add_pdf(&index, &original, DuplicatePolicy::Skip)
+        .await
+        .unwrap()
+        .doc;
+
+    let skipped = add_pdf(&index, &copy, DuplicatePolicy::Skip).await.unwrap();
+    assert_eq!(skipped.action, AddAction::Skipped);
+    assert_eq!(skipped.doc.doc_id, first.doc_id);
+    assert_eq!(list_pdfs(&index).await.unwrap().len(), 1);
+
+    let kept = add_pdf(&index, &copy, DuplicatePolicy::KeepBoth)
+        .await
+        .unwrap();
+    assert_eq!(kept.action, AddAction::KeptBoth);
+    assert_eq!(
+        kept.doc.duplicate_of.as_deref(),
+        Some(first.doc_id.as_str())
+    );
+    assert_eq!(list_pdfs(&index).await.unwrap().len(), 2);
+    remove_pdf(&index, &kept.doc.doc_id).await.unwrap();
+
+    let replaced = add_pdf(&index, &copy, DuplicatePolicy::Replace)
+        .await
+        .unwrap();
+    assert_eq!(replaced.action, AddAction::Replaced);
+    assert_eq!(replaced.existing, vec![first.doc_id.clone()]);
+    let docs = list_pdfs(&index).await.unwrap();
+    assert_eq!(docs.len(), 1);
+    assert_eq!(docs[0].doc_id, replaced.doc.doc_id);
+
+    // Same title and page count with revised text is a new edition.
+    let errata = dir.path().join("phb-2024.pdf");
+    let dome = "Wall of Force creates a dome. ".repeat(10);
+    write_pdf(&errata, "PHB", &[&dome, &grapple]);
+    let edition = add_pdf(&index, &errata, DuplicatePolicy::Skip)
+        .await
+        .unwrap();
+    assert_eq!(edition.action, AddAction::NewEdition);
+    assert_eq!(
+        edition.doc.edition_of.as_deref(),
+        Some(replaced.doc.doc_id.as_str())
+    );
+    assert_eq!(list_pdfs(&index).await.unwrap().len(), 2);
+
+    // Scans have too little text to compare, so only the file counts.
+    let maps = ["Barovia", "Vallaki"].map(|name| {
+        let path = dir.path().join(format!("{name}.pdf"));
+        write_pdf(&path, name, &["", ""]);
+        path
+    });
+    for map in &maps {
+        let report = add_pdf(&index, map, DuplicatePolicy::Skip).await.unwrap();
+        assert_eq!(report.action, AddAction::Added);
+        assert_eq!(report.doc.content_hash, Some(report.doc.hash));
+    }
+}
+
+#[tokio::test]
+async fn libraries_from_before_duplicate_tracking_are_migrated() {
+    let dir = tempfile::tempdir().unwrap();
+    let index = dir.path().join("Index");
+    fs::create_dir_all(&index).unwrap();
+    let mut conn = SqliteConnection::connect(&format!(
+        "sqlite:{}?mode=rwc",
+        index.join("index.sqlite").display()
+    ))
+    .await
+    .unwrap();
+    sqlx::query(
+        "CREATE TABLE pdf_documents (doc_id TEXT PRIMARY KEY, title TEXT NOT NULL, author TEXT,
+         pages INTEGER NOT NULL, hash TEXT NOT NULL, created TEXT, added TEXT NOT NULL,
+         source TEXT)",
+    )
+    .execute(&mut conn)
+    .await
+    .unwrap();
+
+    let pdf = dir.path().join("phb.pdf");
+    write_pdf(&pdf, "Player's Handbook", &["Grappling rules."]);
+    let report = add_pdf(&index, &pdf, DuplicatePolicy::Skip).await.unwrap();
+    assert_eq!(report.action, AddAction::Added);
+    assert_eq!(list_pdfs(&index).await.unwrap(), vec![report.doc]);
+}
@@ -0,0 +1,142 @@
+use std::{collections::BTreeMap, fs};
+
+use blossom_lib::prompts::{
+    install_builtin_prompts, record_prompt_use, remove_prompt_override, reset_prompt,
+    resolve_prompt, save_prompt, PromptSource, PromptTemplate,
+};
+
+fn npc_vars() -> BTreeMap<&'static str, String> {
+    BTreeMap::from([("fields", "who, action".to_string())])
+}
+
+#[test]
+fn renders_builtin_prompts() {
+    let dir = tempfile::tempdir().unwrap();
+    install_builtin_prompts(dir.path()).unwrap();
+    assert!(dir.path().join("detect_intent.yaml").exists());
+
+    let prompt = resolve_prompt(dir.path(), None, "npc_event").unwrap();
+    assert_eq!(prompt.source, PromptSource::Builtin);
+    assert_eq!(prompt.template.version, 1);
+    assert_eq!(
+        prompt.template.render(&npc_vars()).unwrap(),
+        "Reply ONLY in JSON with fields who, action."
+    );
+
+    let err = prompt.template.render(&BTreeMap::new()).unwrap_err();
+    assert!(err.contains("fields: missing value"), "{err}");
+    assert!(resolve_prompt(dir.path(), None, "missing").is_err());
+}
+
+#[test]
+fn values_are_inserted_as_they_are() {
+    let prompt = PromptTemplate {
+        id: "intents".into(),
+        name: String::new(),
+        description: String::new(),
+        version: 1,
+        variables: vec!["intents".into(), "query".into()],
+        template: "Pick one of {{intents}} for {{query}}.".into(),
+    };
+    // A user-written description that looks like a placeholder.
+    let vars = BTreeMap::from([
+        ("intents", "npc ({{query}} about a person)".to_string()),
+        ("query", "Who rules Barovia?".to_string()),
+    ]);
+    assert_eq!(
+        prompt.render(&vars).unwrap(),
+        "Pick one of npc ({{query}} about a person) for Who rules Barovia?."
+    );
+}
+
+#[test]
+fn saves_versions_and_resets() {
+    let dir = tempfile::tempdir().unwrap();
+    install_builtin_prompts(dir.path()).unwrap();
+
+    let mut edited = resolve_prompt(dir.path(), None, "npc_event")
+        .unwrap()
+        .template;
+    edited.template = "Answer as JSON with {{fields}}.".into();
+    let saved = save_prompt(dir.path(), edited.clone()).unwrap();
+    assert_eq!(saved.version, 2);
+    assert!(dir.path().join("history/npc_event.v1.yaml").exists());
+
+    let saved = save_prompt(dir.path(), edited).unwrap();
+    assert_eq!(saved.version, 3);
+
+    let prompt = resolve_prompt(dir.path(), None, "npc_event").unwrap();
+    assert_eq!(prompt.source, PromptSource::Custom);
+    assert_eq!(prompt.template.version, 3);
+    assert_eq!(
+        prompt.template.render(&npc_vars()).unwrap(),
+        "Answer as JSON with who, action."
+    );
+
+    // Resetting is saved as a new version, so later saves don't overwrite history.
+    reset_prompt(dir.path(), "npc_event").unwrap();
+    let prompt = resolve_prompt(dir.path(), None, "npc_event").unwrap();
+    assert_eq!(prompt.source, PromptSource::Builtin);
+    assert_eq!(prompt.template.version, 4);
+    assert!(dir.path().join("history/npc_event.v3.yaml").exists());
+    let saved = save_prompt(dir.path(), prompt.template).unwrap();
+    assert_eq!(saved.version, 5);
+    let v2 = fs::read_to_string(dir.path().join("history/npc_event.v2.yaml")).unwrap();
+    assert!(v2.contains("Answer as JSON"), "{v2}");
+
+    assert!(reset_prompt(dir.path(), "../npc_event").is_err());
+}
+
+#[test]
+fn world_override_wins_and_use_is_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    let world = tempfile::tempdir().unwrap();
+    install_builtin_prompts(dir.path()).unwrap();
+
+    let mut custom = resolve_prompt(dir.path(), None, "npc_event")
+        .unwrap()
+        .template;
+    custom.template = "In the grim north, reply as JSON with {{fields}}.".into();
+    save_prompt(world.path(), custom).unwrap();
+
+    let prompt = resolve_prompt(dir.path(), Some(world.path()), "npc_event").unwrap();
+    assert_eq!(prompt.source, PromptSource::World);
+    assert!(prompt
+        .template
+        .render(&npc_vars())
+        .unwrap()
+        .starts_with("In the grim north"));
+    // Other worlds still get the shared prompt.
+    let other = tempfile::tempdir().unwrap();
+    let prompt_other = resolve_prompt(dir.path(), Some(other.path()), "npc_event").unwrap();
+    assert_eq!(prompt_other.source, PromptSource::Builtin);
+
+    record_prompt_use(dir.path(), &prompt, "npc_event_chat", Some("North")).unwrap();
+    let log = fs::read_to_string(dir.path().join("usage.log")).unwrap();
+    let entry: serde_json::Value = serde_json::from_str(log.trim()).unwrap();
+    assert_eq!(entry["id"], "npc_event");
+    assert_eq!(entry["version"], 2);
+    assert_eq!(entry["source"], "world");
+    assert_eq!(entry["world"], "North");
+
+    assert!(remove_prompt_override(world.path(), "../../prompts/npc_event").is_err());
+    remove_prompt_override(world.path(), "npc_event").unwrap();
+    let prompt = resolve_prompt(dir.path(), Some(world.path()), "npc_event").unwrap();
+    assert_eq!(prompt.source, PromptSource::Builtin);
+}
+
+#[test]
+fn usage_log_is_bounded() {
+    let dir = tempfile::tempdir().unwrap();
+    let prompt = resolve_prompt(dir.path(), None, "npc_event").unwrap();
+    let log = dir.path().join("usage.log");
+    fs::write(&log, "{\"feature\":\"old\"}\n".repeat(100_000)).unwrap();
+    record_prompt_use(dir.path(), &prompt, "npc_event_chat", None).unwrap();
+    let text = fs::read_to_string(&log).unwrap();
+    assert!(text.lines().count() <= 2001);
+    assert!(
+        text.ends_with("\"world\":null}\n"),
+        "{}",
+        &text[text.len() - 80..]
+    );
+}
@@ -0,0 +1,156 @@
+use std::fs;
+
+use blossom_lib::records::{
+    load, load_dir, parse, parse_as, parse_batch, parse_record, validate, Alignment, Component,
+    FieldError, Lore, Npc, RecordKind, Rule, Spell, SpellSchool, SCHEMA_VERSION,
+};
+use serde_json::json;
+
+fn fields(errors: &[FieldError]) -> Vec<&str> {
+    errors.iter().map(|e| e.field.as_str()).collect()
+}
+
+#[test]
+fn saves_are_checked_field_by_field() {
+    let spell = json!({
+        "id": "fireball",
+        "name": "Fireball",
+        "description": "A bright streak flashes to a point you choose.",
+        "level": 3,
+        "school": "evocation",
+        "components": ["V", "S", "M"],
+        "homebrew_note": "kept as is",
+    });
+    let parsed: Spell = parse_as(RecordKind::Spell, spell).unwrap();
+    assert_eq!(parsed.school, Some(SpellSchool::Evocation));
+    assert_eq!(
+        parsed.components,
+        [Component::V, Component::S, Component::M]
+    );
+    assert!(!parsed.concentration && parsed.tags.is_empty());
+    assert_eq!(parsed.schema_version, SCHEMA_VERSION);
+    assert_eq!(parsed.extra["homebrew_note"], "kept as is");
+
+    let bad = json!({
+        "schema_version": 1,
+        "id": "",
+        "description": 7,
+        "level": 12,
+        "school": "pyromancy",
+        "components": ["V", "X"],
+    });
+    let errors = validate(RecordKind::Spell, &bad);
+    assert_eq!(
+        fields(&errors),
+        [
+            "name",
+            "components[1]",
+            "description",
+            "id",
+            "level",
+            "school"
+        ]
+    );
+    assert_eq!(errors[4].message, "must be at most 9");
+    assert!(errors[5].message.contains("\"pyromancy\" is not one of"));
+    let err = parse_record(RecordKind::Spell, bad.clone()).unwrap_err();
+    assert_eq!(err.fields, errors);
+    let err = parse(RecordKind::Spell, bad).unwrap_err();
+    assert!(
+        err.starts_with("invalid spell: name: is required;"),
+        "{err}"
+    );
+
+    // `null` leaves optional and defaulted fields unset.
+    let npc: Npc = parse_as(
+        RecordKind::Npc,
+        json!({"id": "strahd", "name": "Strahd", "alignment": null, "tags": null}),
+    )
+    .unwrap();
+    assert_eq!((npc.alignment, npc.tags.len()), (None, 0));
+    assert!(parse(
+        RecordKind::Npc,
+        json!({"id": "x", "name": "X", "alignment": "evil"})
+    )
+    .is_err());
+}
+
+#[test]
+fn one_bad_record_does_not_sink_an_import() {
+    let batch = parse_batch::<Rule>(
+        RecordKind::Rule,
+        vec![
+            json!({"name": "Grappling", "description": "Contest of Athletics."}),
+            json!({"name": "Broken"}),
+            json!({"name": "Cover", "description": "Half cover gives +2 AC."}),
+        ],
+    );
+    let names: Vec<&str> = batch.records.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, ["Grappling", "Cover"]);
+    assert_eq!(batch.rejected.len(), 1);
+    let rejected = &batch.rejected[0];
+    assert_eq!(
+        (rejected.index, rejected.name.as_deref()),
+        (1, Some("Broken"))
+    );
+    assert_eq!(fields(&rejected.error.fields), ["description"]);
+}
+
+#[test]
+fn unversioned_records_are_upgraded() {
+    let spell: Spell = parse_as(
+        RecordKind::Spell,
+        json!({
+            "name": "Fireball",
+            "description": "Boom.",
+            "level": "3rd",
+            "school": "Evocation",
+            "components": "V, S, M (a tiny ball of bat guano)",
+            "tags": "fire, area",
+        }),
+    )
+    .unwrap();
+    assert_eq!(spell.id, "fireball");
+    assert_eq!(spell.level, 3);
+    assert_eq!(spell.material.as_deref(), Some("a tiny ball of bat guano"));
+    assert_eq!(spell.tags, ["fire", "area"]);
+
+    let npc: Npc = parse_as(
+        RecordKind::Npc,
+        json!({"id": "ireena", "name": "Ireena", "alignment": "LG"}),
+    )
+    .unwrap();
+    assert_eq!(npc.alignment, Some(Alignment::LawfulGood));
+
+    // As written by `pdf_tools.py lore`.
+    let lore: Lore = parse_as(
+        RecordKind::Lore,
+        json!({"id": "a1", "name": "Barovia", "location": null, "hooks": null, "tags": ["lore"]}),
+    )
+    .unwrap();
+    assert!(lore.hooks.is_empty() && lore.location.is_none());
+}
+
+#[test]
+fn loading_migrates_without_touching_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("wall-of-force.json");
+    let old_text =
+        r#"{"name": "Wall of Force", "description": "An invisible wall.", "level": "5th"}"#;
+    fs::write(&old, old_text).unwrap();
+    let broken = dir.path().join("broken.json");
+    let broken_text = r#"{"name": "Broken", "level": "cantrip"}"#;
+    fs::write(&broken, broken_text).unwrap();
+    fs::write(dir.path().join("index.json"), "[]").unwrap();
+
+    let loaded = load(RecordKind::Spell, &old).unwrap();
+    assert_eq!(loaded["schema_version"], SCHEMA_VERSION);
+    assert_eq!(loaded["level"], 5);
+    assert_eq!(fs::read_to_string(&old).unwrap(), old_text);
+
+    // Missing its description: listed all the same.
+    let records = load_dir(RecordKind::Spell, dir.path()).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(fs::read_to_string(&broken).unwrap(), broken_text);
+    assert_eq!(fs::read_to_string(&old).unwrap(), old_text);
+}
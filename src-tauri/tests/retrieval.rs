@@ -0,0 +1,89 @@
+use blossom_lib::retrieval::{
+    cited_passages, format_context, merge_passages, rank_records, sources_for, Passage, SourceKind,
+};
+use serde_json::json;
+
+fn pdf(doc: &str, pages: [u32; 2], text: &str) -> Passage {
+    Passage {
+        id: String::new(),
+        source: SourceKind::Pdf,
+        source_id: doc.into(),
+        title: None,
+        pages: Some(pages),
+        text: text.into(),
+        score: 1.0,
+    }
+}
+
+#[test]
+fn rules_questions_search_pdfs_first() {
+    assert_eq!(sources_for("rules")[0], SourceKind::Pdf);
+    assert!(sources_for("notes").contains(&SourceKind::Vault));
+    assert_eq!(sources_for("custom").len(), 6);
+}
+
+#[test]
+fn merges_dedupes_and_fits_budget() {
+    let pdfs = vec![
+        pdf(
+            "phb",
+            [10, 11],
+            "Grappling uses an Athletics check contested by the target.",
+        ),
+        // Overlaps the pages above.
+        pdf("phb", [11, 12], "Escaping a grapple takes an action."),
+        pdf("dmg", [40, 40], "Improvised damage for falling objects."),
+    ];
+    let vault = vec![Passage {
+        id: String::new(),
+        source: SourceKind::Vault,
+        source_id: "notes/session-3.md".into(),
+        title: None,
+        pages: Some([1, 1]),
+        // Same words as the first PDF passage.
+        text: "Grappling uses an Athletics check contested by the target!".into(),
+        score: 0.5,
+    }];
+    let rules = rank_records(
+        SourceKind::Rule,
+        &[
+            json!({ "id": "grapple", "name": "Grapple", "description": "A grapple check uses Athletics." }),
+            json!({ "id": "stealth", "name": "Hiding", "description": "Hide with a Dexterity check." }),
+        ],
+        "how does a grapple check work",
+        1,
+    );
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].source_id, "grapple");
+    assert_eq!(rules[0].title.as_deref(), Some("Grapple"));
+
+    let merged = merge_passages(vec![pdfs.clone(), rules, vault], 1000);
+    let ids: Vec<(&str, &str)> = merged
+        .iter()
+        .map(|p| (p.id.as_str(), p.source_id.as_str()))
+        .collect();
+    assert_eq!(ids, vec![("S1", "phb"), ("S2", "grapple"), ("S3", "dmg")]);
+
+    // A tight budget keeps only what fits.
+    let merged = merge_passages(vec![pdfs], 25);
+    assert_eq!(merged.len(), 1);
+}
+
+#[test]
+fn formats_and_resolves_citations() {
+    let passages = merge_passages(
+        vec![vec![
+            pdf("phb", [10, 11], "Grappling uses Athletics."),
+            pdf("phb", [40, 40], "Falling deals 1d6 per 10 feet."),
+        ]],
+        1000,
+    );
+    let ctx = format_context(&passages);
+    assert!(ctx.contains("[S1] (pdf: phb p.10-11) Grappling uses Athletics."));
+    assert!(ctx.contains("[S2] (pdf: phb p.40) Falling"));
+
+    let cited = cited_passages("You take 2d6 damage [S2].", &passages);
+    assert_eq!(cited.len(), 1);
+    assert_eq!(cited[0].pages, Some([40, 40]));
+    assert!(format_context(&[]).is_empty());
+}
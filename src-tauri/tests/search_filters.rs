@@ -0,0 +1,163 @@
+use std::{env, fs};
+
+use blossom_lib::embeddings::HASH_MODEL;
+use blossom_lib::search::{search_page, PageRequest, Scope, SearchFilters, SearchMode};
+use blossom_lib::vault_index::{index_vault, parse_front_matter};
+
+#[test]
+fn reads_tags_world_and_date_from_front_matter() {
+    let note =
+        "---\ntags: [NPC, '#villain']\nworld: Barovia\ndate: 2024-05-01T20:00\n---\n# Strahd\n";
+    let (meta, lines) = parse_front_matter(note);
+    assert_eq!(meta.tags, vec!["npc", "villain"]);
+    assert_eq!(meta.world.as_deref(), Some("Barovia"));
+    assert_eq!(meta.date.as_deref(), Some("2024-05-01"));
+    assert_eq!(lines, 5);
+
+    let (meta, _) = parse_front_matter("---\ntags: session, recap\n---\n");
+    assert_eq!(meta.tags, vec!["recap", "session"]);
+    assert_eq!(parse_front_matter("# No front matter").1, 0);
+    assert_eq!(parse_front_matter("---\nnever closed").1, 0);
+}
+
+#[tokio::test]
+async fn filters_facets_and_pages_vault_results() {
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+
+    let vault = tempfile::tempdir().unwrap();
+    let root = vault.path();
+    fs::create_dir_all(root.join("npcs")).unwrap();
+    fs::create_dir_all(root.join("sessions")).unwrap();
+    fs::write(
+        root.join("npcs/strahd.md"),
+        "---\ntags: [npc, villain]\nworld: Barovia\n---\n# Strahd\nThe vampire lord of the castle.",
+    )
+    .unwrap();
+    fs::write(
+        root.join("npcs/ireena.md"),
+        "---\ntags: npc\nworld: Barovia\n---\n# Ireena\nThe burgomaster's daughter fled the castle.",
+    )
+    .unwrap();
+    fs::write(
+        root.join("sessions/one.md"),
+        "---\ndate: 2024-03-02\nworld: Faerun\n---\n# Session one\nWe reached the castle gate.\n\n# Loot\nA castle key.",
+    )
+    .unwrap();
+    let index_dir = root.join("Index");
+    index_vault(
+        root,
+        &index_dir.join("index.sqlite"),
+        "http://127.0.0.1:9",
+        HASH_MODEL,
+        false,
+    )
+    .await
+    .unwrap();
+
+    let search = |filters: SearchFilters, page: PageRequest| {
+        let index_dir = index_dir.clone();
+        async move {
+            search_page(
+                &index_dir,
+                "castle",
+                SearchMode::Keyword,
+                Scope::All,
+                &filters,
+                &page,
+                None,
+            )
+            .await
+            .unwrap()
+        }
+    };
+
+    let all = search(SearchFilters::default(), PageRequest::default()).await;
+    assert_eq!(all.total, 4);
+    assert_eq!(all.facets.folders["npcs"], 2);
+    assert_eq!(all.facets.folders["sessions"], 2);
+    assert_eq!(all.facets.tags["npc"], 2);
+    assert_eq!(all.facets.worlds["Barovia"], 2);
+    // Front matter isn't indexed, and line numbers still point into the file.
+    assert!(all.hits.iter().all(|h| !h.text.contains("world:")));
+    let strahd = all
+        .hits
+        .iter()
+        .find(|h| h.doc_id == "npcs/strahd.md")
+        .unwrap();
+    assert_eq!(strahd.page_range, [6, 6]);
+
+    let villains = search(
+        SearchFilters {
+            tags: vec!["#Villain".into()],
+            ..Default::default()
+        },
+        PageRequest::default(),
+    )
+    .await;
+    assert_eq!(villains.total, 1);
+    assert_eq!(villains.hits[0].doc_id, "npcs/strahd.md");
+
+    let barovia = search(
+        SearchFilters {
+            world: Some("barovia".into()),
+            path_prefix: Some("npcs/".into()),
+            ..Default::default()
+        },
+        PageRequest::default(),
+    )
+    .await;
+    assert_eq!(barovia.facets.docs.len(), 2);
+
+    let dated = search(
+        SearchFilters {
+            date_from: Some("2024-03-01".into()),
+            date_to: Some("2024-03-31".into()),
+            page_from: Some(9),
+            ..Default::default()
+        },
+        PageRequest::default(),
+    )
+    .await;
+    assert_eq!(dated.total, 1);
+    assert!(dated.hits[0].text.starts_with("Loot"));
+
+    // Pages follow each other through the cursor and cover every hit once.
+    let first = search(
+        SearchFilters::default(),
+        PageRequest {
+            limit: Some(3),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert_eq!(first.hits.len(), 3);
+    let second = search(
+        SearchFilters::default(),
+        PageRequest {
+            limit: Some(3),
+            cursor: first.next_cursor.clone(),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert_eq!((second.offset, second.hits.len()), (3, 1));
+    assert!(second.next_cursor.is_none());
+    assert_eq!(second.hits[0].chunk_id, all.hits[3].chunk_id);
+
+    let err = search_page(
+        &index_dir,
+        "gate",
+        SearchMode::Keyword,
+        Scope::All,
+        &SearchFilters::default(),
+        &PageRequest {
+            cursor: first.next_cursor,
+            ..Default::default()
+        },
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert!(err.contains("different"));
+}
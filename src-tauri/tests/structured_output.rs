@@ -0,0 +1,85 @@
+use blossom_lib::{
+    commands::{parse_npc_event, ChatMessage, NpcEvent},
+    llm::{ChatRequest, OllamaBackend},
+    structured::{chat_structured, schema_of, strip_code_fences},
+};
+use httpmock::prelude::*;
+use serde_json::json;
+
+fn messages() -> Vec<ChatMessage> {
+    vec![ChatMessage {
+        role: "user".into(),
+        content: "The innkeeper reacts to the bar fight.".into(),
+    }]
+}
+
+const EVENT: &str = r#"{"who":"Innkeeper","action":"shout","targets":["brawlers"],"effects":[],"narration":"Out, all of you!"}"#;
+
+#[test]
+fn strips_code_fences() {
+    assert_eq!(strip_code_fences("```json\n{\"a\":1}\n```"), "{\"a\":1}");
+    assert_eq!(strip_code_fences("  {\"a\":1} "), "{\"a\":1}");
+    let event = parse_npc_event(&format!("```\n{EVENT}\n```")).unwrap();
+    assert_eq!(event.who, "Innkeeper");
+}
+
+#[test]
+fn schema_lists_required_fields() {
+    let schema = schema_of::<NpcEvent>();
+    assert_eq!(schema["type"], "object");
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect();
+    for field in ["who", "action", "targets", "effects", "narration"] {
+        assert!(required.contains(&field), "{field} not required");
+    }
+}
+
+#[tokio::test]
+async fn repairs_invalid_reply() {
+    let server = MockServer::start();
+    // The repair prompt carries the validation error back to the model.
+    let repaired = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .body_contains("previous reply was not valid");
+        then.status(200)
+            .json_body(json!({ "message": { "role": "assistant", "content": EVENT } }));
+    });
+    let first = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .json_body_partial(r#"{ "format": { "type": "object" } }"#);
+        then.status(200).json_body(json!({
+            "message": { "role": "assistant", "content": "```json\n{\"who\":\"Innkeeper\"}\n```" }
+        }));
+    });
+
+    let backend = OllamaBackend::new(server.base_url());
+    let event: NpcEvent = chat_structured(&backend, ChatRequest::new("m", messages()), 2)
+        .await
+        .unwrap();
+    assert_eq!(event.action, "shout");
+    first.assert_hits(1);
+    repaired.assert_hits(1);
+}
+
+#[tokio::test]
+async fn gives_up_after_bounded_repairs() {
+    let server = MockServer::start();
+    let chat = server.mock(|when, then| {
+        when.method(POST).path("/api/chat");
+        then.status(200)
+            .json_body(json!({ "message": { "role": "assistant", "content": "not json" } }));
+    });
+
+    let backend = OllamaBackend::new(server.base_url());
+    let err = chat_structured::<NpcEvent>(&backend, ChatRequest::new("m", messages()), 2)
+        .await
+        .unwrap_err();
+    assert!(err.contains("after 3 attempts"), "{err}");
+    chat.assert_hits(3);
+}
@@ -0,0 +1,199 @@
+use async_trait::async_trait;
+use blossom_lib::{
+    commands::ChatMessage,
+    llm::{ChatRequest, OllamaBackend, OpenAiBackend},
+    tools::{builtin_tools, roll_dice, run_tool_loop, ToolRunner},
+};
+use httpmock::prelude::*;
+use serde_json::{json, Value};
+
+/// Answers `roll_dice` for real and fails everything else.
+struct DiceOnly;
+
+#[async_trait]
+impl ToolRunner for DiceOnly {
+    async fn run(&self, name: &str, args: &Value) -> Result<Value, String> {
+        match name {
+            "roll_dice" => Ok(json!(roll_dice(args["expression"].as_str().unwrap())?)),
+            other => Err(format!("unknown tool: {other}")),
+        }
+    }
+}
+
+/// Returns a whole book for every call.
+struct Verbose;
+
+#[async_trait]
+impl ToolRunner for Verbose {
+    async fn run(&self, _name: &str, _args: &Value) -> Result<Value, String> {
+        Ok(json!({ "text": "The tale of the tower. ".repeat(4000) }))
+    }
+}
+
+fn request() -> ChatRequest {
+    ChatRequest::new(
+        "m",
+        vec![ChatMessage {
+            role: "user".into(),
+            content: "Roll initiative for the goblins.".into(),
+        }],
+    )
+}
+
+#[test]
+fn rolls_dice_expressions() {
+    let roll = roll_dice("2d6 + 3").unwrap();
+    assert_eq!(roll.rolls.len(), 2);
+    assert!(roll.rolls.iter().all(|r| (1..=6).contains(r)));
+    assert_eq!(roll.modifier, 3);
+    assert_eq!(
+        roll.total,
+        roll.rolls.iter().map(|r| *r as i64).sum::<i64>() + 3
+    );
+
+    let roll = roll_dice("d20-1").unwrap();
+    assert_eq!(roll.rolls.len(), 1);
+    assert_eq!(roll.total, roll.rolls[0] as i64 - 1);
+
+    assert!(roll_dice("2x6").is_err());
+    assert!(roll_dice("0d6").is_err());
+    assert!(roll_dice("1000d6").is_err());
+    assert!(roll_dice("50d6+4294967295d6").is_err());
+    assert!(roll_dice("9223372036854775807+1d6").is_err());
+}
+
+#[tokio::test]
+async fn ollama_runs_tool_calls_until_answer() {
+    let server = MockServer::start();
+    let answer = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .body_contains(r#""role":"tool""#)
+            .body_contains(r#""tool_name":"roll_dice""#);
+        then.status(200).json_body(json!({
+            "message": { "role": "assistant", "content": "The goblins act first." }
+        }));
+    });
+    let call = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .body_contains(r#""name":"list_npcs""#);
+        then.status(200).json_body(json!({
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [
+                    { "function": { "name": "roll_dice", "arguments": { "expression": "1d20+2" } } },
+                    { "function": { "name": "teleport", "arguments": {} } }
+                ]
+            }
+        }));
+    });
+
+    let backend = OllamaBackend::new(server.base_url());
+    let reply = run_tool_loop(&backend, &request(), &builtin_tools(), &DiceOnly, 5)
+        .await
+        .unwrap();
+
+    assert_eq!(reply.content, "The goblins act first.");
+    assert_eq!(reply.tool_calls.len(), 2);
+    assert_eq!(reply.tool_calls[0].name, "roll_dice");
+    assert_eq!(reply.tool_calls[0].arguments["expression"], "1d20+2");
+    let total = reply.tool_calls[0].result.as_ref().unwrap()["total"]
+        .as_i64()
+        .unwrap();
+    assert!((3..=22).contains(&total));
+    assert_eq!(
+        reply.tool_calls[1].error.as_deref(),
+        Some("unknown tool: teleport")
+    );
+    call.assert_hits(1);
+    answer.assert_hits(1);
+}
+
+#[tokio::test]
+async fn openai_tool_calls_use_ids_and_string_arguments() {
+    let server = MockServer::start();
+    let answer = server.mock(|when, then| {
+        when.method(POST)
+            .path("/v1/chat/completions")
+            .body_contains(r#""tool_call_id":"call_7""#);
+        then.status(200).json_body(json!({
+            "choices": [{ "message": { "role": "assistant", "content": "Rolled." } }]
+        }));
+    });
+    let call = server.mock(|when, then| {
+        when.method(POST).path("/v1/chat/completions");
+        then.status(200).json_body(json!({
+            "choices": [{ "message": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_7",
+                    "type": "function",
+                    "function": { "name": "roll_dice", "arguments": "{\"expression\":\"d6\"}" }
+                }]
+            } }]
+        }));
+    });
+
+    let backend = OpenAiBackend::new(server.base_url(), None);
+    let reply = run_tool_loop(&backend, &request(), &builtin_tools(), &DiceOnly, 5)
+        .await
+        .unwrap();
+    assert_eq!(reply.content, "Rolled.");
+    assert_eq!(reply.tool_calls[0].arguments, json!({ "expression": "d6" }));
+    assert!(reply.tool_calls[0].error.is_none());
+    call.assert_hits(1);
+    answer.assert_hits(1);
+}
+
+/// Well under the 4096-token default budget, about four characters a token.
+fn fits_default_budget(req: &HttpMockRequest) -> bool {
+    req.body.as_ref().is_some_and(|b| b.len() < 4096 * 4)
+}
+
+#[tokio::test]
+async fn tool_results_are_cut_to_the_context() {
+    let home = tempfile::tempdir().unwrap();
+    std::env::set_var("HOME", home.path());
+    let server = MockServer::start();
+    let answer = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .body_contains(r#""role":"tool""#)
+            .matches(fits_default_budget);
+        then.status(200).json_body(json!({
+            "message": { "role": "assistant", "content": "The tower fell." }
+        }));
+    });
+    let call = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/chat")
+            .body_contains(r#""name":"list_npcs""#);
+        then.status(200).json_body(json!({
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [
+                    { "function": { "name": "list_lore", "arguments": {} } },
+                    { "function": { "name": "list_npcs", "arguments": {} } },
+                    { "function": { "name": "list_spells", "arguments": {} } }
+                ]
+            }
+        }));
+    });
+
+    let backend = OllamaBackend::new(server.base_url());
+    let reply = run_tool_loop(&backend, &request(), &builtin_tools(), &Verbose, 5)
+        .await
+        .unwrap();
+    assert_eq!(reply.content, "The tower fell.");
+    // The reply keeps every result whole.
+    let text = reply.tool_calls[2].result.as_ref().unwrap()["text"]
+        .as_str()
+        .unwrap();
+    assert_eq!(text.len(), 92_000);
+    call.assert_hits(1);
+    answer.assert_hits(1);
+}
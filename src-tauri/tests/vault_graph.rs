@@ -0,0 +1,135 @@
+use std::{env, fs, thread, time::Duration};
+
+use blossom_lib::embeddings::HASH_MODEL;
+use blossom_lib::vault_graph::{
+    backlinks, broken_links, neighborhood, orphans, outgoing_links, parse_inline_tags, parse_links,
+    resolve_link, WikiLink,
+};
+use blossom_lib::vault_index::index_vault;
+
+#[test]
+fn parses_links_and_tags_outside_code() {
+    let text = "See [[Strahd von Zarovich|the Count]] and ![[maps/Barovia.png]].\n\
+                ```\n[[Not a link]] #nottag\n```\n\
+                Visit [[Castle Ravenloft#Crypts]] `[[inline]]` #quest/open #2 [[#Local]]";
+    let links = parse_links(text);
+    assert_eq!(
+        links[0],
+        WikiLink {
+            target: "Strahd von Zarovich".into(),
+            heading: None,
+            alias: Some("the Count".into()),
+            embed: false,
+            line: 1,
+        }
+    );
+    assert!(links[1].embed);
+    assert_eq!(
+        (
+            links[2].target.as_str(),
+            links[2].heading.as_deref(),
+            links[2].line
+        ),
+        ("Castle Ravenloft", Some("Crypts"), 5)
+    );
+    assert_eq!(links.len(), 3);
+    assert_eq!(parse_inline_tags(text), ["quest/open"]);
+    assert!(parse_inline_tags("# Heading\nissue#4 #NPC").contains(&"npc".to_string()));
+}
+
+#[test]
+fn resolves_like_obsidian() {
+    let notes: Vec<String> = ["npcs/strahd.md", "strahd.md", "lore/barovia/strahd.md"]
+        .map(String::from)
+        .to_vec();
+    assert_eq!(
+        resolve_link("Strahd", "index.md", &notes),
+        Some("strahd.md")
+    );
+    assert_eq!(
+        resolve_link("strahd", "npcs/ireena.md", &notes),
+        Some("npcs/strahd.md")
+    );
+    assert_eq!(
+        resolve_link("barovia/Strahd.md", "index.md", &notes),
+        Some("lore/barovia/strahd.md")
+    );
+    assert_eq!(resolve_link("Ireena", "index.md", &notes), None);
+
+    // Names ending in multibyte characters.
+    let notes: Vec<String> = ["Pokémon.md", "bestiary/Pokémon.txt", "Café"]
+        .map(String::from)
+        .to_vec();
+    assert_eq!(
+        resolve_link("Pokémon", "index.md", &notes),
+        Some("Pokémon.md")
+    );
+    assert_eq!(resolve_link("Café", "index.md", &notes), Some("Café"));
+    assert_eq!(resolve_link("Pokém", "index.md", &notes), None);
+}
+
+#[tokio::test]
+async fn builds_the_link_graph_from_the_vault() {
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    let vault = tempfile::tempdir().unwrap();
+    let root = vault.path();
+    fs::create_dir_all(root.join("npcs")).unwrap();
+    fs::write(
+        root.join("session-1.md"),
+        "---\ntags: [session]\n---\nMet [[Strahd]] at [[Castle Ravenloft]]. #barovia",
+    )
+    .unwrap();
+    fs::write(
+        root.join("npcs/strahd.md"),
+        "Lord of [[Castle Ravenloft]], hunts [[Ireena]].",
+    )
+    .unwrap();
+    fs::write(root.join("Castle Ravenloft.md"), "The castle.").unwrap();
+    fs::write(root.join("loose.md"), "Unlinked thoughts.").unwrap();
+    let index_dir = root.join("Index");
+    let db = index_dir.join("index.sqlite");
+    let index = || index_vault(root, &db, "", HASH_MODEL, false);
+    index().await.unwrap();
+
+    let back = backlinks(&index_dir, "Castle Ravenloft.md").await.unwrap();
+    let sources: Vec<&str> = back.iter().map(|l| l.source.as_str()).collect();
+    assert_eq!(sources, ["npcs/strahd.md", "session-1.md"]);
+    // Front matter lines count toward the link's line.
+    assert_eq!(back[1].link.line, 4);
+
+    let out = outgoing_links(&index_dir, "npcs/strahd.md").await.unwrap();
+    assert_eq!(out[0].path.as_deref(), Some("Castle Ravenloft.md"));
+    assert_eq!(out[1].path, None);
+
+    let broken = broken_links(&index_dir).await.unwrap();
+    assert_eq!(broken.len(), 1);
+    assert_eq!(broken[0].link.target, "Ireena");
+    assert_eq!(orphans(&index_dir).await.unwrap(), ["loose.md"]);
+
+    let near = neighborhood(&index_dir, "session-1.md", 1).await.unwrap();
+    let ids: Vec<&str> = near.nodes.iter().map(|n| n.id.as_str()).collect();
+    assert_eq!(
+        ids,
+        ["Castle Ravenloft.md", "npcs/strahd.md", "session-1.md"]
+    );
+    let session = &near.nodes[2];
+    assert_eq!(session.title, "session-1");
+    assert_eq!(session.tags, ["barovia", "session"]);
+    assert_eq!(near.edges.len(), 3);
+
+    let far = neighborhood(&index_dir, "session-1.md", 2).await.unwrap();
+    let ireena = far.nodes.iter().find(|n| n.id == "ireena").unwrap();
+    assert!(!ireena.exists);
+    assert!(neighborhood(&index_dir, "missing.md", 1).await.is_err());
+
+    // Creating the missing note fixes the link; editing a note updates it.
+    thread::sleep(Duration::from_millis(20));
+    fs::write(root.join("npcs/Ireena.md"), "Hunted.").unwrap();
+    fs::write(root.join("loose.md"), "Now about [[Ireena]].").unwrap();
+    index().await.unwrap();
+    assert!(broken_links(&index_dir).await.unwrap().is_empty());
+    assert!(orphans(&index_dir).await.unwrap().is_empty());
+    let back = backlinks(&index_dir, "npcs/Ireena.md").await.unwrap();
+    assert_eq!(back.len(), 2);
+}
@@ -0,0 +1,105 @@
+use std::{fs, thread, time::Duration};
+
+use blossom_lib::embeddings::HASH_MODEL;
+use blossom_lib::vault_index::{chunk_note, index_vault};
+use sqlx::{Connection, Row, SqliteConnection};
+
+#[test]
+fn chunks_follow_headings() {
+    let note = "Intro line.\n\n# Strahd\nThe vampire lord.\n## Lair\nCastle Ravenloft.\n```\n# not a heading\n```\n# Barovia\nA gloomy valley.\n";
+    let chunks = chunk_note(note);
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks[0].text, "Intro line.");
+    assert!(chunks[0].headings.is_empty());
+    assert_eq!(chunks[1].headings, vec!["Strahd"]);
+    assert_eq!(chunks[2].headings, vec!["Strahd", "Lair"]);
+    assert!(chunks[2]
+        .text
+        .starts_with("Strahd > Lair\nCastle Ravenloft."));
+    assert!(chunks[2].text.contains("# not a heading"));
+    assert_eq!((chunks[2].line_start, chunks[2].line_end), (6, 9));
+    assert_eq!(chunks[3].headings, vec!["Barovia"]);
+
+    // Long sections are split into several chunks under the same heading.
+    let long = format!(
+        "# Rules\n{}",
+        "Words about grappling. ".repeat(40).repeat(4)
+    );
+    let long = long.replace(". W", ".\n\nW");
+    let chunks = chunk_note(&long);
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|c| c.headings == vec!["Rules"]));
+}
+
+async fn chunk_docs(db: &std::path::Path) -> Vec<String> {
+    let mut conn = SqliteConnection::connect(&format!("sqlite:{}", db.display()))
+        .await
+        .unwrap();
+    sqlx::query("SELECT DISTINCT doc_id FROM embeddings ORDER BY doc_id")
+        .fetch_all(&mut conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.get("doc_id"))
+        .collect()
+}
+
+#[tokio::test]
+async fn reindexes_only_changed_notes() {
+    let vault = tempfile::tempdir().unwrap();
+    let root = vault.path();
+    fs::create_dir_all(root.join("npcs")).unwrap();
+    fs::create_dir_all(root.join(".obsidian")).unwrap();
+    fs::write(root.join("npcs/strahd.md"), "# Strahd\nThe vampire lord.").unwrap();
+    fs::write(root.join("session-1.txt"), "We met Ismark.").unwrap();
+    fs::write(root.join(".obsidian/workspace.md"), "ignored").unwrap();
+    fs::write(root.join("map.png"), "ignored").unwrap();
+    let db = root.join("Index").join("index.sqlite");
+    let url = "http://127.0.0.1:9";
+
+    let first = index_vault(root, &db, url, HASH_MODEL, false)
+        .await
+        .unwrap();
+    assert_eq!(first.scanned, 2);
+    assert_eq!(first.indexed, 2);
+    assert_eq!(first.chunks, 2);
+    assert_eq!(
+        chunk_docs(&db).await,
+        vec!["npcs/strahd.md", "session-1.txt"]
+    );
+
+    let again = index_vault(root, &db, url, HASH_MODEL, false)
+        .await
+        .unwrap();
+    assert_eq!((again.indexed, again.unchanged), (0, 2));
+
+    // Same content with a new mtime is only checked by hash.
+    thread::sleep(Duration::from_millis(20));
+    fs::write(root.join("session-1.txt"), "We met Ismark.").unwrap();
+    fs::write(root.join("npcs/strahd.md"), "# Strahd\nLord of Barovia.").unwrap();
+    let edited = index_vault(root, &db, url, HASH_MODEL, false)
+        .await
+        .unwrap();
+    assert_eq!((edited.indexed, edited.unchanged), (1, 1));
+
+    fs::remove_file(root.join("session-1.txt")).unwrap();
+    let removed = index_vault(root, &db, url, HASH_MODEL, false)
+        .await
+        .unwrap();
+    assert_eq!(removed.removed, 1);
+    assert_eq!(chunk_docs(&db).await, vec!["npcs/strahd.md"]);
+
+    let forced = index_vault(root, &db, url, HASH_MODEL, true).await.unwrap();
+    assert_eq!(forced.indexed, 1);
+
+    // An unreachable embedding model keeps the hashed vectors.
+    let offline = index_vault(root, &db, url, "nomic-embed-text", true)
+        .await
+        .unwrap();
+    assert_eq!((offline.indexed, offline.embedded), (1, 0));
+
+    // A vault that isn't there leaves the index alone.
+    let missing = index_vault(&root.join("unmounted"), &db, url, HASH_MODEL, false).await;
+    assert!(missing.unwrap_err().contains("Vault not found"));
+    assert_eq!(chunk_docs(&db).await, vec!["npcs/strahd.md"]);
+}
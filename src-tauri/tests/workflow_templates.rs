@@ -0,0 +1,101 @@
+use blossom_lib::workflow_templates::{install_builtin_templates, load_templates};
+use serde_json::{json, Map, Value};
+
+fn params(v: Value) -> Map<String, Value> {
+    v.as_object().unwrap().clone()
+}
+
+#[test]
+fn renders_builtin_portrait() {
+    let dir = tempfile::tempdir().unwrap();
+    install_builtin_templates(dir.path()).unwrap();
+    let templates = load_templates(dir.path()).unwrap();
+    let ids: Vec<_> = templates.iter().map(|t| t.id.as_str()).collect();
+    assert_eq!(ids, vec!["landscape", "portrait", "token"]);
+
+    let portrait = templates.iter().find(|t| t.id == "portrait").unwrap();
+    let out = portrait
+        .render(&params(json!({
+            "prompt": "an elven ranger",
+            "seed": 42,
+            "size": "768x1024"
+        })))
+        .unwrap();
+
+    let wf = &out.workflow;
+    assert_eq!(wf["3"]["inputs"]["seed"], 42);
+    assert_eq!(wf["3"]["inputs"]["steps"], 30);
+    assert_eq!(wf["5"]["inputs"]["width"], 768);
+    assert_eq!(wf["5"]["inputs"]["height"], 1024);
+    assert_eq!(
+        wf["6"]["inputs"]["text"],
+        "portrait of an elven ranger, detailed face, fantasy character art"
+    );
+    assert_eq!(wf["4"]["inputs"]["ckpt_name"], "sd_xl_base_1.0.safetensors");
+    assert_eq!(out.params["size"], "768x1024");
+    assert!(!out.params.contains_key("size.width"));
+}
+
+#[test]
+fn generates_seed_when_missing() {
+    let dir = tempfile::tempdir().unwrap();
+    install_builtin_templates(dir.path()).unwrap();
+    let token = load_templates(dir.path())
+        .unwrap()
+        .into_iter()
+        .find(|t| t.id == "token")
+        .unwrap();
+    let out = token
+        .render(&params(json!({ "prompt": "dwarf", "seed": -1 })))
+        .unwrap();
+    assert!(out.params["seed"].is_u64());
+    assert_eq!(out.workflow["3"]["inputs"]["seed"], out.params["seed"]);
+}
+
+#[test]
+fn reports_all_validation_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    install_builtin_templates(dir.path()).unwrap();
+    let landscape = load_templates(dir.path())
+        .unwrap()
+        .into_iter()
+        .find(|t| t.id == "landscape")
+        .unwrap();
+    let err = landscape
+        .render(&params(json!({
+            "size": "1000x7",
+            "steps": "many",
+            "cfg": 99,
+            "style": "noir"
+        })))
+        .unwrap_err();
+    for expected in [
+        "style: unknown parameter",
+        "prompt: required",
+        "size: width and height must be positive multiples of 8",
+        "steps: expected an integer",
+        "cfg: must be at most 30",
+    ] {
+        assert!(err.contains(expected), "missing {expected:?} in {err}");
+    }
+}
+
+#[test]
+fn inserts_values_verbatim() {
+    let dir = tempfile::tempdir().unwrap();
+    install_builtin_templates(dir.path()).unwrap();
+    let portrait = load_templates(dir.path())
+        .unwrap()
+        .into_iter()
+        .find(|t| t.id == "portrait")
+        .unwrap();
+    let out = portrait
+        .render(&params(
+            json!({ "prompt": "a {{seed}} sign and {{braces}}", "seed": 7 }),
+        ))
+        .unwrap();
+    assert_eq!(
+        out.workflow["6"]["inputs"]["text"],
+        "portrait of a {{seed}} sign and {{braces}}, detailed face, fantasy character art"
+    );
+}
@@ -0,0 +1,179 @@
+use std::{env, fs, path::Path, thread, time::Duration};
+
+use blossom_lib::embeddings::HASH_MODEL;
+use blossom_lib::retrieval::SourceKind;
+use blossom_lib::search::{snippet, Snippet};
+use blossom_lib::vault_index::index_vault;
+use blossom_lib::world_search::{search_world_index, RecordDirs};
+use serde_json::json;
+
+fn save(dir: &Path, id: &str, record: serde_json::Value) {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join(format!("{id}.json")), record.to_string()).unwrap();
+}
+
+#[test]
+fn snippets_start_near_the_best_match() {
+    let filler = "filler ".repeat(60);
+    let text = format!("{filler} Strahd von Zarovich rules Barovia. {filler}");
+    let s = snippet(&text, "strahd", 60);
+    assert!(s.text.starts_with('…') && s.text.ends_with('…'));
+    assert!(s.text.contains("Strahd von Zarovich"));
+    assert_eq!(
+        snippet("Short   text\nhere", "missing", 60),
+        Snippet {
+            text: "Short text here".into(),
+            highlights: vec![],
+        }
+    );
+}
+
+#[test]
+fn highlights_give_byte_and_char_offsets() {
+    // The window with both terms wins over the earlier lone match.
+    let text = format!(
+        "Walls everywhere. {} The wall of force shimmered.",
+        "é ".repeat(80)
+    );
+    let s = snippet(&text, "wall force", 60);
+    let words: Vec<&str> = s
+        .highlights
+        .iter()
+        .map(|h| &s.text[h.start..h.end])
+        .collect();
+    assert_eq!(words, vec!["wall", "force"]);
+    let chars: Vec<char> = s.text.chars().collect();
+    for h in &s.highlights {
+        let by_char: String = chars[h.char_start..h.char_end].iter().collect();
+        assert_eq!(by_char, &s.text[h.start..h.end]);
+    }
+    // Inflections match, as they do for vector hits.
+    let s = snippet("Two walls of stone.", "wall", 60);
+    assert_eq!(s.highlights[0].char_start..s.highlights[0].char_end, 4..9);
+}
+
+#[tokio::test]
+async fn finds_records_notes_and_pdfs_in_one_search() {
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    env::set_var("BLOSSOM_OLLAMA_URL", "http://127.0.0.1:9");
+
+    let data = tempfile::tempdir().unwrap();
+    let root = data.path();
+    let (npcs, lore, spells) = (
+        root.join("worlds/barovia/npcs"),
+        root.join("worlds/barovia/lore"),
+        root.join("spells"),
+    );
+    let dirs = RecordDirs {
+        npcs: Some(npcs.clone()),
+        lore: Some(lore.clone()),
+        spells: Some(spells.clone()),
+        rules: Some(root.join("rules")),
+    };
+    save(
+        &npcs,
+        "strahd",
+        json!({"id": "strahd", "name": "Strahd", "description": "Vampire lord of Castle Ravenloft."}),
+    );
+    save(
+        &npcs,
+        "ireena",
+        json!({"id": "ireena", "name": "Ireena", "description": "Hunted by Strahd."}),
+    );
+    save(
+        &lore,
+        "ravenloft",
+        json!({"id": "ravenloft", "name": "Castle Ravenloft", "summary": "Seat of Strahd's power."}),
+    );
+    save(
+        &spells,
+        "sunbeam",
+        json!({"id": "sunbeam", "name": "Sunbeam", "description": "Radiant light that vampires fear."}),
+    );
+    fs::write(lore.join("index.json"), "[]").unwrap();
+
+    let vault = root.join("vault");
+    fs::create_dir_all(&vault).unwrap();
+    fs::write(
+        vault.join("session-3.md"),
+        "# Session 3\nStrahd visited the party at dinner.",
+    )
+    .unwrap();
+    let index_dir = vault.join("Index");
+    index_vault(
+        &vault,
+        &index_dir.join("index.sqlite"),
+        "http://127.0.0.1:9",
+        HASH_MODEL,
+        false,
+    )
+    .await
+    .unwrap();
+
+    use SourceKind::*;
+    let all = [Npc, Lore, Spell, Rule, Vault, Pdf];
+    let hits = search_world_index(&index_dir, &dirs, "barovia", "Strahd", &all, 10)
+        .await
+        .unwrap();
+    assert_eq!((hits[0].kind, hits[0].id.as_str()), (Npc, "strahd"));
+    assert!(hits
+        .iter()
+        .any(|h| h.kind == Lore && h.title == "Castle Ravenloft"));
+    let note = hits.iter().find(|h| h.kind == Vault).unwrap();
+    assert_eq!(
+        (note.id.as_str(), note.title.as_str()),
+        ("session-3.md", "session-3")
+    );
+    assert!(note.snippet.contains("Strahd visited"));
+    assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+    assert!(hits.iter().all(|h| h.kind != Spell));
+
+    // Kinds are ranked together: the castle's own entry beats an NPC that
+    // only mentions it, whichever kind is asked for first.
+    let hits = search_world_index(&index_dir, &dirs, "barovia", "Ravenloft", &[Npc, Lore], 10)
+        .await
+        .unwrap();
+    assert_eq!((hits[0].kind, hits[1].kind), (Lore, Npc));
+
+    // Kinds without a folder are not searched.
+    let npcs_only = RecordDirs {
+        npcs: Some(npcs.clone()),
+        ..RecordDirs::default()
+    };
+    let hits = search_world_index(
+        &index_dir,
+        &npcs_only,
+        "barovia",
+        "Ravenloft",
+        &[Npc, Lore],
+        10,
+    )
+    .await
+    .unwrap();
+    assert!(hits.iter().all(|h| h.kind == Npc));
+
+    // Only the kinds asked for, and spells are shared by every world.
+    let hits = search_world_index(&index_dir, &dirs, "faerun", "vampires", &[Spell, Npc], 10)
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].title, "Sunbeam");
+
+    // Edited and deleted records are picked up on the next search.
+    thread::sleep(Duration::from_millis(20));
+    save(
+        &npcs,
+        "ireena",
+        json!({"id": "ireena", "name": "Ireena Kolyana", "description": "The burgomaster's daughter."}),
+    );
+    fs::remove_file(npcs.join("strahd.json")).unwrap();
+    let hits = search_world_index(&index_dir, &dirs, "barovia", "Strahd", &[Npc], 10)
+        .await
+        .unwrap();
+    assert!(hits.is_empty());
+    let hits = search_world_index(&index_dir, &dirs, "barovia", "Kolyana", &[Npc], 10)
+        .await
+        .unwrap();
+    assert_eq!(hits[0].id, "ireena");
+}
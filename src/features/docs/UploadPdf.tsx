@@ -1,20 +1,51 @@
+import { useState } from "react";
 import { open } from "@tauri-apps/plugin-dialog";
-import { useDocs } from "./useDocs";
+import { AddResult, DuplicatePolicy, useDocs } from "./useDocs";
 import TaskList from "../../components/TaskQueue/TaskList";
 
+function describe(result: AddResult): string {
+  const title = result.title ?? result.doc_id;
+  switch (result.action) {
+    case "skipped":
+      return `${title} is already in the library.`;
+    case "replaced":
+      return `Replaced the earlier copy of ${title}.`;
+    case "kept_both":
+      return `Added ${title} next to its existing copy.`;
+    case "new_edition":
+      return `Added ${title} as a new edition.`;
+    default:
+      return `Added ${title}.`;
+  }
+}
+
 export function UploadPdf() {
   const { addDoc } = useDocs();
+  const [onDuplicate, setOnDuplicate] = useState<DuplicatePolicy>("skip");
+  const [status, setStatus] = useState<string | null>(null);
 
   async function handleClick() {
     const selected = await open({ filters: [{ name: "PDF", extensions: ["pdf"] }] });
     if (typeof selected === "string") {
-      await addDoc(selected, true);
+      setStatus(describe(await addDoc(selected, true, onDuplicate)));
     }
   }
 
   return (
     <div>
       <button onClick={handleClick}>Upload PDF</button>
+      <label>
+        If already imported:{" "}
+        <select
+          value={onDuplicate}
+          onChange={(e) => setOnDuplicate(e.target.value as DuplicatePolicy)}
+        >
+          <option value="skip">Skip</option>
+          <option value="replace">Replace</option>
+          <option value="keep_both">Keep both</option>
+        </select>
+      </label>
+      {status && <p>{status}</p>}
       <TaskList />
     </div>
   );
@@ -8,10 +8,27 @@ export interface DocMeta {
   created?: string;
 }
 
+export type DuplicatePolicy = "skip" | "replace" | "keep_both";
+
+export interface AddResult extends DocMeta {
+  action: "added" | "new_edition" | "skipped" | "replaced" | "kept_both";
+  existing: string[];
+  edition_of?: string | null;
+}
+
+/** Offsets of a matched term in a snippet, in bytes and in characters. */
+export interface Highlight {
+  start: number;
+  end: number;
+  char_start: number;
+  char_end: number;
+}
+
 export interface SearchResult {
   doc_id: string;
   page: number;
   snippet: string;
+  highlights: Highlight[];
   score: number;
 }
 
@@ -35,9 +52,15 @@ export function useDocs() {
     await invoke("pdf_ingest", { docId });
   }
 
-  async function addDoc(path: string, ingest = false) {
-    const meta = await invoke<DocMeta>("pdf_add", { path });
-    if (ingest) {
+  async function addDoc(
+    path: string,
+    ingest = false,
+    onDuplicate: DuplicatePolicy = "skip",
+  ) {
+    const meta = await invoke<AddResult>("pdf_add", { path, onDuplicate });
+    // A skipped duplicate was already imported and tagged, and a kept copy
+    // would only be tagged twice.
+    if (ingest && meta.action !== "skipped" && meta.action !== "kept_both") {
       await ingestDoc(meta.doc_id);
     }
     refresh();
@@ -52,12 +75,20 @@ export function useDocs() {
   async function searchDocs(query: string): Promise<SearchResult[]> {
     try {
       const hits = await invoke<
-        { doc_id: string; page_range: [number, number]; text: string; score: number }[]
+        {
+          doc_id: string;
+          page_range: [number, number];
+          text: string;
+          score: number;
+          snippet: string;
+          highlights: Highlight[];
+        }[]
       >("pdf_search", { query });
       return hits.map((hit) => ({
         doc_id: hit.doc_id,
         page: hit.page_range[0],
-        snippet: hit.text,
+        snippet: hit.snippet,
+        highlights: hit.highlights,
         score: hit.score,
       }));
     } catch (err) {
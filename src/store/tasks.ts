@@ -208,18 +208,49 @@ export const useTasks = create<TasksState>((set, get) => ({
 
 export type { TasksState };
 
+/** A field that doesn't match its record's schema, e.g. `level` or `components[1]`. */
+export interface FieldError {
+  field: string;
+  message: string;
+}
+
+/** Why a record save was rejected; `fields` is empty for errors such as `exists`. */
+export interface RecordError {
+  message: string;
+  fields: FieldError[];
+}
+
+function toRecordError(error: unknown): RecordError {
+  if (error && typeof error === 'object' && 'message' in error) {
+    const { message, fields } = error as Partial<RecordError>;
+    return { message: String(message), fields: Array.isArray(fields) ? fields : [] };
+  }
+  return { message: String(error), fields: [] };
+}
+
+/** Messages of a rejected save keyed by field, for forms to show next to each input. */
+export function recordFieldErrors(error: RecordError): Record<string, string> {
+  return Object.fromEntries(error.fields.map((f) => [f.field, f.message]));
+}
+
 export async function listSpells() {
   return invoke<any[]>('list_spells');
 }
 
+/** Rejects with a {@link RecordError}. */
 export async function saveSpell(spell: unknown, overwrite?: boolean) {
-  return invoke<void>('save_spell', { spell, overwrite });
+  return invoke<void>('save_spell', { spell, overwrite }).catch((e) => {
+    throw toRecordError(e);
+  });
 }
 
 export async function listRules() {
   return invoke<any[]>('list_rules');
 }
 
+/** Rejects with a {@link RecordError}. */
 export async function saveRule(rule: unknown, overwrite?: boolean) {
-  return invoke<void>('save_rule', { rule, overwrite });
+  return invoke<void>('save_rule', { rule, overwrite }).catch((e) => {
+    throw toRecordError(e);
+  });
 }